@@ -1,6 +1,8 @@
 /// <reference lib="dom"/>
 /// <reference types="node" />
 
+import { Readable } from "stream"
+
 export class DOMPoint extends globalThis.DOMPoint {}
 export class DOMRect extends globalThis.DOMRect {}
 export class CanvasGradient extends globalThis.CanvasGradient {}
@@ -62,6 +64,14 @@ export interface ImageInfo {
 export interface ImageOptions {
   /** Describes how to process raw image buffer with decoded pixels */
   raw?: ImageInfo | undefined
+  /** Whether to rotate & flip the image according to its EXIF orientation tag. Default is 'from-image'. */
+  imageOrientation?: "from-image" | "none"
+  /** Whether to decode on a background thread ('async') or block until the image is ready. Default is 'auto' (currently the same as 'sync'). */
+  decoding?: "sync" | "async" | "auto"
+  /** Decode the pixels immediately rather than the first time the image is drawn. Default is false. */
+  raster?: boolean
+  /** Reduced resolution to decode still bitmap images at (0–1). Default is 1. */
+  scale?: number
 }
 
 export function loadImage(src: string | Buffer, options?: ImageOptions): Promise<Image>
@@ -70,6 +80,77 @@ export class Image extends globalThis.Image {
   constructor(options?: ImageOptions)
   get src(): string
   set src(src: string | Buffer)
+  /** Whether setting `src` decodes the data on a background thread */
+  decoding: "sync" | "async" | "auto"
+  /** Encoding of the source data (`undefined` for raw pixels & unrecognized formats) */
+  readonly format: "png" | "jpeg" | "webp" | "gif" | "bmp" | "ico" | "avif" | "heic" | "svg" | undefined
+  /** EXIF orientation tag value (1–8) */
+  readonly orientation: number
+  /** Resolution relative to 72 dpi (as in the export `density` option) */
+  readonly density: number
+  /** Export color space name for familiar profiles, or the embedded ICC profile's description */
+  readonly colorSpace: ExportColorSpace | string | undefined
+  /** Raw EXIF block (starting with its TIFF header) */
+  readonly exif: Buffer | undefined
+  /** Embedded XMP packet */
+  readonly xmp: string | undefined
+  /** Number of frames in an animated GIF, WebP, or APNG (or 1 for still images) */
+  readonly frameCount: number
+  /** Display time of each frame in milliseconds */
+  readonly durations: number[]
+  /** Number of times the animation plays through (0 = forever) */
+  readonly loop: number
+  /** Index of the frame to use when the image is drawn (or used in a pattern). Default is 0. */
+  frame: number
+  /** Pixel format of the decoded image */
+  readonly colorType: ColorType | undefined
+  /** Whether the decoded pixels' color values are pre-multiplied by their alpha */
+  readonly premultiplied: boolean | undefined
+
+  /** Returns a new Image containing the given region (areas outside the image's bounds are transparent) */
+  crop(x: number, y: number, width: number, height: number): Image
+  /** Returns a new Image scaled to the given dimensions */
+  resize(width: number, height: number, options?: ImageResizeOptions): Image
+  /** Returns a new Image rotated clockwise by a multiple of 90° */
+  rotate(degrees: number): Image
+  /** Returns a new Image mirrored along the given axis */
+  flip(direction?: "horizontal" | "vertical"): Image
+  /** Returns a new Image with its pixels stored in a different format */
+  convert(options?: ImageConvertOptions): Image
+  /** Encodes the image in PNG, JPEG, or WebP format */
+  toBuffer(format?: ImageEncodingFormat, options?: ImageEncodingOptions): Promise<Buffer>
+  toBufferSync(format?: ImageEncodingFormat, options?: ImageEncodingOptions): Buffer
+}
+
+export interface ImageResizeOptions {
+  /** Sampling quality ('none' for nearest-neighbor, or an imageSmoothingQuality level). Default is 'high'. */
+  quality?: "none" | ImageSmoothingQuality
+}
+
+export interface ImageConvertOptions {
+  /** Default is 'rgba'. */
+  colorType?: ColorType
+  /** Default is false. */
+  premultiplied?: boolean
+}
+
+export type ImageEncodingFormat = "png" | "jpg" | "jpeg" | "webp" | "image/png" | "image/jpeg" | "image/webp"
+
+export interface ImageEncodingOptions {
+  /** Compression level for JPEG & lossy WebP output (0–1). Default is 0.92. */
+  quality?: number
+  /** Pixels per inch (in multiples of 72) written to the file’s resolution metadata. Default is 1. */
+  density?: number
+  /** Use lossless compression for WebP output (`quality` is ignored). Default is `false`. */
+  lossless?: boolean
+  /** EXIF, XMP, and text metadata to embed in the file */
+  metadata?: DocumentMetadata
+  /** Color space to convert the image to (with a matching ICC profile). Defaults to the image’s own. */
+  colorSpace?: ExportColorSpace
+  /** Bits per channel for PNG output. Default is 8. */
+  bitDepth?: 8 | 16
+  /** Encoder settings for PNG and JPEG output */
+  encoding?: EncodingOptions
 }
 
 /** Extended ImageDataSettings for the extended ImageData type. */
@@ -96,6 +177,31 @@ export class ImageData extends globalThis.ImageData {
 /** Utility function to look up depth of a given color type. */
 export function colorTypeBytesPerPixel(colorType: ColorType): number
 
+export interface CompareOptions {
+  /** Per-channel difference (0–255) a pixel may have before it counts as mismatched. Default: `0` */
+  threshold?: number
+  /** Whether to generate an image highlighting the mismatched pixels. Default: `false` */
+  diff?: boolean
+}
+
+export interface Comparison {
+  /** The largest difference found in each of the red, green, blue, and alpha channels */
+  maxError: [number, number, number, number]
+  /** The number of pixels with at least one channel differing by more than `threshold` */
+  mismatched: number
+  /** The total number of pixels compared */
+  total: number
+  /** Mean structural similarity of the two images (1.0 when identical) */
+  ssim: number
+  /** A visualization of the mismatched pixels (only present if the `diff` option was set) */
+  diff?: Image
+}
+
+/** Compare two equally-sized images pixel-by-pixel and perceptually (on a background thread). */
+export function compareImages(a: Canvas | Image | Buffer, b: Canvas | Image | Buffer, options?: CompareOptions): Promise<Comparison>
+/** Compare two equally-sized images pixel-by-pixel and perceptually, blocking until the comparison is complete. */
+export function compareImagesSync(a: Canvas | Image | Buffer, b: Canvas | Image | Buffer, options?: CompareOptions): Comparison
+
 
 //
 // DOMMatrix
@@ -113,7 +219,132 @@ export class DOMMatrix extends globalThis.DOMMatrix {
 // Canvas
 //
 
-export type ExportFormat = "png" | "jpg" | "jpeg" | "pdf" | "svg" | "raw";
+export type ExportFormat = "png" | "jpg" | "jpeg" | "webp" | "gif" | "apng" | "tiff" | "tif" | "bmp" | "ico" | "pdf" | "svg" | "skp" | "raw";
+export type FrameDisposal = "none" | "background" | "previous";
+export type ExportColorSpace = "srgb" | "display-p3" | "rec2020" | "srgb-linear";
+
+export interface DocumentMetadata {
+  title?: string
+  author?: string
+  subject?: string
+  keywords?: string | string[]
+  creator?: string
+  creationDate?: Date
+  modificationDate?: Date
+  /** Copyright notice for the EXIF data of PNG, JPEG, and WebP output */
+  copyright?: string
+  /** XMP packet to embed in PNG, JPEG, and WebP output */
+  xmp?: string
+  /** Key-value pairs to add to PNG output as text chunks */
+  text?: {[key: string]: string}
+}
+
+export interface Bookmark {
+  title: string
+  /** Page the bookmark opens to (1-based). Default is 1. */
+  page?: number
+  /** Name of a destination created with `ctx.addDestination()` (overrides `page`) */
+  destination?: string
+  children?: Bookmark[]
+}
+
+export type PaperSize = "letter" | "legal" | "tabloid" | "a3" | "a4" | "a5"
+
+export interface PageSetup {
+  /** Finished (‘trim’) size of the page in points. Defaults to the page’s own dimensions. */
+  size?: PaperSize | [width: number, height: number]
+  /** Swap the `size` dimensions as needed to make the page taller or wider */
+  orientation?: "portrait" | "landscape"
+  /** Points of extra printable area around the trim size on every side. Default is 0. */
+  bleed?: number
+  /** Display the whole sheet ("bleed") or just the trim area ("trim") in PDF viewers. Default is 'bleed'. */
+  crop?: "bleed" | "trim"
+}
+
+export type PageLabelStyle = "decimal" | "lower-roman" | "upper-roman" | "lower-alpha" | "upper-alpha"
+
+export interface PageLabel {
+  /** First page of the range (1-based) */
+  page: number
+  /** Numbering style (or `null` to use only the prefix). Default is 'decimal'. */
+  style?: PageLabelStyle | null
+  /** Text displayed before each page number */
+  prefix?: string
+  /** Number of the range’s first page. Default is 1. */
+  start?: number
+}
+
+export type AttachmentRelationship = "Source" | "Data" | "Alternative" | "Supplement" | "Unspecified"
+
+export interface Attachment {
+  /** File name shown in the viewer’s attachments panel (must be unique) */
+  name: string
+  data: string | Buffer | ArrayBufferView | ArrayBuffer
+  mimeType?: string
+  description?: string
+  /** How the file relates to the document (as used by PDF/A-3 and ZUGFeRD). Default is 'Unspecified'. */
+  relationship?: AttachmentRelationship
+}
+
+export interface TextFieldOptions {
+  /** Initial contents of the field */
+  value?: string
+  /** Allow line breaks in the entered text. Default is `false`. */
+  multiline?: boolean
+  /** Maximum number of characters the field accepts */
+  maxLength?: number
+  /** Font size in points (or 0 to fit the text to the field’s height). Default is 0. */
+  fontSize?: number
+}
+
+export interface TagOptions {
+  /** Alternate description of the tagged content (e.g., for a `Figure`) */
+  alt?: string
+  /** Language of the tagged content (e.g., `"en-US"`) */
+  lang?: string
+}
+
+export type PngFilter = "none" | "sub" | "up" | "average" | "paeth";
+export type ChromaSubsampling = "4:4:4" | "4:2:2" | "4:2:0" | "4:4:0" | "4:1:1";
+
+export interface EncodingOptions {
+  /** zlib compression level for PNG output (0–9). Default is 6. */
+  compression?: number
+  /** Row filter(s) the PNG encoder may choose between. Default is all of them (or 'none' for palette images). */
+  filters?: PngFilter | PngFilter[]
+  /** Write an indexed-color PNG with at most 256 colors. Default is `false`. */
+  palette?: boolean
+  /** Chroma subsampling for JPEG output. Default is '4:2:0'. */
+  chromaSubsampling?: ChromaSubsampling
+  /** Write a progressive JPEG. Default is `false`. */
+  progressive?: boolean
+}
+
+export interface SvgOptions {
+  /** Refer to fonts by name ("system") or include the glyphs used as WOFF data ("embed") */
+  fonts?: "system" | "embed"
+  /** Encode bitmaps within the file ("inline") or write them alongside it ("files", saveAs only) */
+  images?: "inline" | "files"
+  /** Use the canvas size as the SVG's width & height ("absolute") or only as its viewBox ("viewbox") */
+  sizing?: "absolute" | "viewbox"
+}
+
+export interface TransformOptions {
+  /** Matrix to apply to the page (after it has been flipped and rotated) */
+  matrix?: DOMMatrix | number[]
+  /** Degrees to rotate the page clockwise. Default is 0. */
+  rotate?: number
+  /** Mirror the page horizontally. Default is `false`. */
+  flipX?: boolean
+  /** Mirror the page vertically. Default is `false`. */
+  flipY?: boolean
+  /** Output width (requires `height` as well). Defaults to the size of the transformed page. */
+  width?: number
+  /** Output height (requires `width` as well). Defaults to the size of the transformed page. */
+  height?: number
+  /** How to scale the transformed page to fit the output size. Default is 'contain'. */
+  fit?: Exclude<FitStyle, "resize">
+}
 
 export interface RenderOptions {
   /** Page to export: Defaults to 1 (i.e., first page) */
@@ -148,6 +379,128 @@ export interface RenderOptions {
 
   /** Whether raw output color data is premultiplied with alpha value. Default is `false`. */
   premultiplied?: boolean,
+
+  /** Use lossless compression for WebP output (`quality` is ignored). Default is `false`. */
+  lossless?: boolean,
+
+  /** Combine all pages into an animated WebP (GIF and APNG output is always animated). Default is `false`. */
+  animated?: boolean,
+
+  /** Milliseconds to display each frame of an animation (or an array of per-frame values). Default is 100. */
+  delay?: number | number[],
+
+  /** Number of times to play an animation (0 for infinite looping). Default is 0. */
+  loop?: number | boolean,
+
+  /** How each animation frame is cleared before drawing the next (or an array of per-frame values). Default is 'background'. */
+  disposal?: FrameDisposal | FrameDisposal[],
+
+  /** Document properties to embed in PDF output (or as EXIF, XMP, and text metadata in PNG, JPEG, and WebP images) */
+  metadata?: DocumentMetadata,
+
+  /** Entries for the PDF’s outline (a.k.a. table of contents) sidebar */
+  bookmarks?: Bookmark[],
+
+  /** Generate an archival PDF/A-2b document (or PDF/A-3b if it has attachments). Default is `false`. */
+  pdfa?: boolean,
+
+  /** Color space to convert PNG, JPEG, and WebP output to (with a matching ICC profile). Default is 'srgb'. */
+  colorSpace?: ExportColorSpace,
+
+  /** Bits per channel for PNG output. Default is 8. */
+  bitDepth?: 8 | 16,
+
+  /** Encoder settings for PNG and JPEG output */
+  encoding?: EncodingOptions,
+
+  /** Font, bitmap, and sizing settings for SVG output */
+  svg?: SvgOptions,
+
+  /** Layer number (or [first, last] range) to export. Defaults to all layers */
+  layers?: number | [first: number, last: number],
+
+  /** Composite the selected layers into a single image (true) or treat each as a separate page (false) */
+  flatten?: boolean,
+
+  /** Rotate, flip, or fit the page into a fixed output size (letterboxed with the `matte` color) */
+  transform?: TransformOptions,
+
+  /** Sheet size and print boxes for PDF output (either for every page or an array with one entry per page) */
+  pageSetup?: PageSetup | PageSetup[],
+
+  /** Page numbering schemes for the PDF viewer (each applying from its `page` until the next label’s) */
+  pageLabels?: PageLabel[],
+
+  /** Files to embed in PDF output */
+  attachments?: Attachment[],
+
+  /** Cancels a background export (before its next page is started) when aborted. Not accepted by the synchronous exports */
+  signal?: AbortSignal,
+
+  /** Called with the number of completed pages (and the total) as a background export proceeds. Not accepted by the synchronous exports */
+  onProgress?: (done: number, total: number) => void,
+}
+
+export interface TileOptions {
+  /** Image format for the individual tiles (default: "png") */
+  format?: "png" | "jpg" | "jpeg" | "webp"
+  /** Page to render (1-based, negative values count back from the last page) */
+  page?: number
+  /** Background color to draw beneath the canvas contents */
+  matte?: string
+  /** Pixels per canvas unit at the pyramid's most detailed level */
+  density?: number
+  /** Compression quality for jpg and webp tiles */
+  quality?: number
+  /** Width & height of each tile in pixels (default: 256) */
+  tileSize?: number
+  /** Pixels of overlap between neighboring deepzoom tiles (default: 0) */
+  overlap?: number
+  /** Arrangement of tiles on disk (default: "xyz") */
+  layout?: "xyz" | "deepzoom"
+}
+
+export interface ThumbnailSize {
+  /** Maximum width of the thumbnail in pixels */
+  width?: number
+  /** Maximum height of the thumbnail in pixels */
+  height?: number
+  /** Image format for this thumbnail (default: "png") */
+  format?: "png" | "jpg" | "jpeg" | "webp"
+  /** Compression quality for jpg and webp thumbnails (defaults to the `quality` option) */
+  quality?: number
+}
+
+export interface ThumbnailOptions {
+  /** Page to render (1-based, negative values count back from the last page) */
+  page?: number
+  /** Background color to draw beneath the canvas contents */
+  matte?: string
+  /** Pixels per canvas unit of the full-sized rendering the thumbnails are scaled down from */
+  density?: number
+  /** Default compression quality for jpg and webp thumbnails */
+  quality?: number
+  /** Use lossless compression for webp thumbnails. Default is `false`. */
+  lossless?: boolean
+  /** EXIF, XMP, and text metadata to embed in each thumbnail */
+  metadata?: DocumentMetadata
+  /** Color space to render the page in (with a matching ICC profile). Default is 'srgb'. */
+  colorSpace?: ExportColorSpace
+  /** Bits per channel for png thumbnails. Default is 8. */
+  bitDepth?: 8 | 16
+  /** Encoder settings for png and jpg thumbnails */
+  encoding?: EncodingOptions
+  /** Cubic resampling filter (default: "mitchell") */
+  filter?: "mitchell" | "catmull-rom"
+}
+
+export interface RawOptions extends RenderOptions {
+  /** Arrangement of the pixel data: interleaved color channels (the default) or planar YUV 4:2:0 */
+  layout?: "interleaved" | "yuv420" | "nv12"
+  /** Bytes per row of pixels (or of the luma plane for YUV layouts). Defaults to tightly packed rows */
+  rowBytes?: number
+  /** An existing buffer to render into (it will be detached and its memory returned in the Buffer the Promise resolves to) */
+  buffer?: Buffer | ArrayBufferView
 }
 
 export interface SaveOptions extends RenderOptions {
@@ -159,6 +512,8 @@ export class Canvas {
   /** @internal */
   constructor(width?: number, height?: number)
   static contexts: WeakMap<Canvas, readonly CanvasRenderingContext2D[]>
+  /** Create a canvas from a serialized Skia picture (.skp file) */
+  static fromPicture(src: Buffer | string): Canvas
 
   /**
    * @deprecated Use the saveAsSync, toBufferSync, and toDataURLSync methods
@@ -178,18 +533,24 @@ export class Canvas {
 
   saveAs(filename: string, options?: SaveOptions): Promise<void>
   toBuffer(format: ExportFormat, options?: RenderOptions): Promise<Buffer>
+  toStream(format: ExportFormat, options?: RenderOptions): Readable
+  saveTiles(path: string, options?: TileOptions): Promise<void>
+  toThumbnails(sizes: (number | ThumbnailSize)[], options?: ThumbnailOptions): Promise<Buffer[]>
   toDataURL(format: ExportFormat, options?: RenderOptions): Promise<string>
-  toRaw(options?: RenderOptions): Promise<Buffer>
+  toRaw(options?: RawOptions): Promise<Buffer>
   toImageData(options?: RenderOptions): Promise<ImageData>
 
   saveAsSync(filename: string, options?: SaveOptions): void
   toBufferSync(format: ExportFormat, options?: RenderOptions): Buffer
+  toThumbnailsSync(sizes: (number | ThumbnailSize)[], options?: ThumbnailOptions): Buffer[]
   toDataURLSync(format: ExportFormat, options?: RenderOptions): string
 
   get pdf(): Promise<Buffer>
   get svg(): Promise<Buffer>
   get jpg(): Promise<Buffer>
   get png(): Promise<Buffer>
+  get webp(): Promise<Buffer>
+  get gif(): Promise<Buffer>
   get raw(): Promise<Buffer>
 }
 
@@ -252,6 +613,7 @@ type CornerRadius = number | DOMPoint
 
 export interface CanvasRenderingContext2D extends CanvasCompositing, CanvasDrawImage, CanvasDrawPath, CanvasFillStrokeStyles, CanvasFilters, CanvasImageData, CanvasImageSmoothing, CanvasPath, CanvasPathDrawingStyles, CanvasRect, CanvasShadowStyles, CanvasState, CanvasText, CanvasTextDrawingStyles, CanvasTransform, CanvasUserInterface {
   readonly canvas: Canvas;
+  readonly layerCount: number;
   fontVariant: string;
   textTracking: number;
   textWrap: boolean;
@@ -273,6 +635,12 @@ export interface CanvasRenderingContext2D extends CanvasCompositing, CanvasDrawI
   measureText(text: string, maxWidth?: number): TextMetrics
   outlineText(text: string): Path2D
 
+  addLink(url: string, x: number, y: number, width: number, height: number): void
+  addDestination(name: string, x: number, y: number): void
+  addTextField(name: string, x: number, y: number, width: number, height: number, options?: TextFieldOptions): void
+  beginTag(type: string, options?: TagOptions): void
+  endTag(): void
+
   reset(): void
 }
 
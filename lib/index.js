@@ -2,6 +2,7 @@
 
 const fs = require('fs'),
       {EventEmitter} = require('events'),
+      {Readable} = require('stream'),
       {inspect} = require('util'),
       {sync:glob, hasMagic} = require('glob'),
       get = require('simple-get'),
@@ -76,6 +77,28 @@ function signature(args){
 
 const toString = val => typeof val=='string' ? val : new String(val).toString()
 
+// link an export's AbortSignal to a native token that the background task checks between pages
+const cancellable = (signal, task) => {
+  if (!signal) return task()
+
+  let token = neon.CancelToken.new(),
+      abort = () => neon.CancelToken.cancel(token)
+  if (signal.aborted) abort()
+  else signal.addEventListener('abort', abort, {once:true})
+
+  return task(token)
+    .catch(err => { throw signal.aborted && signal.reason || err })
+    .finally(() => signal.removeEventListener('abort', abort))
+}
+
+// synchronous exports are finished by the time they return, so there's nothing to cancel or report on
+const synchronous = ({signal, onProgress, ...options}) => {
+  if (signal!==undefined || onProgress!==undefined){
+    throw new TypeError("The signal and onProgress options can only be used with asynchronous exports")
+  }
+  return options
+}
+
 //
 // Helpers to reconcile Skia and DOMMatrix’s disagreement about row/col orientation
 //
@@ -402,6 +425,15 @@ class Canvas extends RustClass{
     Object.assign(this, {width, height})
   }
 
+  static fromPicture(src){
+    let data = Buffer.isBuffer(src) ? src : fs.readFileSync(src),
+        canvas = new Canvas(),
+        [width, height] = canvas.getContext("2d").ƒ('loadPicture', data)
+    canvas.prop('width', width)
+    canvas.prop('height', height)
+    return canvas
+  }
+
   getContext(kind){
     return (kind=="2d") ? this.#contexts[0] || this.newPage() : null
   }
@@ -443,6 +475,8 @@ class Canvas extends RustClass{
 
   get png(){ return this.toBuffer("png") }
   get jpg(){ return this.toBuffer("jpg") }
+  get webp(){ return this.toBuffer("webp") }
+  get gif(){ return this.toBuffer("gif") }
   get pdf(){ return this.toBuffer("pdf") }
   get svg(){ return this.toBuffer("svg") }
   get raw(){ return this.toBuffer("raw") }
@@ -459,45 +493,105 @@ class Canvas extends RustClass{
     if (!this.async) return this.saveAsSync(...arguments) // support while deprecated
 
     opts = typeof opts=='number' ? {quality:opts} : opts
-    let {format, quality, pages, padding, pattern, density, outline, matte, bounds, premultiplied, colorType} =
-      io.options(this.pages, this.size, {filename, ...opts})
-    return this.ƒ("save", pages.map(core), pattern, padding, format, quality, density, outline, matte, bounds, premultiplied, colorType)
+    let {pages, pattern, padding, signal, ...options} = io.options(this.pages, this.size, {filename, ...opts})
+    return cancellable(signal, token =>
+      this.ƒ("save", pages.map(core), pattern, padding, {...options, token})
+    )
   }
 
   saveAsSync(filename, opts={}){
     opts = typeof opts=='number' ? {quality:opts} : opts
-    let {format, quality, pages, padding, pattern, density, outline, matte, bounds, premultiplied, colorType} =
-      io.options(this.pages, this.size, {filename, ...opts})
-    this.ƒ("saveSync", pages.map(core), pattern, padding, format, quality, density, outline, matte, bounds, premultiplied, colorType)
+    let {pages, pattern, padding, ...options} = synchronous(io.options(this.pages, this.size, {filename, ...opts}))
+    this.ƒ("saveSync", pages.map(core), pattern, padding, options)
+  }
+
+  saveTiles(dirname, opts={}){
+    let {tileSize, overlap, layout, ...rest} = opts,
+        {format, quality, pages, density, matte} = io.options(this.pages, this.size, {format:'png', ...rest}),
+        tiling = io.tiles_opt({tileSize, overlap, layout})
+
+    if (!['png', 'jpg', 'webp'].includes(format)) throw new TypeError(`Tiles must be in "png", "jpg", or "webp" format`)
+    return this.ƒ("saveTiles", pages.map(core), dirname, format, quality, density, matte, tiling)
+  }
+
+  toThumbnails(sizes, opts={}){
+    let {filter, ...rest} = opts,
+        {quality, pages, density, matte, lossless, metadata, colorSpace, bitDepth, encoding} = io.options(this.pages, this.size, {format:'png', ...rest}),
+        thumbnails = io.thumbnails_opt(sizes, {quality, filter})
+    return this.ƒ("toThumbnails", pages.map(core), thumbnails.sizes, thumbnails.filter, {density, matte, lossless, metadata, colorSpace, bitDepth, encoding})
+  }
+
+  toThumbnailsSync(sizes, opts={}){
+    let {filter, ...rest} = opts,
+        {quality, pages, density, matte, lossless, metadata, colorSpace, bitDepth, encoding} = io.options(this.pages, this.size, {format:'png', ...rest}),
+        thumbnails = io.thumbnails_opt(sizes, {quality, filter})
+    return this.ƒ("toThumbnailsSync", pages.map(core), thumbnails.sizes, thumbnails.filter, {density, matte, lossless, metadata, colorSpace, bitDepth, encoding})
   }
 
   toBuffer(extension="png", opts={}){
     if (!this.async) return this.toBufferSync(...arguments) // support while deprecated
 
     opts = typeof opts=='number' ? {quality:opts} : opts
-    let {format, quality, pages, density, outline, matte, bounds, premultiplied, colorType} =
-      io.options(this.pages, this.size, {extension, ...opts})
-    return this.ƒ("toBuffer", pages.map(core), format, quality, density, outline, matte, bounds, premultiplied, colorType)
+    let {pages, signal, ...options} = io.options(this.pages, this.size, {extension, ...opts})
+    return cancellable(signal, token =>
+      this.ƒ("toBuffer", pages.map(core), {...options, token})
+    )
   }
 
   toBufferSync(extension="png", opts={}){
     opts = typeof opts=='number' ? {quality:opts} : opts
-    let {format, quality, pages, density, outline, matte, bounds, premultiplied, colorType} =
-      io.options(this.pages, this.size, {extension, ...opts})
-    return this.ƒ("toBufferSync", pages.map(core), format, quality, density, outline, matte, bounds, premultiplied, colorType)
+    let {pages, ...options} = synchronous(io.options(this.pages, this.size, {extension, ...opts}))
+    return this.ƒ("toBufferSync", pages.map(core), options)
   }
 
-  /** A slightly more efficient version of toBuffer('raw'); Uses only `left`, `top`, `width`, `height`, `pages`, `matte`, `colorType`, and `premultiplied` options. */
+  toStream(extension="png", opts={}){
+    opts = typeof opts=='number' ? {quality:opts} : opts
+    let {pages, signal, ...options} = io.options(this.pages, this.size, {extension, ...opts}),
+        token = signal ? neon.CancelToken.new() : undefined,
+        canvas = this,
+        abort = () => {
+          neon.CancelToken.cancel(token)
+          stream.destroy(signal.reason)
+        }
+
+    // each read asks the background thread for a chunk, which it delivers once encoded (destroying the stream stops it)
+    let stream = new Readable({
+      highWaterMark: 65536,
+      read(){
+        canvas.ƒ("readStream", src)
+      },
+      destroy(err, callback){
+        canvas.ƒ("closeStream", src)
+        if (signal) signal.removeEventListener('abort', abort)
+        callback(err)
+      }
+    })
+
+    let src = this.ƒ("toStream", pages.map(core), {...options, token}, (err, chunk) => {
+      if (err) stream.destroy(err)
+      else stream.push(chunk)
+    })
+
+    if (signal && signal.aborted) process.nextTick(abort)
+    else if (signal) signal.addEventListener('abort', abort, {once:true})
+
+    return stream
+  }
+
+  /** A slightly more efficient version of toBuffer('raw'); Uses only `left`, `top`, `width`, `height`, `pages`, `matte`, `colorType`, `premultiplied`, `layout`, `rowBytes`, `buffer`, `transform`, `signal`, and `onProgress` options. */
   toRaw(opts = {}) {
-    const {pages, bounds, matte, colorType, premultiplied} = io.options(this.pages, this.size, {format: "raw", ...opts})
-    return this.ƒ("toRaw", pages.map(core), bounds, matte, premultiplied, colorType)
+    const {pages, bounds, matte, colorType, premultiplied, transform, signal, onProgress} = io.options(this.pages, this.size, {format: "raw", ...opts}),
+          {layout, rowBytes, buffer} = io.raw_opt(opts)
+    return cancellable(signal, token =>
+      this.ƒ("toRaw", pages.map(core), {bounds, matte, premultiplied, colorType, layout, rowBytes, buffer, transfer:io.transfer_buffer, transform, token, onProgress})
+    )
   }
 
-  /** `toRaw()` wrapped in an ImageData type result; takes all the same options. */
+  /** `toRaw()` wrapped in an ImageData type result; takes the same options (except for `layout`, `rowBytes`, and `transform`). */
   toImageData(opts = {}) {
-    const {colorType, premultiplied} = opts,
+    const {colorType, premultiplied, layout, rowBytes, transform, ...rest} = opts,
       bounds = io.bounds_opt(this.size, opts)
-    return this.toRaw(opts).then(b => new ImageData(b, bounds.width, bounds.height, { colorType, premultiplied }) )
+    return this.toRaw({colorType, premultiplied, ...rest}).then(b => new ImageData(b, bounds.width, bounds.height, { colorType, premultiplied }) )
   }
 
   toDataURL(extension="png", opts={}){
@@ -591,6 +685,7 @@ class CanvasRenderingContext2D extends RustClass{
   }
 
   get canvas(){ return this.#canvas.deref() }
+  get layerCount(){ return this.prop('layerCount') }
 
   // -- global state & content reset ------------------------------------------
   reset(){ this.ƒ('reset') }
@@ -805,6 +900,20 @@ class CanvasRenderingContext2D extends RustClass{
   get filter(){        return this.prop('filter') }
   set filter(str){            this.prop('filter', css.filter(str)) }
 
+  // -- pdf annotations -------------------------------------------------------
+  addLink(url, x, y, width, height){ this.ƒ('addLink', toString(url), x, y, width, height) }
+  addDestination(name, x, y){ this.ƒ('addDestination', toString(name), x, y) }
+  addTextField(name, x, y, width, height, {value='', multiline=false, maxLength, fontSize=0}={}){
+    if (maxLength!==undefined && (!Number.isInteger(maxLength) || maxLength<1)){
+      throw new TypeError("The maxLength option must be a positive integer")
+    }
+    this.ƒ('addTextField', toString(name), x, y, width, height, toString(value), !!multiline, maxLength, fontSize)
+  }
+  beginTag(type, {alt, lang}={}){
+    this.ƒ('beginTag', toString(type), alt==null ? undefined : toString(alt), lang==null ? undefined : toString(lang))
+  }
+  endTag(){ this.ƒ('endTag') }
+
   [REPR](depth, options) {
     let props = [ "canvas", "currentTransform", "fillStyle", "strokeStyle", "font", "fontVariant",
                   "direction", "textAlign", "textBaseline", "textTracking", "textWrap", "globalAlpha",
@@ -860,13 +969,49 @@ class Image extends RustClass {
 
   constructor(options = null){
     super(Image).alloc()
+    let {imageOrientation, decoding, raster, scale} = options || {}
+    if (imageOrientation!==undefined && !['from-image', 'none'].includes(imageOrientation)){
+      throw new TypeError(`The imageOrientation option must be "from-image" or "none"`)
+    }
+    if (scale!==undefined && !(typeof scale=='number' && scale>0 && scale<=1)){
+      throw new RangeError(`The scale option must be a number greater than 0 and no larger than 1 (got ${scale})`)
+    }
     this.#options = options;
+    this.decoding = decoding
+  }
+
+  get decoding(){ return this.#decoding }
+  set decoding(mode){
+    if (mode!==undefined && !['sync', 'async', 'auto'].includes(mode)){
+      throw new TypeError(`The decoding mode must be "sync", "async", or "auto"`)
+    }
+    this.#decoding = mode=='async' ? 'async' : mode=='sync' ? 'sync' : 'auto'
   }
+  #decoding = 'auto';
 
   get complete(){ return this.prop('complete') }
   get height(){ return this.prop('height') }
   get width(){ return this.prop('width') }
 
+  get format(){ return this.prop('format') }
+  get orientation(){ return this.prop('orientation') }
+  get density(){ return this.prop('density') }
+  get colorSpace(){ return this.prop('colorSpace') }
+  get exif(){ return this.prop('exif') }
+  get xmp(){ return this.prop('xmp') }
+
+  get frameCount(){ return this.prop('frameCount') }
+  get durations(){ return this.prop('durations') }
+  get loop(){ return this.prop('loop') }
+  get frame(){ return this.prop('frame') }
+  set frame(index){
+    let count = this.frameCount
+    if (!Number.isInteger(index) || index<0 || index>=count){
+      throw new RangeError(`Frame must be an integer in the range 0–${count - 1} (got ${index})`)
+    }
+    this.prop('frame', index)
+  }
+
   get src(){ return this.prop('src') }
   set src(src){
     var onload = img => fetch.emit('ok', img),
@@ -888,6 +1033,8 @@ class Image extends RustClass {
       let split = src.indexOf(','),
           enc = src.lastIndexOf('base64', split) !== -1 ? 'base64' : 'utf8',
           content = src.slice(split + 1);
+      // non-base64 payloads (e.g., inline SVG markup) are typically percent-encoded
+      if (enc=='utf8' && /%[0-9a-f]{2}/i.test(content)) content = decodeURIComponent(content)
       data = Buffer.from(content, enc);
     } else if (/^\s*https?:\/\//.test(src)) {
       // remote URL
@@ -897,8 +1044,7 @@ class Image extends RustClass {
         else if (code < 200 || code >= 300) {
           onerror(new Error(`Failed to load image from "${src}" (error ${code})`))
         }else{
-          if (this.prop("data", data)) onload(this)
-          else onerror(new Error("Could not decode image data"))
+          this.#load(data, onload, onerror)
         }
       })
     } else {
@@ -907,19 +1053,75 @@ class Image extends RustClass {
     }
 
     this.prop("src", src)
-    if (data) {
-      let success;
+    if (data) this.#load(data, onload, onerror)
+  }
+
+  #load(data, onload, onerror){
+    let failed = () => onerror(new Error("Could not decode image data")),
+        {imageOrientation, raster, scale, raw} = this.#options || {},
+        args = [imageOrientation!='none', !!raster, scale ?? 1]
+
+    if (raw){
       // if data contains raw pixel buffer and was decoded previously already
-      if (this.#options?.raw) {
-        success = this.ƒ('load_pixel_data', data, this.#options.raw)
-      } else {
-        success = this.prop("data", data)
-      }
+      this.ƒ('load_pixel_data', data, raw) ? onload(this) : failed()
+    }else if (this.#decoding=='async'){
+      // decode on a background thread, leaving the image incomplete until it's ready
+      this.ƒ('decode_data', data, ...args).then(ok => ok ? onload(this) : failed(), onerror)
+    }else{
+      this.prop("data", data, ...args) ? onload(this) : failed()
+    }
+  }
+
+  get colorType(){ return this.prop('colorType') }
+  get premultiplied(){ return this.prop('premultiplied') }
+
+  crop(x, y, width, height){
+    if (![x, y].every(Number.isFinite) || [width, height].some(dim => !Number.isInteger(dim) || dim<1)){
+      throw new TypeError("Expected x & y coordinates and a positive integer width & height")
+    }
+    return this.#derive('crop', x, y, width, height)
+  }
 
-      if (success) onload(this)
-      else onerror(new Error("Could not decode image data"))
+  resize(width, height, {quality='high'}={}){
+    if ([width, height].some(dim => !Number.isInteger(dim) || dim<1)){
+      throw new TypeError("The width and height must be positive integers")
     }
+    if (!['none', 'low', 'medium', 'high'].includes(quality)){
+      throw new TypeError(`The quality option must be "none", "low", "medium", or "high"`)
+    }
+    return this.#derive('resize', width, height, quality)
+  }
+
+  rotate(degrees){
+    // expressed as the equivalent EXIF orientation value
+    let orientation = {0:1, 90:6, 180:3, 270:8}[(degrees % 360 + 360) % 360]
+    if (!orientation) throw new RangeError(`Images can only be rotated by multiples of 90° (got ${degrees})`)
+    return this.#derive('orient', orientation)
+  }
 
+  flip(direction='horizontal'){
+    let orientation = {horizontal:2, vertical:4}[direction]
+    if (!orientation) throw new TypeError(`The flip direction must be "horizontal" or "vertical"`)
+    return this.#derive('orient', orientation)
+  }
+
+  convert({colorType='rgba', premultiplied=false}={}){
+    return this.#derive('convert', colorType, !!premultiplied)
+  }
+
+  toBuffer(format='png', options){
+    return this.ƒ('toBuffer', io.image_format_opt(format, options))
+  }
+
+  toBufferSync(format='png', options){
+    return this.ƒ('toBufferSync', io.image_format_opt(format, options))
+  }
+
+  #derive(op, ...args){
+    if (!this.complete) throw new Error("Image has not been loaded yet")
+    let img = new Image()
+    this.ƒ(op, core(img), ...args)
+    return img
   }
 
   decode(){
@@ -929,9 +1131,9 @@ class Image extends RustClass {
   }
 
   [REPR](depth, options) {
-    let {width, height, complete, src} = this
+    let {width, height, complete, format, src} = this
     options.maxStringLength = src.match(/^data:/) ? 128 : Infinity;
-    return `Image ${inspect({width, height, complete, src}, options)}`
+    return `Image ${inspect({width, height, complete, format, src}, options)}`
   }
 }
 
@@ -1106,8 +1308,37 @@ class TextMetrics{
 
 const loadImage = (src, options = null) => Object.assign(new Image(options), {src}).decode()
 
+const compare_args = (a, b, {threshold=0, diff=false}={}) => {
+  let source = img => {
+        if (img instanceof Canvas){
+          let {length} = img.pages
+          if (length > 1) throw TypeError(`Expected a single-page Canvas to compare (got one with ${length} pages)`)
+          return core(img.getContext('2d'))
+        }
+        return img instanceof Image ? core(img)
+             : Buffer.isBuffer(img) ? img
+             : undefined
+      },
+      [srcA, srcB] = [a, b].map(source)
+
+  if (!srcA || !srcB) throw TypeError("Expected a Canvas, Image, or Buffer to compare")
+  if (!Number.isFinite(threshold) || threshold < 0 || threshold > 255){
+    throw RangeError("The `threshold` option must be a number between 0 and 255")
+  }
+  return [srcA, srcB, threshold, !!diff]
+}
+
+const comparison = result => {
+  if (result.diff) result.diff = Object.assign(new Image(), {src:result.diff})
+  return result
+}
+
+const compareImages = (a, b, options) => neon.utils.compareImages(...compare_args(a, b, options)).then(comparison)
+
+const compareImagesSync = (a, b, options) => comparison(neon.utils.compareImagesSync(...compare_args(a, b, options)))
+
 module.exports = {
   Canvas, CanvasGradient, CanvasPattern, CanvasRenderingContext2D, CanvasTexture,
-  TextMetrics, Image, ImageData, Path2D, Window, loadImage, colorTypeBytesPerPixel, ...geometry,
+  TextMetrics, Image, ImageData, Path2D, Window, loadImage, compareImages, compareImagesSync, colorTypeBytesPerPixel, ...geometry,
   FontLibrary: new FontLibrary(), App: GUI.App
 }
@@ -1,6 +1,7 @@
 "use strict"
 
-const {basename, extname} = require('path')
+const {basename, extname} = require('path'),
+      {DOMMatrix} = require('./geometry')
 
 //
 // Mime type <-> File extension mappings
@@ -13,19 +14,27 @@ class Format{
         jpg = "image/jpeg",
         jpeg = "image/jpeg",
         webp = "image/webp",
+        gif = "image/gif",
+        apng = "image/apng",
+        tiff = "image/tiff",
+        tif = "image/tiff",
+        bmp = "image/bmp",
+        ico = "image/x-icon",
         pdf = "application/pdf",
         svg = "image/svg+xml",
+        skp = "application/x-skp",
         raw = "image/x-dcraw"  // ?
 
     Object.assign(this, {
       toMime: this.toMime.bind(this),
       fromMime: this.fromMime.bind(this),
       expected: isWeb ? `"png", "jpg", or "webp"`
-                      : `"png", "jpg", "pdf", "svg", or "raw"`,
+                      : `"png", "jpg", "webp", "gif", "apng", "tiff", "bmp", "ico", "pdf", "svg", "skp", or "raw"`,
       formats: isWeb ? {png, jpg, jpeg, webp}
-                     : {png, jpg, jpeg, pdf, svg, raw},
+                     : {png, jpg, jpeg, webp, gif, apng, tiff, tif, bmp, ico, pdf, svg, skp, raw},
       mimes: isWeb ? {[png]: "png", [jpg]: "jpg", [webp]: "webp"}
-                   : {[png]: "png", [jpg]: "jpg", [pdf]: "pdf", [svg]: "svg", [raw]: "raw"},
+                   : {[png]: "png", [jpg]: "jpg", [webp]: "webp", [gif]: "gif", [apng]: "apng",
+                      [tiff]: "tiff", [bmp]: "bmp", [ico]: "ico", [pdf]: "pdf", [svg]: "svg", [skp]: "skp", [raw]: "raw"},
     })
   }
 
@@ -51,9 +60,350 @@ function bounds_opt(size, {left, top, width, height}) {
   }
 }
 
+function date_opt(date, name){
+  if (date===undefined) return
+  let d = new Date(date)
+  if (isNaN(d)) throw new TypeError(`The ${name} metadata must be a Date (or a value that can be converted to one)`)
+  return [d.getUTCFullYear(), d.getUTCMonth() + 1, d.getUTCDate(), d.getUTCHours(), d.getUTCMinutes(), d.getUTCSeconds(), d.getUTCDay()]
+}
+
+function metadata_opt(metadata){
+  if (metadata===undefined) return
+  if (!metadata || typeof metadata!='object') throw new TypeError("The metadata option must be an object")
+
+  let {title, author, subject, keywords, creator, creationDate, modificationDate, copyright, xmp, text:entries} = metadata,
+      text = str => str===undefined ? undefined : String(str)
+
+  // PNG text chunks are passed along as a flat list of alternating keys & values
+  if (entries!==undefined && (!entries || typeof entries!='object')){
+    throw new TypeError("The text metadata must be an object with string values")
+  }
+  entries = Object.entries(entries || {}).flatMap(([key, val]) => {
+    if (!/^[\x21-\x7e\xa1-\xff](?:[\x21-\x7e\xa1-\xff]| (?! )){0,78}$/.test(key) || key.endsWith(' ')){
+      throw new TypeError(`Text metadata keys must be 1–79 Latin-1 characters without leading, trailing, or consecutive spaces (got "${key}")`)
+    }
+    return [key, String(val)]
+  })
+
+  return {
+    title: text(title),
+    author: text(author),
+    subject: text(subject),
+    keywords: keywords===undefined ? undefined : [keywords].flat().join(', '),
+    creator: text(creator),
+    creationDate: date_opt(creationDate, 'creationDate'),
+    modificationDate: date_opt(modificationDate, 'modificationDate'),
+    copyright: text(copyright),
+    xmp: text(xmp),
+    text: entries,
+  }
+}
+
+function encoding_opt(encoding, bitDepth){
+  if (encoding===undefined) return
+  if (!encoding || typeof encoding!='object') throw new TypeError("The encoding option must be an object")
+
+  let {compression=6, filters=[], palette=false, chromaSubsampling='4:2:0', progressive=false} = encoding
+  filters = [filters].flat()
+
+  if (!Number.isInteger(compression) || compression<0 || compression>9){
+    throw new TypeError("The encoding.compression option must be an integer in the range 0–9")
+  }
+  if (!filters.every(f => ['none', 'sub', 'up', 'average', 'paeth'].includes(f))){
+    throw new TypeError(`The encoding.filters option must be "none", "sub", "up", "average", or "paeth" (or an array of them)`)
+  }
+  if (!['4:4:4', '4:2:2', '4:2:0', '4:4:0', '4:1:1'].includes(chromaSubsampling)){
+    throw new TypeError(`The encoding.chromaSubsampling option must be "4:4:4", "4:2:2", "4:2:0", "4:4:0", or "4:1:1"`)
+  }
+  if (palette && bitDepth==16){
+    throw new TypeError("The encoding.palette option can't be combined with a bitDepth of 16")
+  }
+  return {compression, filters, palette:!!palette, chromaSubsampling, progressive:!!progressive}
+}
+
+function tiles_opt({tileSize=256, overlap=0, layout='xyz'}={}){
+  if (!Number.isInteger(tileSize) || tileSize<1){
+    throw new TypeError("The tileSize option must be a positive integer")
+  }
+  if (!Number.isInteger(overlap) || overlap<0 || overlap>=tileSize){
+    throw new TypeError("The overlap option must be a non-negative integer smaller than the tileSize")
+  }
+  if (!['xyz', 'deepzoom'].includes(layout)){
+    throw new TypeError(`The layout option must be "xyz" or "deepzoom"`)
+  }
+  if (overlap && layout=='xyz'){
+    throw new TypeError(`The overlap option can only be used with the "deepzoom" layout`)
+  }
+  return {size:tileSize, overlap, layout}
+}
+
+function thumbnails_opt(sizes, {quality, filter='mitchell'}={}){
+  let {fromMime, toMime} = new Format()
+  if (!Array.isArray(sizes) || !sizes.length){
+    throw new TypeError("Expected an array of thumbnail sizes")
+  }
+  if (!['mitchell', 'catmull-rom'].includes(filter)){
+    throw new TypeError(`The filter option must be "mitchell" or "catmull-rom"`)
+  }
+
+  // a bare number is shorthand for a square bounding box
+  sizes = sizes.map(size => {
+    let {width, height, format='png', quality:q=quality} = typeof size=='number' ? {width:size, height:size} : size || {}
+    format = fromMime(toMime(format) || format)
+
+    if (width===undefined && height===undefined){
+      throw new TypeError("Each thumbnail needs a width, a height, or both")
+    }
+    if ([width, height].some(dim => dim!==undefined && (!Number.isInteger(dim) || dim<1))){
+      throw new TypeError("Thumbnail widths and heights must be positive integers")
+    }
+    if (!['png', 'jpg', 'webp'].includes(format)){
+      throw new TypeError(`Thumbnails must be in "png", "jpg", or "webp" format`)
+    }
+    if (typeof q!='number' || !isFinite(q) || q<0 || q>1){
+      throw new TypeError("The quality option must be an number in the 0.0–1.0 range")
+    }
+    return {width, height, format, quality:q}
+  })
+
+  return {sizes, filter}
+}
+
+function image_format_opt(format, {quality=0.92, density=1, ...rest}={}){
+  let {fromMime, toMime} = new Format()
+  format = fromMime(toMime(format) || format)
+  if (!['png', 'jpg', 'webp'].includes(format)){
+    throw new TypeError(`Images can only be encoded in "png", "jpg", or "webp" format`)
+  }
+  if (typeof quality!='number' || !isFinite(quality) || quality<0 || quality>1){
+    throw new TypeError("The quality option must be an number in the 0.0–1.0 range")
+  }
+  if (typeof density!='number' || !Number.isInteger(density) || density<1){
+    throw new TypeError("The density option must be a non-negative integer")
+  }
+  return {format, quality, density, ...image_encoding_opt(rest)}
+}
+
+// settings for encoding a bitmap (shared by canvas exports, thumbnails, and Image.toBuffer)
+function image_encoding_opt({lossless, metadata, colorSpace, bitDepth=8, encoding}={}){
+  if (colorSpace!==undefined && !['srgb', 'display-p3', 'rec2020', 'srgb-linear'].includes(colorSpace)){
+    throw new TypeError(`The colorSpace option must be "srgb", "display-p3", "rec2020", or "srgb-linear"`)
+  }
+  if (bitDepth!==8 && bitDepth!==16){
+    throw new TypeError("The bitDepth option must be 8 or 16")
+  }
+  return {lossless:!!lossless, metadata:metadata_opt(metadata), colorSpace, bitDepth, encoding:encoding_opt(encoding, bitDepth)}
+}
+
+function raw_opt({layout='interleaved', rowBytes, buffer}={}){
+  if (!['interleaved', 'yuv420', 'nv12'].includes(layout)){
+    throw new TypeError(`The layout option must be "interleaved", "yuv420", or "nv12"`)
+  }
+  if (rowBytes!==undefined && (!Number.isInteger(rowBytes) || rowBytes<1)){
+    throw new TypeError("The rowBytes option must be a positive integer")
+  }
+  if (ArrayBuffer.isView(buffer) && !Buffer.isBuffer(buffer)){
+    // wrap other typed arrays (e.g., an ImageData's pixels) without copying them
+    buffer = Buffer.from(buffer.buffer, buffer.byteOffset, buffer.byteLength)
+  }else if (buffer!==undefined && !Buffer.isBuffer(buffer)){
+    throw new TypeError("The buffer option must be a Buffer or typed array")
+  }
+  return {layout, rowBytes, buffer}
+}
+
+// hands a buffer's memory over to toRaw() to be rendered into: as with a BYOB stream read, the caller's ArrayBuffer
+// is detached and its memory is returned wrapped in a new Buffer, so nothing else can touch it while the task runs
+function transfer_buffer(buffer){
+  let memory = buffer.buffer
+  if (buffer.byteOffset!=0 || buffer.byteLength!=memory.byteLength){
+    // detaching would also empty every other view of the same memory (including slices of Node’s shared pool)
+    throw new TypeError("The buffer option must span its entire ArrayBuffer (e.g., one from Buffer.alloc() rather than a slice)")
+  }
+  try{
+    return Buffer.from(structuredClone(memory, {transfer:[memory]}))
+  }catch(e){
+    throw new TypeError(`The buffer option could not be transferred (${e.message})`)
+  }
+}
+
+function svg_opt(svg, {filename, outline}){
+  if (svg===undefined) return
+  if (!svg || typeof svg!='object') throw new TypeError("The svg option must be an object")
+
+  let {fonts='system', images='inline', sizing='absolute'} = svg
+  if (!['system', 'embed'].includes(fonts)){
+    throw new TypeError(`The svg.fonts option must be "system" or "embed"`)
+  }
+  if (!['inline', 'files'].includes(images)){
+    throw new TypeError(`The svg.images option must be "inline" or "files"`)
+  }
+  if (!['absolute', 'viewbox'].includes(sizing)){
+    throw new TypeError(`The svg.sizing option must be "absolute" or "viewbox"`)
+  }
+  if (fonts=='embed' && outline===true){
+    throw new TypeError(`The svg.fonts option can't be set to "embed" when outline is true`)
+  }
+  if (images=='files' && !filename){
+    throw new TypeError(`The svg.images option can only be set to "files" when saving to disk`)
+  }
+  return {fonts, images, sizing}
+}
+
+function transform_opt(transform){
+  if (transform===undefined) return
+  if (!transform || typeof transform!='object') throw new TypeError("The transform option must be an object")
+
+  let {matrix, rotate=0, flipX=false, flipY=false, width, height, fit='contain'} = transform
+  try{
+    matrix = new DOMMatrix(matrix)
+  }catch{
+    throw new TypeError("The transform.matrix option must be a DOMMatrix or an array of 6 values")
+  }
+  if (!matrix.is2D){
+    throw new TypeError("The transform.matrix option must be a 2D matrix")
+  }
+  if (typeof rotate!='number' || !isFinite(rotate)){
+    throw new TypeError("The transform.rotate option must be a number of degrees")
+  }
+  if ((width===undefined) != (height===undefined) || [width, height].some(dim => dim!==undefined && (typeof dim!='number' || !isFinite(dim) || dim<=0))){
+    throw new TypeError("The transform.width and transform.height options must be provided together as positive numbers")
+  }
+  if (!["none", "contain-x", "contain-y", "contain", "cover", "fill", "scale-down"].includes(fit)){
+    throw new TypeError(`The transform.fit option must be "none", "contain", "contain-x", "contain-y", "cover", "fill", or "scale-down"`)
+  }
+
+  // quarter turns are kept exact so right-angle rotations don't pick up rounding errors in the output size
+  let rad = rotate * Math.PI / 180,
+      [cos, sin] = rotate % 90 ? [Math.cos(rad), Math.sin(rad)] : [Math.round(Math.cos(rad)), Math.round(Math.sin(rad))],
+      {a, b, c, d, e, f} = matrix.multiply(new DOMMatrix([cos, sin, -sin, cos, 0, 0])).scale(flipX ? -1 : 1, flipY ? -1 : 1)
+
+  return {matrix:[a, c, e, b, d, f, 0, 0, 1], width, height, fit}
+}
+
+// named paper sizes (in portrait orientation) measured in points
+const PAPER_SIZES = {
+  letter: [612, 792],
+  legal: [612, 1008],
+  tabloid: [792, 1224],
+  a3: [841.89, 1190.55],
+  a4: [595.28, 841.89],
+  a5: [419.53, 595.28],
+}
+
+function page_setup_opt(pageSetup){
+  if (pageSetup===undefined) return
+
+  // a single object applies to every page (as does the last entry of an array that's shorter than the document)
+  return [pageSetup].flat().map(({size, orientation, bleed=0, crop='bleed'}={}) => {
+    if (typeof size=='string'){
+      let dims = PAPER_SIZES[size.toLowerCase()]
+      if (!dims) throw new TypeError(`Unknown paper size "${size}" (expected ${Object.keys(PAPER_SIZES).join(', ')}, or a [width, height] array)`)
+      size = dims
+    }
+    if (size!==undefined && (!Array.isArray(size) || size.length!=2 || !size.every(n => typeof n=='number' && isFinite(n) && n>0))){
+      throw new TypeError("The pageSetup.size option must be a paper name or a [width, height] array of positive numbers")
+    }
+    if (orientation!==undefined){
+      if (!['portrait', 'landscape'].includes(orientation)){
+        throw new TypeError(`The pageSetup.orientation option must be "portrait" or "landscape"`)
+      }
+      if (!size) throw new TypeError("The pageSetup.orientation option can only be used along with a size")
+      let [short, long] = [...size].sort((a, b) => a - b)
+      size = orientation=='portrait' ? [short, long] : [long, short]
+    }
+    if (typeof bleed!='number' || !isFinite(bleed) || bleed<0){
+      throw new TypeError("The pageSetup.bleed option must be a non-negative number")
+    }
+    if (!['bleed', 'trim'].includes(crop)){
+      throw new TypeError(`The pageSetup.crop option must be "bleed" or "trim"`)
+    }
+    return {size, bleed, crop}
+  })
+}
+
+function bookmarks_opt(bookmarks, pp){
+  if (bookmarks===undefined) return
+  if (!Array.isArray(bookmarks)) throw new TypeError("The bookmarks option must be an array")
+
+  return bookmarks.map(({title, page=1, destination, children=[]}={}) => {
+    if (title===undefined) throw new TypeError("Each bookmark must have a title")
+    if (destination===undefined && (!Number.isInteger(page) || page<1 || page>pp)) throw new RangeError(
+      `Bookmark page must be an integer in the range 1–${pp} (got ${page})`
+    )
+    return {
+      title: String(title),
+      page: page - 1,
+      destination: destination===undefined ? undefined : String(destination),
+      children: bookmarks_opt(children, pp)
+    }
+  })
+}
+
+const LABEL_STYLES = {
+  'decimal': 'D',
+  'lower-roman': 'r',
+  'upper-roman': 'R',
+  'lower-alpha': 'a',
+  'upper-alpha': 'A',
+}
+
+function page_labels_opt(pageLabels, pp){
+  if (pageLabels===undefined) return
+  if (!Array.isArray(pageLabels)) throw new TypeError("The pageLabels option must be an array")
+
+  let labels = pageLabels.map(({page=1, style='decimal', prefix='', start=1}={}) => {
+    if (!Number.isInteger(page) || page<1 || page>pp) throw new RangeError(
+      `Page label page must be an integer in the range 1–${pp} (got ${page})`
+    )
+    if (style!==null && !(style in LABEL_STYLES)) throw new TypeError(
+      `The page label style must be ${Object.keys(LABEL_STYLES).map(s => `"${s}"`).join(', ')}, or null (got ${style})`
+    )
+    if (!Number.isInteger(start) || start<1) throw new TypeError("The page label start must be a positive integer")
+    return {page, style:style===null ? undefined : LABEL_STYLES[style], prefix:String(prefix), start}
+  }).sort((a, b) => a.page - b.page)
+
+  if (labels.some(({page}, i) => i>0 && page==labels[i-1].page)){
+    throw new RangeError("Each page can only begin a single page label range")
+  }
+
+  // the PDF spec requires the first range to begin on the first page
+  if (labels.length && labels[0].page!=1) labels.unshift({page:1, style:'D', prefix:'', start:1})
+  return labels
+}
+
+function attachments_opt(attachments){
+  if (attachments===undefined) return
+  if (!Array.isArray(attachments)) throw new TypeError("The attachments option must be an array")
+
+  let names = new Set()
+  return attachments.map(({name, data, mimeType, description, relationship='Unspecified'}={}) => {
+    if (typeof name!='string' || !name) throw new TypeError("Each attachment must have a name")
+    if (names.has(name)) throw new TypeError(`Attachment names must be unique (found "${name}" more than once)`)
+    names.add(name)
+
+    if (typeof data=='string') data = Buffer.from(data)
+    else if (ArrayBuffer.isView(data)) data = Buffer.from(data.buffer, data.byteOffset, data.byteLength)
+    else if (data instanceof ArrayBuffer) data = Buffer.from(data)
+    else throw new TypeError(`The data for attachment "${name}" must be a string, Buffer, or TypedArray`)
+
+    if (!['Source', 'Data', 'Alternative', 'Supplement', 'Unspecified'].includes(relationship)){
+      throw new TypeError(`The attachment relationship must be "Source", "Data", "Alternative", "Supplement", or "Unspecified"`)
+    }
+    return {
+      name,
+      data,
+      mimeType: mimeType===undefined ? undefined : String(mimeType),
+      description: description===undefined ? undefined : String(description),
+      relationship
+    }
+  })
+}
+
 function options(pages, size, {
     filename='', extension='', format, page, quality, matte, density, outline, archive,
-    left, top, width, height, premultiplied, colorType
+    left, top, width, height, premultiplied, colorType, lossless, animated, delay, loop, disposal,
+    metadata, bookmarks, pdfa, colorSpace='srgb', bitDepth=8, encoding, svg, layers, flatten=true,
+    transform, pageSetup, pageLabels, attachments, signal, onProgress
   } = {}) {
   var {fromMime, toMime, expected} = new Format(),
       archive = archive || 'canvas',
@@ -83,8 +433,11 @@ function options(pages, size, {
             : `Canvas has pages 1–${pp} (${idx} is out of bounds)`
   )
 
+  // gif & apng are always animated (unless writing one file per page), webp only when requested
+  animated = !isSequence && (format=='gif' || format=='apng' || format=='webp' && !!animated)
+
   pages = isFinite(idx) ? [pages[idx]]
-        : isSequence || format=='pdf' ? pages
+        : isSequence || format=='pdf' || format=='tiff' || format=='ico' || animated ? pages
         : pages.slice(-1) // default to the 'current' context
 
   if (quality===undefined){
@@ -102,15 +455,67 @@ function options(pages, size, {
     throw new TypeError("The density option must be a non-negative integer")
   }
 
+  svg = format=='svg' ? svg_opt(svg, {filename, outline}) : undefined
+
   if (outline===undefined){
-    outline = true
+    // text must stay live for embedded fonts to be used
+    outline = svg?.fonts != 'embed'
   }else if (format == 'svg'){
     outline = !!outline
   }
 
+  let animation
+  if (animated){
+    let delays = [delay===undefined ? 100 : delay].flat(),
+        disposals = [disposal || 'background'].flat()
+
+    if (!delays.length || !delays.every(ms => typeof ms=='number' && isFinite(ms) && ms>=0)){
+      throw new TypeError("The delay option must be a non-negative number of milliseconds (or an array of them)")
+    }
+    if (!disposals.length || !disposals.every(d => ['none', 'background', 'previous'].includes(d))){
+      throw new TypeError(`The disposal option must be "none", "background", or "previous" (or an array of them)`)
+    }
+    if (loop===undefined || loop===true){
+      loop = 0
+    }else if (loop===false){
+      loop = 1
+    }else if (!Number.isInteger(loop) || loop<0 || loop>65535){
+      throw new TypeError("The loop option must be a non-negative integer (or 0 to repeat forever)")
+    }
+    animation = {delays, disposals, loop}
+  }
+
+  ({lossless, metadata, colorSpace, bitDepth, encoding} = image_encoding_opt({lossless, metadata, colorSpace, bitDepth, encoding}))
+  bookmarks = bookmarks_opt(bookmarks, pages.length)
+  pdfa = !!pdfa
+  pageSetup = page_setup_opt(pageSetup)
+  pageLabels = page_labels_opt(pageLabels, pages.length)
+  attachments = attachments_opt(attachments)
+
+  if (layers!==undefined){
+    layers = [layers].flat()
+    if (layers.length==1) layers.push(layers[0])
+    if (layers.length!=2 || !layers.every(n => Number.isInteger(n) && n!=0)){
+      throw new TypeError("The layers option must be a non-zero integer or a [first, last] pair of them")
+    }
+  }
+  flatten = !!flatten
+  if (!flatten && !isSequence && !animation && !['pdf', 'tiff'].includes(format)){
+    throw new TypeError(`Exporting unflattened layers requires a multi-page format ("pdf", "tiff", "gif", or "apng") or a filename pattern containing "{}"`)
+  }
+
+  transform = transform_opt(transform)
+
+  if (signal!==undefined && (typeof signal?.aborted!='boolean' || typeof signal.addEventListener!='function')){
+    throw new TypeError("The signal option must be an AbortSignal")
+  }
+  if (onProgress!==undefined && typeof onProgress!='function'){
+    throw new TypeError("The onProgress option must be a function")
+  }
+
   const bounds = bounds_opt(size, {left, top, width, height})
 
-  return {filename, pattern, format, mime, pages, padding, quality, matte, density, outline, archive, bounds, premultiplied, colorType}
+  return {filename, pattern, format, mime, pages, padding, quality, matte, density, outline, archive, bounds, premultiplied, colorType, lossless, animation, metadata, bookmarks, pdfa, colorSpace, bitDepth, encoding, svg, layers, flatten, transform, pageSetup, pageLabels, attachments, signal, onProgress}
 }
 
 //
@@ -324,4 +729,4 @@ const atScale = (pages, density, matte) => pages.map(page => {
   return scaled
 })
 
-module.exports = {asBuffer, asDownload, asZipDownload, atScale, options, bounds_opt}
+module.exports = {asBuffer, asDownload, asZipDownload, atScale, options, bounds_opt, tiles_opt, thumbnails_opt, image_format_opt, raw_opt, transfer_buffer}
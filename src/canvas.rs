@@ -1,15 +1,27 @@
 #![allow(non_snake_case)]
 use std::cell::RefCell;
+use std::io::{self, Write};
+use std::thread;
+use std::sync::Arc;
+use crossbeam::channel::{self, Sender, Receiver};
 use neon::{prelude::*, types::buffer::TypedArray};
-use skia_safe::{Size, Rect};
+use skia_safe::{Size, Rect, Data};
 
 use crate::utils::*;
-use crate::context::page::pages_arg;
+use crate::context::page::{pages_arg, export_options_arg};
+use crate::context::progress::progress_for_keys;
+use crate::context::raw::{raw_layout_for_keys, TransferredBuffer};
+use crate::context::tiles::tiling_arg;
+use crate::context::thumbnails::thumbnails_arg;
+use crate::context::transform::transform_for_key;
 use crate::gpu;
 
 pub type BoxedCanvas = JsBox<RefCell<Canvas>>;
 impl Finalize for Canvas {}
 
+pub type BoxedStream = JsBox<RefCell<EncodedStream>>;
+impl Finalize for EncodedStream {}
+
 pub struct Canvas{
   pub width: f32,
   pub height: f32,
@@ -25,6 +37,67 @@ impl Canvas{
   pub fn size(&self) -> Size { Size::new(self.width, self.height) }
 }
 
+// an encoded file that is handed to js a chunk at a time as it's being written by a background thread
+pub struct EncodedStream{
+  requests: Option<Sender<Channel>>,
+}
+
+const STREAM_CHUNK:usize = 64 * 1024; // matches the Readable's highWaterMark
+const STREAM_BACKLOG:usize = 16;      // number of reads that can be queued up before the writer catches up
+
+// collects the output of an export into chunks of STREAM_CHUNK bytes and passes them to the stream's callback.
+// each chunk waits for a read request, whose channel keeps the event loop alive until the chunk has been delivered
+struct ChunkWriter{
+  requests: Receiver<Channel>,
+  callback: Arc<Root<JsFunction>>,
+  chunk: Vec<u8>,
+}
+
+impl ChunkWriter{
+  // hands the callback an error, the next chunk, or null once the file is complete
+  fn deliver(&self, result:Result<Option<Vec<u8>>, String>) -> Result<(), String>{
+    let channel = self.requests.recv().map_err(|_| "Stream was closed before the export finished".to_string())?;
+    let callback = Arc::clone(&self.callback);
+    channel.send(move |mut cx| {
+      let callback = callback.to_inner(&mut cx);
+      let this = cx.undefined();
+      let args = match result {
+        Ok(Some(chunk)) => {
+          let mut buffer = cx.buffer(chunk.len())?;
+          buffer.as_mut_slice(&mut cx).copy_from_slice(&chunk);
+          vec![cx.null().upcast::<JsValue>(), buffer.upcast()]
+        },
+        Ok(None) => vec![cx.null().upcast::<JsValue>(), cx.null().upcast()],
+        Err(msg) => vec![cx.error(msg)?.upcast::<JsValue>()]
+      };
+      callback.call(&mut cx, this, args)?;
+      Ok(())
+    });
+    Ok(())
+  }
+
+  fn send_chunk(&mut self) -> Result<(), String>{
+    if self.chunk.is_empty(){ return Ok(()) }
+    let chunk = std::mem::take(&mut self.chunk);
+    self.deliver(Ok(Some(chunk)))
+  }
+}
+
+impl Write for ChunkWriter{
+  fn write(&mut self, buf:&[u8]) -> io::Result<usize>{
+    let len = buf.len().min(STREAM_CHUNK - self.chunk.len());
+    self.chunk.extend_from_slice(&buf[..len]);
+    if self.chunk.len() == STREAM_CHUNK {
+      self.send_chunk().map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))?;
+    }
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> io::Result<()>{
+    self.send_chunk().map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+  }
+}
+
 //
 // -- Javascript Methods --------------------------------------------------------------------------
 //
@@ -98,23 +171,10 @@ pub fn set_engine(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 pub fn toBuffer(mut cx: FunctionContext) -> JsResult<JsPromise> {
   let this = cx.argument::<BoxedCanvas>(0)?;
   let pages = pages_arg(&mut cx, 1, &this)?;
-  let file_format = string_arg(&mut cx, 2, "format")?;
-  let quality = float_arg(&mut cx, 3, "quality")?;
-  let density = float_arg(&mut cx, 4, "density")?;
-  let outline = bool_arg(&mut cx, 5, "outline")?;
-  let matte = color_arg(&mut cx, 6);
-  let bounds = opt_rect_obj_arg(&mut cx, 7, this.borrow().size());
-  let premult = opt_bool_arg(&mut cx, 8);
-  let ctype = color_type_arg(&mut cx, 9);
+  let (pages, options) = export_options_arg(&mut cx, 2, pages, this.borrow().size())?;
 
   let promise = cx
-    .task(move || {
-      if file_format=="pdf" && pages.len() > 1 {
-        pages.as_pdf(quality, density, matte)
-      }else{
-        pages.first().encoded_as(&file_format, quality, density, outline, matte, bounds, premult, ctype, pages.engine)
-      }
-    })
+    .task(move || pages.encoded(&options))
     .promise(move |mut cx, result| {
       let data = result.or_else(|err| cx.throw_error(err))?;
       let mut buffer = cx.buffer(data.len())?;
@@ -128,24 +188,9 @@ pub fn toBuffer(mut cx: FunctionContext) -> JsResult<JsPromise> {
 pub fn toBufferSync(mut cx: FunctionContext) -> JsResult<JsValue> {
   let this = cx.argument::<BoxedCanvas>(0)?;
   let pages = pages_arg(&mut cx, 1, &this)?;
-  let file_format = string_arg(&mut cx, 2, "format")?;
-  let quality = float_arg(&mut cx, 3, "quality")?;
-  let density = float_arg(&mut cx, 4, "density")?;
-  let outline = bool_arg(&mut cx, 5, "outline")?;
-  let matte = color_arg(&mut cx, 6);
-  let bounds = opt_rect_obj_arg(&mut cx, 7, this.borrow().size());
-  let premult = opt_bool_arg(&mut cx, 8);
-  let ctype = color_type_arg(&mut cx, 9);
-
-  let encoded = {
-    if file_format=="pdf" && pages.len() > 1 {
-      pages.as_pdf(quality, density, matte)
-    }else{
-      pages.first().encoded_as(&file_format, quality, density, outline, matte, bounds, premult, ctype, pages.engine)
-    }
-  };
+  let (pages, options) = export_options_arg(&mut cx, 2, pages, this.borrow().size())?;
 
-  match encoded{
+  match pages.encoded(&options){
     Ok(data) => {
       let mut buffer = cx.buffer(data.len())?;
       buffer.as_mut_slice(&mut cx).copy_from_slice(&data);
@@ -155,14 +200,52 @@ pub fn toBufferSync(mut cx: FunctionContext) -> JsResult<JsValue> {
   }
 }
 
+// like toBuffer, but passes the file to a callback a chunk at a time (each one requested by readStream) as a
+// background thread encodes it. formats that can't be written progressively are encoded in full, then chunked
+pub fn toStream(mut cx: FunctionContext) -> JsResult<BoxedStream> {
+  let this = cx.argument::<BoxedCanvas>(0)?;
+  let pages = pages_arg(&mut cx, 1, &this)?;
+  let (pages, options) = export_options_arg(&mut cx, 2, pages, this.borrow().size())?;
+  let callback = Arc::new(cx.argument::<JsFunction>(3)?.root(&mut cx));
+
+  // a dedicated thread (rather than the task pool) since it blocks whenever the consumer falls behind
+  let (requests, receiver) = channel::bounded(STREAM_BACKLOG);
+  thread::spawn(move || {
+    let mut out = ChunkWriter{ requests:receiver, callback, chunk:vec![] };
+    let result = pages.write_to(&mut out, &options).and_then(|_| out.send_chunk());
+    out.deliver(result.map(|_| None)).ok();
+  });
+
+  Ok(cx.boxed(RefCell::new(EncodedStream{ requests:Some(requests) })))
+}
+
+// asks the writer thread for the next chunk, which it passes to the toStream callback once it's ready
+pub fn readStream(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let stream = cx.argument::<BoxedStream>(1)?;
+  if let Some(requests) = &stream.borrow().requests {
+    requests.try_send(cx.channel()).ok();
+  }
+  Ok(cx.undefined())
+}
+
+// stops a stream early (the writer thread quits the next time it waits for a read request)
+pub fn closeStream(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let stream = cx.argument::<BoxedStream>(1)?;
+  stream.borrow_mut().requests.take();
+  Ok(cx.undefined())
+}
+
 // a slightly more efficient version of toBuffer('raw')
 pub fn toRaw(mut cx: FunctionContext) -> JsResult<JsPromise> {
   let this = cx.argument::<BoxedCanvas>(0)?;
   let pages = pages_arg(&mut cx, 1, &this)?;
-  let bounds = rect_obj_arg(&mut cx, 2, this.borrow().size()).unwrap();
-  let matte = color_arg(&mut cx, 3);
-  let premult = opt_bool_arg(&mut cx, 4);
-  let ctype = color_type_arg(&mut cx, 5);
+  let options = cx.argument::<JsObject>(2)?;
+  let size = this.borrow().size();
+  let bounds = opt_rect_for_key(&mut cx, &options, "bounds", size).unwrap_or_else(|| Rect::from_size(size));
+  let matte = opt_color_for_key(&mut cx, &options, "matte");
+  let premult = opt_bool_for_key(&mut cx, &options, "premultiplied");
+  let ctype = opt_color_type_for_key(&mut cx, &options, "colorType");
+  let pages = pages.with_progress(progress_for_keys(&mut cx, &options)?);
 
   let canvas_bounds = Rect::from_size(this.borrow().size());
   if bounds.is_empty() || !canvas_bounds.intersects(bounds) {
@@ -174,24 +257,58 @@ pub fn toRaw(mut cx: FunctionContext) -> JsResult<JsPromise> {
     return Ok(promise);
   }
 
+  // an output transform renders the cropped page into a frame of its own
+  let (pages, bounds) = pages.with_transform(transform_for_key(&mut cx, &options, "transform")?, Some(bounds))
+    .or_else(|err| cx.throw_range_error(err))?;
+  let bounds = bounds.unwrap_or(pages.first().bounds);
+
+  // the size of the raw output depends on its layout & stride, and the caller may provide a buffer to render into
+  let info = make_raw_image_info(bounds.size().to_floor(), premult, ctype);
+  let layout = raw_layout_for_keys(&mut cx, &options)?;
+  layout.validate(&info).or_else(|err| cx.throw_range_error(err))?;
+  let byte_size = layout.byte_size(&info);
+
+  // the caller's buffer is rendered into directly once its memory has been transferred to a new Buffer that only
+  // the task (and, once it settles, the promise) can reach
+  let target = match opt_value_for_key(&mut cx, &options, "buffer").and_then(|val| val.downcast::<JsBuffer, _>(&mut cx).ok()) {
+    Some(buffer) => {
+      let len = buffer.as_slice(&cx).len();
+      if len < byte_size {
+        return cx.throw_range_error(format!("Buffer is too small to hold the image ({} bytes needed, {} provided)", byte_size, len))
+      }
+      let transfer:Handle<JsFunction> = options.get(&mut cx, "transfer")?;
+      let this = cx.undefined();
+      let mut buffer = transfer.call(&mut cx, this, vec![buffer])?.downcast_or_throw::<JsBuffer, _>(&mut cx)?;
+      let memory = TransferredBuffer::new(buffer.as_mut_slice(&mut cx));
+      Some((buffer.root(&mut cx), memory))
+    },
+    None => None
+  };
+  let (root, mut memory) = target.unzip();
+
   let promise = cx
     .task(move || {
+        pages.progress.check()?;
         let picture = pages.first().get_picture(matte, Some(&bounds)).ok_or("Could not generate picture")?;
-        let size = bounds.size().to_floor();
-        let info = make_raw_image_info(size, premult, ctype);
-        let mut buffer: Vec<u8> = vec![0; info.compute_min_byte_size()];
-        if pages.first().get_pixels(&mut buffer, &picture, &info /*, (bounds.left.floor() as i32, bounds.top.floor() as i32) */) {
-          Ok(buffer)
-        } else {
-          Err("Could not generate raw image".to_string())
-        }
+        let mut pixels: Vec<u8> = vec![];
+        let dest = match memory.as_mut() {
+          Some(memory) => memory.as_mut_slice(),
+          None => { pixels.resize(byte_size, 0); &mut pixels[..] }
+        };
+        pages.first().write_raw(&picture, &info, &layout, dest)?;
+        pages.progress.advance();
+        Ok(pixels)
     })
     .promise(move |mut cx, result| {
-      let data = result.or_else(|err| cx.throw_error(err))?;
-      let mut buffer = cx.buffer(data.len())?;
-      buffer.as_mut_slice(&mut cx).copy_from_slice(&data);
-
-      Ok(buffer)
+      let pixels = result.or_else(|err| cx.throw_error(err))?;
+      match root {
+        Some(root) => Ok(root.into_inner(&mut cx)),
+        None => {
+          let mut buffer = cx.buffer(pixels.len())?;
+          buffer.as_mut_slice(&mut cx).copy_from_slice(&pixels);
+          Ok(buffer)
+        }
+      }
     });
 
   Ok(promise)
@@ -201,27 +318,32 @@ pub fn save(mut cx: FunctionContext) -> JsResult<JsPromise> {
   let this = cx.argument::<BoxedCanvas>(0)?;
   let pages = pages_arg(&mut cx, 1, &this)?;
   let name_pattern = string_arg(&mut cx, 2, "filePath")?;
-  let sequence = !cx.argument::<JsValue>(3)?.is_a::<JsUndefined, _>(&mut cx);
-  let padding = opt_float_arg(&mut cx, 3).unwrap_or(-1.0);
-  let file_format = string_arg(&mut cx, 4, "format")?;
-  let quality = float_arg(&mut cx, 5, "quality")?;
-  let density = float_arg(&mut cx, 6, "density")?;
-  let outline = bool_arg(&mut cx, 7, "outline")?;
-  let matte = color_arg(&mut cx, 8);
-  let bounds = opt_rect_obj_arg(&mut cx, 9, this.borrow().size());
-  let premult = opt_bool_arg(&mut cx, 10);
-  let ctype = color_type_arg(&mut cx, 11);
+  let padding = opt_float_arg(&mut cx, 3);
+  let (pages, options) = export_options_arg(&mut cx, 4, pages, this.borrow().size())?;
 
   let promise = cx
-    .task(move || {
-      if sequence {
-        pages.write_sequence(&name_pattern, &file_format, padding, quality, density, outline, matte, bounds, premult, ctype)
-      } else if file_format == "pdf" {
-        pages.write_pdf(&name_pattern, quality, density, matte)
-      } else {
-        pages.write_image(&name_pattern, &file_format, quality, density, outline, matte, bounds, premult, ctype)
-      }
-    })
+    .task(move || pages.write(&name_pattern, padding, &options))
+    .promise(move |mut cx, result| {
+      result.or_else(|err| cx.throw_error(err))?;
+      Ok(cx.undefined())
+    });
+
+  Ok(promise)
+}
+
+// renders a single page as a pyramid of fixed-size tiles (xyz or deepzoom layout) within a directory
+pub fn saveTiles(mut cx: FunctionContext) -> JsResult<JsPromise> {
+  let this = cx.argument::<BoxedCanvas>(0)?;
+  let pages = pages_arg(&mut cx, 1, &this)?;
+  let path = string_arg(&mut cx, 2, "path")?;
+  let file_format = string_arg(&mut cx, 3, "format")?;
+  let quality = float_arg(&mut cx, 4, "quality")?;
+  let density = float_arg(&mut cx, 5, "density")?;
+  let matte = color_arg(&mut cx, 6);
+  let tiling = tiling_arg(&mut cx, 7)?;
+
+  let promise = cx
+    .task(move || pages.first().write_tiles(&path, &file_format, quality, density, matte, &tiling, pages.engine))
     .promise(move |mut cx, result| {
       result.or_else(|err| cx.throw_error(err))?;
       Ok(cx.undefined())
@@ -230,32 +352,55 @@ pub fn save(mut cx: FunctionContext) -> JsResult<JsPromise> {
   Ok(promise)
 }
 
+// render the page once and resample it to each of the requested sizes (with each thumbnail encoded separately)
+pub fn toThumbnails(mut cx: FunctionContext) -> JsResult<JsPromise> {
+  let this = cx.argument::<BoxedCanvas>(0)?;
+  let pages = pages_arg(&mut cx, 1, &this)?;
+  let thumbnails = thumbnails_arg(&mut cx, 2)?;
+  let options = cx.argument::<JsObject>(4)?;
+  let matte = opt_color_for_key(&mut cx, &options, "matte");
+
+  let promise = cx
+    .task(move || pages.first().as_thumbnails(matte, &thumbnails, pages.engine))
+    .promise(move |mut cx, result| {
+      let encoded = result.or_else(|err| cx.throw_error(err))?;
+      buffers_to_array(&mut cx, &encoded)
+    });
+
+  Ok(promise)
+}
+
+pub fn toThumbnailsSync(mut cx: FunctionContext) -> JsResult<JsArray> {
+  let this = cx.argument::<BoxedCanvas>(0)?;
+  let pages = pages_arg(&mut cx, 1, &this)?;
+  let thumbnails = thumbnails_arg(&mut cx, 2)?;
+  let options = cx.argument::<JsObject>(4)?;
+  let matte = opt_color_for_key(&mut cx, &options, "matte");
+
+  match pages.first().as_thumbnails(matte, &thumbnails, pages.engine){
+    Ok(encoded) => buffers_to_array(&mut cx, &encoded),
+    Err(msg) => cx.throw_error(msg)
+  }
+}
+
+fn buffers_to_array<'a>(cx: &mut impl Context<'a>, encoded:&[Data]) -> JsResult<'a, JsArray> {
+  let array = JsArray::new(cx, encoded.len() as u32);
+  for (i, data) in encoded.iter().enumerate() {
+    let mut buffer = cx.buffer(data.len())?;
+    buffer.as_mut_slice(cx).copy_from_slice(data);
+    array.set(cx, i as u32, buffer)?;
+  }
+  Ok(array)
+}
+
 pub fn saveSync(mut cx: FunctionContext) -> JsResult<JsUndefined> {
   let this = cx.argument::<BoxedCanvas>(0)?;
   let pages = pages_arg(&mut cx, 1, &this)?;
   let name_pattern = string_arg(&mut cx, 2, "filePath")?;
-  let sequence = !cx.argument::<JsValue>(3)?.is_a::<JsUndefined, _>(&mut cx);
-  let padding = opt_float_arg(&mut cx, 3).unwrap_or(-1.0);
-  let file_format = string_arg(&mut cx, 4, "format")?;
-  let quality = float_arg(&mut cx, 5, "quality")?;
-  let density = float_arg(&mut cx, 6, "density")?;
-  let outline = bool_arg(&mut cx, 7, "outline")?;
-  let matte = color_arg(&mut cx, 8);
-  let bounds = opt_rect_obj_arg(&mut cx, 9, this.borrow().size());
-  let premult = opt_bool_arg(&mut cx, 10);
-  let ctype = color_type_arg(&mut cx, 11);
-
-  let result = {
-    if sequence {
-      pages.write_sequence(&name_pattern, &file_format, padding, quality, density, outline, matte, bounds, premult, ctype)
-    } else if file_format == "pdf" {
-      pages.write_pdf(&name_pattern, quality, density, matte)
-    } else {
-      pages.write_image(&name_pattern, &file_format, quality, density, outline, matte, bounds, premult, ctype)
-    }
-  };
+  let padding = opt_float_arg(&mut cx, 3);
+  let (pages, options) = export_options_arg(&mut cx, 4, pages, this.borrow().size())?;
 
-  match result{
+  match pages.write(&name_pattern, padding, &options){
     Ok(_) => Ok(cx.undefined()),
     Err(msg) => cx.throw_error(msg)
   }
@@ -0,0 +1,227 @@
+//
+// Pixel-level and perceptual comparison of two images (for visual regression tests)
+//
+use rayon::prelude::*;
+use neon::{prelude::*, types::buffer::TypedArray};
+use skia_safe::{Image as SkImage, Data, ImageInfo, ColorType, AlphaType, ColorSpace, EncodedImageFormat};
+use skia_safe::image::CachingHint;
+
+use crate::utils::*;
+use crate::image::BoxedImage;
+use crate::context::BoxedContext2D;
+
+// SSIM is computed over 8×8 windows that overlap by half their width
+const WINDOW:usize = 8;
+const STRIDE:usize = WINDOW / 2;
+
+pub struct Comparison{
+  pub max_error: [u8; 4],
+  pub mismatched: usize,
+  pub ssim: f64,
+  pub diff: Option<Vec<u8>>,
+}
+
+pub fn compare(a:&[u8], b:&[u8], width:usize, height:usize, threshold:u8, with_diff:bool) -> Comparison {
+  let row_bytes = width * 4;
+  let (max_error, mismatched) = a.par_chunks(row_bytes).zip(b.par_chunks(row_bytes))
+    .map(|(row_a, row_b)|{
+      let mut max = [0u8; 4];
+      let mut count = 0;
+      for (px_a, px_b) in row_a.chunks_exact(4).zip(row_b.chunks_exact(4)){
+        let mut over = false;
+        for c in 0..4 {
+          let delta = px_a[c].abs_diff(px_b[c]);
+          max[c] = max[c].max(delta);
+          over |= delta > threshold;
+        }
+        count += over as usize;
+      }
+      (max, count)
+    })
+    .reduce(|| ([0u8; 4], 0), |(max_a, count_a), (max_b, count_b)|{
+      let mut max = max_a;
+      max.iter_mut().zip(max_b).for_each(|(m, n)| *m = (*m).max(n));
+      (max, count_a + count_b)
+    });
+
+  let ssim = ssim(&luma_plane(a), &luma_plane(b), width, height);
+  let diff = with_diff.then(|| diff_pixels(a, b, threshold));
+  Comparison{ max_error, mismatched, ssim, diff }
+}
+
+// perceived brightness of an unpremultiplied RGBA pixel after compositing it over white
+fn luma(px:&[u8]) -> f64 {
+  let alpha = px[3] as f64 / 255.0;
+  let y = 0.299 * px[0] as f64 + 0.587 * px[1] as f64 + 0.114 * px[2] as f64;
+  y * alpha + 255.0 * (1.0 - alpha)
+}
+
+fn luma_plane(pixels:&[u8]) -> Vec<f64> {
+  pixels.par_chunks_exact(4).map(luma).collect()
+}
+
+// mean structural similarity of two luma planes (1.0 for identical images, approaching 0 as they diverge)
+fn ssim(x:&[f64], y:&[f64], width:usize, height:usize) -> f64 {
+  if width == 0 || height == 0 { return 1.0 }
+
+  let c1 = (0.01 * 255.0_f64).powi(2);
+  let c2 = (0.03 * 255.0_f64).powi(2);
+  let (win_w, win_h) = (WINDOW.min(width), WINDOW.min(height));
+
+  // window origins, with a final one aligned to the far edge if the stride doesn't land there evenly
+  let origins = |extent:usize, win:usize| {
+    let mut starts:Vec<usize> = (0..=extent - win).step_by(STRIDE).collect();
+    if starts.last() != Some(&(extent - win)) { starts.push(extent - win) }
+    starts
+  };
+  let lefts = origins(width, win_w);
+  let tops = origins(height, win_h);
+  let n = (win_w * win_h) as f64;
+
+  let total:f64 = tops.par_iter().map(|&top|{
+    lefts.iter().map(|&left|{
+      let (mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+      for row in top..top + win_h {
+        for i in row * width + left..row * width + left + win_w {
+          let (vx, vy) = (x[i], y[i]);
+          sx += vx; sy += vy;
+          sxx += vx * vx; syy += vy * vy; sxy += vx * vy;
+        }
+      }
+      let (mx, my) = (sx / n, sy / n);
+      let (var_x, var_y, cov) = (sxx / n - mx * mx, syy / n - my * my, sxy / n - mx * my);
+      ((2.0 * mx * my + c1) * (2.0 * cov + c2)) / ((mx * mx + my * my + c1) * (var_x + var_y + c2))
+    }).sum::<f64>()
+  }).sum();
+
+  total / (lefts.len() * tops.len()) as f64
+}
+
+// a faded grayscale copy of the first image with mismatched pixels painted over it
+// (shading from yellow for barely-over-threshold differences to red for the largest ones)
+fn diff_pixels(a:&[u8], b:&[u8], threshold:u8) -> Vec<u8> {
+  a.par_chunks_exact(4).zip(b.par_chunks_exact(4))
+    .flat_map_iter(|(px_a, px_b)|{
+      let delta = (0..4).map(|c| px_a[c].abs_diff(px_b[c])).max().unwrap_or(0);
+      if delta > threshold {
+        [255, 255 - delta, 0, 255]
+      }else{
+        let faded = 255 - ((255.0 - luma(px_a)) / 4.0) as u8;
+        [faded, faded, faded, 255]
+      }
+    })
+    .collect()
+}
+
+//
+// -- Javascript Methods --------------------------------------------------------------------------
+//
+
+fn image_arg(cx: &mut FunctionContext, idx:usize) -> NeonResult<SkImage> {
+  let arg = cx.argument::<JsValue>(idx as i32)?;
+  let image = if let Ok(img) = arg.downcast::<BoxedImage, _>(cx){
+    img.borrow().image.clone()
+  }else if let Ok(ctx) = arg.downcast::<BoxedContext2D, _>(cx){
+    ctx.borrow().get_image()
+  }else if let Ok(buffer) = arg.downcast::<JsBuffer, _>(cx){
+    let data = Data::new_copy(buffer.as_slice(cx));
+    match SkImage::from_encoded(data){
+      Some(image) => Some(image),
+      None => return cx.throw_error("Could not decode image data")
+    }
+  }else{
+    return cx.throw_type_error("Expected a Canvas, Image, or Buffer to compare")
+  };
+
+  match image {
+    Some(image) => Ok(image),
+    None => cx.throw_error("Image has not been loaded yet")
+  }
+}
+
+fn image_pixels(image:&SkImage) -> Result<Vec<u8>, String> {
+  let info = ImageInfo::new(image.dimensions(), ColorType::RGBA8888, AlphaType::Unpremul, Some(ColorSpace::new_srgb()));
+  let mut pixels = vec![0u8; info.compute_min_byte_size()];
+  match image.read_pixels(&info, &mut pixels, info.min_row_bytes(), (0, 0), CachingHint::Allow){
+    true => Ok(pixels),
+    false => Err("Could not read pixels from image".to_string())
+  }
+}
+
+// the images to compare along with the threshold & diff options
+fn compare_args(cx: &mut FunctionContext) -> NeonResult<(SkImage, SkImage, u8, bool)> {
+  let img_a = image_arg(cx, 0)?;
+  let img_b = image_arg(cx, 1)?;
+  let threshold = float_arg_or(cx, 2, 0.0).clamp(0.0, 255.0) as u8;
+  let with_diff = bool_arg_or(cx, 3, false);
+
+  if img_a.dimensions() != img_b.dimensions() {
+    let (a, b) = (img_a.dimensions(), img_b.dimensions());
+    return cx.throw_range_error(format!(
+      "Images must be the same size to be compared (got {}×{} and {}×{})", a.width, a.height, b.width, b.height
+    ))
+  }
+  Ok((img_a, img_b, threshold, with_diff))
+}
+
+// decodes both images and compares their pixels, returning the results along with the diff image encoded as a PNG
+fn compare_images(img_a:&SkImage, img_b:&SkImage, threshold:u8, with_diff:bool) -> Result<(Comparison, Option<Data>), String> {
+  let (width, height) = (img_a.width() as usize, img_a.height() as usize);
+  let (pixels_a, pixels_b) = (image_pixels(img_a)?, image_pixels(img_b)?);
+  let mut result = compare(&pixels_a, &pixels_b, width, height, threshold, with_diff);
+
+  let png = match result.diff.take() {
+    Some(diff) => {
+      let info = ImageInfo::new(img_a.dimensions(), ColorType::RGBA8888, AlphaType::Unpremul, Some(ColorSpace::new_srgb()));
+      let png = SkImage::from_raster_data(&info, Data::new_copy(&diff), info.min_row_bytes())
+        .and_then(|img| img.encode_to_data(EncodedImageFormat::PNG))
+        .ok_or("Could not encode diff image")?;
+      Some(png)
+    },
+    None => None
+  };
+  Ok((result, png))
+}
+
+fn comparison_object<'a>(cx: &mut impl Context<'a>, result:&Comparison, total:usize, diff:Option<Data>) -> JsResult<'a, JsObject> {
+  let js_object: Handle<JsObject> = cx.empty_object();
+  let max_error = floats_to_array(cx, &result.max_error.map(|n| n as f32))?;
+  let mismatched = cx.number(result.mismatched as f64);
+  let total = cx.number(total as f64);
+  let ssim = cx.number(result.ssim);
+  js_object.set(cx, "maxError", max_error)?;
+  js_object.set(cx, "mismatched", mismatched)?;
+  js_object.set(cx, "total", total)?;
+  js_object.set(cx, "ssim", ssim)?;
+
+  if let Some(png) = diff {
+    let mut buffer = cx.buffer(png.len())?;
+    buffer.as_mut_slice(cx).copy_from_slice(&png);
+    js_object.set(cx, "diff", buffer)?;
+  }
+  Ok(js_object)
+}
+
+#[allow(non_snake_case)]
+pub fn compareImages(mut cx: FunctionContext) -> JsResult<JsPromise> {
+  let (img_a, img_b, threshold, with_diff) = compare_args(&mut cx)?;
+  let total = (img_a.width() * img_a.height()) as usize;
+
+  let promise = cx
+    .task(move || compare_images(&img_a, &img_b, threshold, with_diff))
+    .promise(move |mut cx, result| {
+      let (result, diff) = result.or_else(|err| cx.throw_error(err))?;
+      comparison_object(&mut cx, &result, total, diff)
+    });
+
+  Ok(promise)
+}
+
+#[allow(non_snake_case)]
+pub fn compareImagesSync(mut cx: FunctionContext) -> JsResult<JsObject> {
+  let (img_a, img_b, threshold, with_diff) = compare_args(&mut cx)?;
+  let total = (img_a.width() * img_a.height()) as usize;
+
+  let (result, diff) = compare_images(&img_a, &img_b, threshold, with_diff).or_else(|err| cx.throw_error(err))?;
+  comparison_object(&mut cx, &result, total, diff)
+}
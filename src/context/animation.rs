@@ -0,0 +1,692 @@
+//
+// Animated GIF, APNG, and WebP files assembled from a sequence of rendered frames
+// (and decoded back into individual frames when loaded as an Image)
+//
+
+use std::collections::HashMap;
+use neon::prelude::*;
+use skia_safe::{Data, Image as SkImage, ColorType, AlphaType, EncodedImageFormat, Surface, IRect, Rect, Paint,
+                BlendMode, Color, ClipOp};
+use skia_safe::codec::{Codec, Options as CodecOptions, ZeroInitialized, Result as CodecResult};
+use crc::{Crc, CRC_32_ISO_HDLC};
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+use crate::utils::*;
+use super::raster::unpremultiplied_pixels;
+
+const DEFAULT_DELAY:f32 = 100.0; // milliseconds
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Disposal{
+  None,       // leave the frame in place and draw the next one on top of it
+  Background, // clear the frame's region to transparent before drawing the next one
+  Previous,   // restore the region to what it looked like before the frame was drawn
+}
+
+#[derive(Clone, Debug)]
+pub struct Animation{
+  pub delays: Vec<f32>,         // per-frame durations in ms (the final value repeats for any extra frames)
+  pub disposals: Vec<Disposal>, // per-frame disposal methods (likewise)
+  pub loops: u16,               // total number of times to play through (0 = forever)
+}
+
+impl Default for Animation{
+  fn default() -> Self {
+    Animation{ delays:vec![DEFAULT_DELAY], disposals:vec![Disposal::Background], loops:0 }
+  }
+}
+
+impl Animation{
+  pub fn delay(&self, idx:usize) -> f32{
+    self.delays.get(idx).or_else(|| self.delays.last()).copied().unwrap_or(DEFAULT_DELAY).max(0.0)
+  }
+
+  pub fn disposal(&self, idx:usize) -> Disposal{
+    self.disposals.get(idx).or_else(|| self.disposals.last()).copied().unwrap_or(Disposal::Background)
+  }
+}
+
+pub fn encode(frames:&[SkImage], format:&str, quality:f32, lossless:bool, animation:&Animation) -> Result<Data, String>{
+  if frames.is_empty(){
+    return Err("Cannot create an animation without any frames".to_string())
+  }
+
+  let frames = uniform_frames(frames)?;
+  match format {
+    "gif" => encode_gif(&frames, animation),
+    "apng" => encode_apng(&frames, animation),
+    "webp" => encode_webp(&frames, quality, lossless, animation),
+    _ => Err(format!("Unsupported animation format {}", format))
+  }
+}
+
+// pad any frames smaller than the largest one so they all share the same dimensions
+fn uniform_frames(frames:&[SkImage]) -> Result<Vec<SkImage>, String>{
+  let width = frames.iter().map(|f| f.width()).max().unwrap_or(0);
+  let height = frames.iter().map(|f| f.height()).max().unwrap_or(0);
+
+  frames.iter().map(|frame|{
+    if frame.width() == width && frame.height() == height {
+      return Ok(frame.clone())
+    }
+    let mut surface = Surface::new_raster_n32_premul((width, height))
+      .ok_or(format!("Could not allocate new {}×{} bitmap", width, height))?;
+    surface.canvas().draw_image(frame, (0.0, 0.0), None);
+    Ok(surface.image_snapshot())
+  }).collect()
+}
+
+//
+// GIF
+//
+
+fn encode_gif(frames:&[SkImage], animation:&Animation) -> Result<Data, String>{
+  let (width, height) = (frames[0].width(), frames[0].height());
+  if width > u16::MAX as i32 || height > u16::MAX as i32 {
+    return Err(format!("GIF dimensions are limited to {}×{} pixels", u16::MAX, u16::MAX))
+  }
+  let (width, height) = (width as u16, height as u16);
+
+  // header & logical screen descriptor (no global color table)
+  let mut gif = b"GIF89a".to_vec();
+  gif.extend(width.to_le_bytes());
+  gif.extend(height.to_le_bytes());
+  gif.extend([0x00, 0x00, 0x00]);
+
+  // the NETSCAPE2.0 extension counts *repetitions*, so a single play-through omits it entirely
+  if frames.len() > 1 && animation.loops != 1 {
+    gif.extend([0x21, 0xFF, 0x0B]);
+    gif.extend(b"NETSCAPE2.0");
+    gif.extend([0x03, 0x01]);
+    gif.extend(animation.loops.saturating_sub(1).to_le_bytes());
+    gif.push(0x00);
+  }
+
+  for (idx, frame) in frames.iter().enumerate(){
+    let (palette, indices, transparent) = quantize(&unpremultiplied_pixels(frame, ColorType::RGBA8888)?);
+    let depth = (1..=8).find(|bits| 1 << bits >= palette.len()).unwrap_or(8);
+    let disposal:u8 = match animation.disposal(idx){
+      Disposal::None => 1,
+      Disposal::Background => 2,
+      Disposal::Previous => 3,
+    };
+    let delay = (animation.delay(idx) / 10.0).round().min(u16::MAX as f32) as u16;
+
+    // graphic control extension
+    gif.extend([0x21, 0xF9, 0x04, disposal << 2 | transparent.is_some() as u8]);
+    gif.extend(delay.to_le_bytes());
+    gif.extend([transparent.unwrap_or(0), 0x00]);
+
+    // image descriptor & local color table
+    gif.push(0x2C);
+    gif.extend([0u8; 4]);
+    gif.extend(width.to_le_bytes());
+    gif.extend(height.to_le_bytes());
+    gif.push(0x80 | (depth as u8 - 1));
+    for i in 0..(1 << depth){
+      gif.extend(palette.get(i).unwrap_or(&[0, 0, 0]));
+    }
+
+    // LZW-compressed indices, broken up into sub-blocks
+    let min_code_size = depth.max(2) as u8;
+    gif.push(min_code_size);
+    for block in lzw_compress(&indices, min_code_size).chunks(255){
+      gif.push(block.len() as u8);
+      gif.extend(block);
+    }
+    gif.push(0x00);
+  }
+
+  gif.push(0x3B);
+  Ok(Data::new_copy(&gif))
+}
+
+// Reduce an unpremultiplied RGBA buffer to a palette of at most 256 colors (one of which is reserved
+// for transparency if any pixels are less than half-opaque) using median-cut, returning the palette,
+// the per-pixel palette indices, and the index of the transparent entry (if any)
+pub(super) fn quantize(pixels:&[u8]) -> (Vec<[u8; 3]>, Vec<u8>, Option<u8>){
+  let is_clear = |px:&[u8]| px[3] < 128;
+  let has_alpha = pixels.chunks_exact(4).any(is_clear);
+  let max_colors = if has_alpha { 255 } else { 256 };
+
+  let mut histogram:HashMap<[u8; 3], u32> = HashMap::new();
+  for px in pixels.chunks_exact(4).filter(|px| px[3] >= 128){
+    *histogram.entry([px[0], px[1], px[2]]).or_insert(0) += 1;
+  }
+
+  let mut lookup:HashMap<[u8; 3], u8> = HashMap::new();
+  let mut palette:Vec<[u8; 3]> = if histogram.len() <= max_colors {
+    let colors:Vec<[u8; 3]> = histogram.keys().copied().collect();
+    lookup.extend(colors.iter().enumerate().map(|(i, c)| (*c, i as u8)));
+    colors
+  }else{
+    median_cut(histogram.into_iter().collect(), max_colors)
+  };
+
+  let transparent = has_alpha.then(|| palette.len() as u8);
+  if has_alpha {
+    palette.push([0, 0, 0]);
+  }
+
+  let opaque = &palette[..palette.len() - has_alpha as usize];
+  let indices = pixels.chunks_exact(4).map(|px|{
+    if is_clear(px) {
+      return transparent.unwrap_or(0)
+    }
+    let rgb = [px[0], px[1], px[2]];
+    *lookup.entry(rgb).or_insert_with(|| nearest_color(opaque, rgb))
+  }).collect();
+
+  (palette, indices, transparent)
+}
+
+fn median_cut(colors:Vec<([u8; 3], u32)>, max_colors:usize) -> Vec<[u8; 3]>{
+  let extent = |bucket:&[([u8; 3], u32)]| -> (usize, u8) {
+    (0..3).map(|ch|{
+      let lo = bucket.iter().map(|(c, _)| c[ch]).min().unwrap_or(0);
+      let hi = bucket.iter().map(|(c, _)| c[ch]).max().unwrap_or(0);
+      (ch, hi - lo)
+    }).max_by_key(|(_, range)| *range).unwrap_or((0, 0))
+  };
+
+  let mut buckets = vec![colors];
+  while buckets.len() < max_colors {
+    // split the bucket spanning the widest range of values along that channel
+    let widest = buckets.iter().enumerate()
+      .filter(|(_, bucket)| bucket.len() > 1)
+      .map(|(i, bucket)| (i, extent(bucket)))
+      .max_by_key(|(_, (_, range))| *range);
+
+    let (idx, channel) = match widest {
+      Some((idx, (channel, range))) if range > 0 => (idx, channel),
+      _ => break
+    };
+
+    let mut bucket = buckets.swap_remove(idx);
+    bucket.sort_unstable_by_key(|(c, _)| c[channel]);
+    let total:u64 = bucket.iter().map(|(_, n)| *n as u64).sum();
+    let mut running = 0;
+    let median = bucket.iter().position(|(_, n)|{
+      running += *n as u64;
+      running * 2 >= total
+    }).unwrap_or(0).clamp(0, bucket.len() - 2);
+    let upper = bucket.split_off(median + 1);
+    buckets.push(bucket);
+    buckets.push(upper);
+  }
+
+  buckets.iter().map(|bucket|{
+    let total:u64 = bucket.iter().map(|(_, n)| *n as u64).sum::<u64>().max(1);
+    let mut avg = [0u8; 3];
+    for (ch, val) in avg.iter_mut().enumerate(){
+      let sum:u64 = bucket.iter().map(|(c, n)| c[ch] as u64 * *n as u64).sum();
+      *val = ((sum + total / 2) / total) as u8;
+    }
+    avg
+  }).collect()
+}
+
+fn nearest_color(palette:&[[u8; 3]], rgb:[u8; 3]) -> u8{
+  palette.iter().enumerate().min_by_key(|(_, c)|{
+    (0..3).map(|ch| (c[ch] as i32 - rgb[ch] as i32).pow(2)).sum::<i32>()
+  }).map(|(i, _)| i as u8).unwrap_or(0)
+}
+
+fn lzw_compress(indices:&[u8], min_code_size:u8) -> Vec<u8>{
+  let clear_code = 1u16 << min_code_size;
+  let end_code = clear_code + 1;
+  let mut next_code = end_code + 1;
+  let mut code_size = min_code_size + 1;
+  let mut dict:HashMap<(u16, u8), u16> = HashMap::new();
+
+  let mut output:Vec<u8> = vec![];
+  let (mut acc, mut acc_bits) = (0u32, 0u8);
+  let mut emit = |code:u16, size:u8|{
+    acc |= (code as u32) << acc_bits;
+    acc_bits += size;
+    while acc_bits >= 8 {
+      output.push(acc as u8);
+      acc >>= 8;
+      acc_bits -= 8;
+    }
+  };
+
+  emit(clear_code, code_size);
+  let mut prefix:Option<u16> = None;
+  for &k in indices {
+    prefix = Some(match prefix {
+      None => k as u16,
+      Some(p) => match dict.get(&(p, k)) {
+        Some(&code) => code,
+        None => {
+          emit(p, code_size);
+          if next_code < 4096 {
+            dict.insert((p, k), next_code);
+            next_code += 1;
+            if next_code > 1 << code_size && code_size < 12 {
+              code_size += 1;
+            }
+          }else{
+            // the code table is full, so start over with a fresh one
+            emit(clear_code, code_size);
+            dict.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+          }
+          k as u16
+        }
+      }
+    });
+  }
+  if let Some(p) = prefix {
+    emit(p, code_size);
+    // the decoder adds one last table entry after reading the final code (and may widen its codes)
+    if next_code >= 1 << code_size && code_size < 12 {
+      code_size += 1;
+    }
+  }
+  emit(end_code, code_size);
+  if acc_bits > 0 {
+    output.push(acc as u8); // flush any remaining bits
+  }
+  output
+}
+
+//
+// APNG
+//
+
+pub(super) fn png_chunk(tag:&[u8], body:&[u8]) -> Vec<u8>{
+  let mut digest = CRC32.digest();
+  digest.update(tag);
+  digest.update(body);
+  [
+    &(body.len() as u32).to_be_bytes()[..], tag, body, &digest.finalize().to_be_bytes()[..]
+  ].concat()
+}
+
+// split a PNG file into its (tag, body) chunks
+pub(super) fn png_chunks(data:&[u8]) -> Result<Vec<(&[u8], &[u8])>, String>{
+  let mut chunks = vec![];
+  let mut pos = 8;
+  while pos + 12 <= data.len() {
+    let len = u32::from_be_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]) as usize;
+    let body = pos + 8;
+    if body + len + 4 > data.len(){ break }
+    chunks.push((&data[pos+4..body], &data[body..body+len]));
+    pos = body + len + 4;
+  }
+  match chunks.first() {
+    Some((b"IHDR", _)) => Ok(chunks),
+    _ => Err("Could not parse PNG-encoded frame".to_string())
+  }
+}
+
+fn encode_apng(frames:&[SkImage], animation:&Animation) -> Result<Data, String>{
+  let encoded = frames.iter().map(|frame|
+    frame.encode_to_data(EncodedImageFormat::PNG).ok_or("Could not encode frame as PNG".to_string())
+  ).collect::<Result<Vec<Data>, String>>()?;
+
+  let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+  let mut sequence = 0u32;
+  for (idx, data) in encoded.iter().enumerate(){
+    let chunks = png_chunks(data.as_bytes())?;
+    let (_, ihdr) = chunks[0];
+
+    if idx == 0 {
+      let num_plays = animation.loops as u32;
+      let actl = [(frames.len() as u32).to_be_bytes(), num_plays.to_be_bytes()].concat();
+      apng.extend(png_chunk(b"IHDR", ihdr));
+      apng.extend(png_chunk(b"acTL", &actl));
+      // carry over any ancillary chunks (color space, gamma, etc.) from the first frame
+      for (tag, body) in chunks.iter().skip(1).take_while(|(tag, _)| *tag != b"IDAT"){
+        apng.extend(png_chunk(tag, body));
+      }
+    }
+
+    let delay = animation.delay(idx).round().min(u16::MAX as f32) as u16;
+    let dispose_op:u8 = match animation.disposal(idx){
+      Disposal::None => 0,
+      Disposal::Background => 1,
+      Disposal::Previous => 2,
+    };
+    let fctl = [
+      &sequence.to_be_bytes()[..],
+      &ihdr[0..8],                  // width & height
+      &[0u8; 8],                    // x & y offset
+      &delay.to_be_bytes(), &1000u16.to_be_bytes(),
+      &[dispose_op, 1],             // blend_op = APNG_BLEND_OP_OVER
+    ].concat();
+    apng.extend(png_chunk(b"fcTL", &fctl));
+    sequence += 1;
+
+    for (_, body) in chunks.iter().filter(|(tag, _)| *tag == b"IDAT"){
+      if idx == 0 {
+        apng.extend(png_chunk(b"IDAT", body));
+      }else{
+        apng.extend(png_chunk(b"fdAT", &[&sequence.to_be_bytes()[..], *body].concat()));
+        sequence += 1;
+      }
+    }
+  }
+  apng.extend(png_chunk(b"IEND", &[]));
+  Ok(Data::new_copy(&apng))
+}
+
+//
+// WebP
+//
+
+pub(super) fn riff_chunk(tag:&[u8], body:&[u8]) -> Vec<u8>{
+  let mut chunk = [tag, &(body.len() as u32).to_le_bytes()[..], body].concat();
+  if body.len() % 2 == 1 {
+    chunk.push(0);
+  }
+  chunk
+}
+
+pub(super) fn u24(val:u32) -> [u8; 3]{
+  let [a, b, c, _] = val.to_le_bytes();
+  [a, b, c]
+}
+
+// extract the image-data chunks (ALPH, VP8, & VP8L) from a WebP file, noting whether it uses alpha
+fn webp_bitstream(data:&[u8]) -> Result<(Vec<u8>, bool), String>{
+  if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+    return Err("Could not parse WebP-encoded frame".to_string())
+  }
+
+  let (mut stream, mut has_alpha) = (vec![], false);
+  let mut pos = 12;
+  while pos + 8 <= data.len() {
+    let tag = &data[pos..pos+4];
+    let len = u32::from_le_bytes([data[pos+4], data[pos+5], data[pos+6], data[pos+7]]) as usize;
+    let end = (pos + 8 + len + len % 2).min(data.len());
+    match tag {
+      b"ALPH" | b"VP8L" => { has_alpha = true; stream.extend(&data[pos..end]) },
+      b"VP8 " => stream.extend(&data[pos..end]),
+      _ => {}
+    }
+    pos = end;
+  }
+  Ok((stream, has_alpha))
+}
+
+fn encode_webp(frames:&[SkImage], quality:f32, lossless:bool, animation:&Animation) -> Result<Data, String>{
+  let (width, height) = (frames[0].width() as u32, frames[0].height() as u32);
+
+  // Skia's WebP encoder switches to lossless compression when the quality is exactly 100
+  let quality = if lossless { 100 } else { ((quality*100.0) as i32).min(99) };
+  let mut has_alpha = false;
+  let mut anmf = vec![];
+  for (idx, frame) in frames.iter().enumerate(){
+    let data = frame.encode_to_data_with_quality(EncodedImageFormat::WEBP, quality)
+      .ok_or("Could not encode frame as WebP".to_string())?;
+    let (stream, alpha) = webp_bitstream(data.as_bytes())?;
+    has_alpha |= alpha;
+
+    let duration = animation.delay(idx).round().min(0xFFFFFF as f32) as u32;
+    // WebP has no equivalent to "previous" so it's treated like "background"
+    let dispose:u8 = match animation.disposal(idx){
+      Disposal::None => 0,
+      Disposal::Background | Disposal::Previous => 1,
+    };
+    let header = [
+      &u24(0)[..], &u24(0),                 // x & y offset
+      &u24(width - 1), &u24(height - 1),
+      &u24(duration),
+      &[dispose],                           // alpha-blend with the previous frame
+    ].concat();
+    anmf.extend(riff_chunk(b"ANMF", &[header, stream].concat()));
+  }
+
+  let flags:u8 = 0x02 | if has_alpha { 0x10 } else { 0 };
+  let vp8x = [&[flags, 0, 0, 0][..], &u24(width - 1), &u24(height - 1)].concat();
+  let anim = [&[0u8; 4][..], &animation.loops.to_le_bytes()].concat(); // transparent background
+  let body = [
+    &b"WEBP"[..], &riff_chunk(b"VP8X", &vp8x)[..], &riff_chunk(b"ANIM", &anim)[..], &anmf[..]
+  ].concat();
+
+  Ok(Data::new_copy(&riff_chunk(b"RIFF", &body)))
+}
+
+//
+// Decoding
+//
+
+#[derive(Clone)]
+enum FrameSource{
+  Codec{ required:Vec<Option<usize>> }, // GIF & WebP (with the earlier frame each one is drawn on top of)
+  Apng{ frames:Vec<ApngFrame> },        // which Skia only decodes the default image of
+}
+
+#[derive(Clone)]
+struct ApngFrame{
+  png: Data, // the frame's image data repackaged as a standalone PNG file
+  rect: IRect,
+  disposal: Disposal,
+  blend: bool,
+}
+
+#[derive(Clone)]
+pub struct AnimatedSource{
+  data: Data,
+  source: FrameSource,
+  pub durations: Vec<f32>, // per-frame durations in ms
+  pub loops: u16,          // total number of times to play through (0 = forever)
+}
+
+impl AnimatedSource{
+  // returns None for still images
+  pub fn read(data:&Data) -> Option<Self>{
+    match data.as_bytes().starts_with(b"\x89PNG") {
+      true => Self::read_apng(data),
+      false => Self::read_codec(data)
+    }
+  }
+
+  pub fn frame_count(&self) -> usize{
+    self.durations.len()
+  }
+
+  fn read_codec(data:&Data) -> Option<Self>{
+    let mut codec = Codec::from_data(data.clone())?;
+    let count = codec.get_frame_count();
+    if count < 2 { return None }
+
+    let frames = (0..count).map(|idx| codec.get_frame_info(idx)).collect::<Option<Vec<_>>>()?;
+    let durations = frames.iter().map(|info| info.duration.max(0) as f32).collect();
+    let required = frames.iter().map(|info| usize::try_from(info.required_frame).ok()).collect();
+
+    // skia reports the number of *repetitions* (or None for infinite looping)
+    let loops = match codec.get_repetition_count() {
+      Some(count) => (count + 1).min(u16::MAX as usize) as u16,
+      None => 0
+    };
+    Some(AnimatedSource{ data:data.clone(), source:FrameSource::Codec{ required }, durations, loops })
+  }
+
+  fn read_apng(data:&Data) -> Option<Self>{
+    let chunks = png_chunks(data.as_bytes()).ok()?;
+    let actl = chunks.iter().find(|(tag, _)| *tag == b"acTL").map(|(_, body)| *body)?;
+    let loops = u32::from_be_bytes(actl.get(4..8)?.try_into().ok()?).min(u16::MAX as u32) as u16;
+    let (_, ihdr) = chunks[0];
+
+    // chunks describing the color palette, gamma, etc. that each frame's standalone PNG will need
+    let shared:Vec<u8> = chunks.iter()
+      .skip(1)
+      .take_while(|(tag, _)| *tag != b"IDAT")
+      .filter(|(tag, _)| !matches!(*tag, b"acTL" | b"fcTL"))
+      .flat_map(|(tag, body)| png_chunk(tag, body))
+      .collect();
+
+    let mut frames = vec![];
+    let mut durations = vec![];
+    let mut current:Option<(&[u8], Vec<u8>)> = None; // the frame's fcTL & image data
+    let finish = |fctl:&[u8], image_data:Vec<u8>| -> Option<(ApngFrame, f32)>{
+      let field = |pos:usize| Some(u32::from_be_bytes(fctl.get(pos..pos + 4)?.try_into().ok()?));
+      let (width, height, x, y) = (field(4)?, field(8)?, field(12)?, field(16)?);
+      let delay_num = u16::from_be_bytes(fctl.get(20..22)?.try_into().ok()?) as f32;
+      let delay_den = match u16::from_be_bytes(fctl.get(22..24)?.try_into().ok()?) { 0 => 100.0, den => den as f32 };
+      let disposal = match fctl.get(24)? { 1 => Disposal::Background, 2 => Disposal::Previous, _ => Disposal::None };
+      let blend = *fctl.get(25)? == 1;
+
+      let png = [
+        &b"\x89PNG\r\n\x1a\n"[..],
+        &png_chunk(b"IHDR", &[&width.to_be_bytes()[..], &height.to_be_bytes(), &ihdr[8..]].concat()),
+        &shared,
+        &image_data,
+        &png_chunk(b"IEND", &[]),
+      ].concat();
+      let rect = IRect::from_xywh(x as i32, y as i32, width as i32, height as i32);
+      Some((ApngFrame{ png:Data::new_copy(&png), rect, disposal, blend }, 1000.0 * delay_num / delay_den))
+    };
+
+    for (tag, body) in chunks.iter(){
+      match *tag {
+        b"fcTL" => {
+          if let Some((fctl, image_data)) = current.take() {
+            let (frame, duration) = finish(fctl, image_data)?;
+            frames.push(frame);
+            durations.push(duration);
+          }
+          current = Some((body, vec![]));
+        },
+        // the default image is only part of the animation if an fcTL precedes it
+        b"IDAT" => if let Some((_, image_data)) = current.as_mut() {
+          image_data.extend(png_chunk(b"IDAT", body));
+        },
+        b"fdAT" => if let Some((_, image_data)) = current.as_mut() {
+          image_data.extend(png_chunk(b"IDAT", body.get(4..)?));
+        },
+        _ => {}
+      }
+    }
+    if let Some((fctl, image_data)) = current.take() {
+      let (frame, duration) = finish(fctl, image_data)?;
+      frames.push(frame);
+      durations.push(duration);
+    }
+
+    match frames.len() > 1 {
+      true => Some(AnimatedSource{ data:data.clone(), source:FrameSource::Apng{ frames }, durations, loops }),
+      false => None
+    }
+  }
+
+  // composite the frame with any earlier frames it depends on
+  pub fn decode(&self, index:usize) -> Result<SkImage, String>{
+    if index >= self.frame_count() {
+      return Err(format!("Frame {} is out of range (the image has {})", index, self.frame_count()))
+    }
+    match &self.source {
+      FrameSource::Codec{ required } => self.decode_codec(index, required),
+      FrameSource::Apng{ frames } => self.decode_apng(index, frames),
+    }
+  }
+
+  fn decode_codec(&self, index:usize, required:&[Option<usize>]) -> Result<SkImage, String>{
+    let err = || format!("Could not decode frame {}", index);
+    let mut codec = Codec::from_data(self.data.clone()).ok_or_else(err)?;
+    let info = codec.info().with_color_type(ColorType::N32).with_alpha_type(AlphaType::Premul);
+    let row_bytes = info.min_row_bytes();
+
+    let mut chain = vec![index];
+    while let Some(prior) = chain.last().and_then(|idx| required.get(*idx).copied().flatten()) {
+      chain.push(prior);
+    }
+
+    // decode each frame on top of the one before it in the chain
+    let mut pixels = vec![0u8; info.compute_byte_size(row_bytes)];
+    let mut prior_frame = None;
+    for frame_index in chain.into_iter().rev(){
+      let options = CodecOptions{ zero_initialized:ZeroInitialized::No, subset:None, frame_index, prior_frame };
+      match codec.get_pixels_with_options(&info, &mut pixels, row_bytes, Some(&options)) {
+        CodecResult::Success | CodecResult::IncompleteInput => prior_frame = Some(frame_index),
+        _ => return Err(err())
+      }
+    }
+    SkImage::from_raster_data(&info, Data::new_copy(&pixels), row_bytes).ok_or_else(err)
+  }
+
+  fn decode_apng(&self, index:usize, frames:&[ApngFrame]) -> Result<SkImage, String>{
+    let err = || format!("Could not decode frame {}", index);
+    let chunks = png_chunks(self.data.as_bytes())?;
+    let (_, ihdr) = chunks[0];
+    let width = i32::from_be_bytes(ihdr[0..4].try_into().map_err(|_| err())?);
+    let height = i32::from_be_bytes(ihdr[4..8].try_into().map_err(|_| err())?);
+    let mut surface = Surface::new_raster_n32_premul((width, height))
+      .ok_or(format!("Could not allocate new {}×{} bitmap", width, height))?;
+
+    // play the animation through to the requested frame
+    let mut clear = Paint::default();
+    clear.set_blend_mode(BlendMode::Clear);
+    for (idx, frame) in frames.iter().enumerate().take(index + 1){
+      let image = SkImage::from_encoded(frame.png.clone()).ok_or_else(err)?;
+      let rect = Rect::from_irect(frame.rect);
+      let previous = match (idx, frame.disposal) {
+        (0, _) => None, // the first frame can't be disposed to 'previous' (and is treated as 'background')
+        (_, Disposal::Previous) => Some(surface.image_snapshot()),
+        _ => None
+      };
+
+      let canvas = surface.canvas();
+      if !frame.blend {
+        canvas.draw_rect(rect, &clear);
+      }
+      canvas.draw_image(&image, (rect.left, rect.top), None);
+      if idx == index { break }
+
+      match (frame.disposal, previous) {
+        (Disposal::Previous, Some(previous)) => {
+          canvas.save();
+          canvas.clip_rect(rect, ClipOp::Intersect, false);
+          canvas.clear(Color::TRANSPARENT);
+          canvas.draw_image(&previous, (0, 0), None);
+          canvas.restore();
+        },
+        (Disposal::None, _) => {},
+        _ => { canvas.draw_rect(rect, &clear); }
+      }
+    }
+    Ok(surface.image_snapshot())
+  }
+}
+
+//
+// Helpers
+//
+
+pub fn to_disposal(disposal:&str) -> Option<Disposal>{
+  match disposal{
+    "none" => Some(Disposal::None),
+    "background" => Some(Disposal::Background),
+    "previous" => Some(Disposal::Previous),
+    _ => None
+  }
+}
+
+pub fn animation_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Option<Animation>>{
+  let obj = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(None)
+    },
+    None => return Ok(None)
+  };
+
+  let delays:Handle<JsArray> = obj.get(cx, "delays")?;
+  let delays = delays.to_vec(cx)?;
+  let delays = floats_in(cx, &delays);
+  let disposals = strings_at_key(cx, &obj, "disposals")?
+    .iter()
+    .map(|name| to_disposal(name))
+    .collect::<Option<Vec<Disposal>>>();
+  let disposals = match disposals{
+    Some(disposals) => disposals,
+    None => return cx.throw_type_error("Expected \"none\", \"background\", or \"previous\" for disposal")
+  };
+  let loops = opt_float_for_key(cx, &obj, "loop").unwrap_or(0.0).clamp(0.0, u16::MAX as f32) as u16;
+
+  Ok(Some(Animation{ delays, disposals, loops }))
+}
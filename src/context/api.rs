@@ -13,6 +13,9 @@ use skia_safe::textlayout::TextDirection;
 use skia_safe::PaintStyle::{Fill, Stroke};
 
 use super::{Context2D, BoxedContext2D, Dye};
+use super::page::picture_from_data;
+use super::document::TextField;
+use super::structure::Tag;
 use crate::canvas::{Canvas, BoxedCanvas};
 use crate::path::{Path2D, BoxedPath2D};
 use crate::image::{Image, BoxedImage};
@@ -42,6 +45,12 @@ pub fn resetSize(mut cx: FunctionContext) -> JsResult<JsUndefined> {
   Ok(cx.undefined())
 }
 
+pub fn get_layerCount(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let count = this.borrow().get_page().layers.len();
+  Ok(cx.number(count as f64))
+}
+
 pub fn get_size(mut cx: FunctionContext) -> JsResult<JsArray> {
   let this = cx.argument::<BoxedContext2D>(0)?;
   let bounds = this.borrow().bounds;
@@ -719,11 +728,12 @@ fn _layout_rects(width:f32, height:f32, nums:&[f32]) -> Option<(Rect, Rect)> {
 pub fn drawImage(mut cx: FunctionContext) -> JsResult<JsUndefined> {
   let this = cx.argument::<BoxedContext2D>(0)?;
   let source = cx.argument::<JsValue>(1)?;
-  let image = {
+  let (image, picture) = {
     if let Ok(obj) = source.downcast::<BoxedImage, _>(&mut cx){
-      (&obj.borrow().image).clone()
+      let obj = obj.borrow();
+      (obj.image.clone(), obj.picture.clone())
     }else if let Ok(obj) = source.downcast::<BoxedContext2D, _>(&mut cx){
-      obj.borrow().get_image()
+      (obj.borrow().get_image(), None)
     }else{
       return Ok(cx.undefined())
     }
@@ -745,8 +755,13 @@ pub fn drawImage(mut cx: FunctionContext) -> JsResult<JsUndefined> {
       // shrink src to lie within the image bounds and adjust dst proportionately
       let (src, dst) = fit_bounds(width, height, src, dst);
 
+      // vector sources are rendered at the destination's resolution rather than as a bitmap
+      // (and cropped to the source rect just as a bitmap would be)
       let mut this = this.borrow_mut();
-      this.draw_image(&image, &src, &dst);
+      match picture {
+        Some(_) => this.draw_picture(&picture, &src, &dst, true),
+        None => this.draw_image(&image, &src, &dst)
+      }
       Ok(cx.undefined())
     },
     None => cx.throw_error(format!("Expected 2, 4, or 8 coordinates (got {})", nums.len()))
@@ -772,13 +787,27 @@ pub fn drawCanvas(mut cx: FunctionContext) -> JsResult<JsUndefined> {
       };
 
       let mut this = this.borrow_mut();
-      this.draw_picture(&pict, &src, &dst);
+      this.draw_picture(&pict, &src, &dst, false);
       Ok(cx.undefined())
     },
     None => cx.throw_error(format!("Expected 2, 4, or 8 coordinates (got {})", nums.len()))
   }
 }
 
+pub fn loadPicture(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let buffer = cx.argument::<JsBuffer>(1)?;
+  let picture = match picture_from_data(buffer.as_slice(&cx)){
+    Some(picture) => picture,
+    None => return cx.throw_error("Could not decode picture data")
+  };
+
+  let mut this = this.borrow_mut();
+  this.load_picture(picture);
+  let size = this.bounds.size();
+  floats_to_array(&mut cx, &[size.width, size.height])
+}
+
 pub fn getImageData(mut cx: FunctionContext) -> JsResult<JsBuffer> {
   let this = cx.argument::<BoxedContext2D>(0)?;
   let mut this = this.borrow_mut();
@@ -1174,3 +1203,68 @@ pub fn set_shadowOffsetY(mut cx: FunctionContext) -> JsResult<JsUndefined> {
   }
   Ok(cx.undefined())
 }
+
+//
+// Document Annotations
+//
+
+pub fn addLink(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let mut this = this.borrow_mut();
+  let url = string_arg(&mut cx, 1, "url")?;
+  let nums = float_args(&mut cx, 2..6)?;
+  if let [x, y, w, h] = nums.as_slice() {
+    let rect = Rect::from_xywh(*x, *y, *w, *h);
+    this.add_link(&rect, &url);
+  }
+  Ok(cx.undefined())
+}
+
+pub fn addTextField(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let mut this = this.borrow_mut();
+  let name = string_arg(&mut cx, 1, "name")?;
+  let nums = float_args(&mut cx, 2..6)?;
+  let value = opt_string_arg(&mut cx, 6).unwrap_or_default();
+  let multiline = bool_arg_or(&mut cx, 7, false);
+  let max_length = opt_float_arg(&mut cx, 8).map(|len| len.max(0.0) as u32);
+  let font_size = float_arg_or(&mut cx, 9, 0.0).max(0.0);
+  if let [x, y, w, h] = nums.as_slice() {
+    let rect = Rect::from_xywh(*x, *y, *w, *h);
+    this.add_text_field(&rect, TextField{ name, value, multiline, max_length, font_size });
+  }
+  Ok(cx.undefined())
+}
+
+pub fn beginTag(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let mut this = this.borrow_mut();
+  let kind = string_arg(&mut cx, 1, "type")?;
+  let alt = opt_string_arg(&mut cx, 2);
+  let lang = opt_string_arg(&mut cx, 3);
+  match Tag::new(&kind, alt, lang) {
+    Ok(tag) => this.begin_tag(tag),
+    Err(msg) => return cx.throw_type_error(msg)
+  }
+  Ok(cx.undefined())
+}
+
+pub fn endTag(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let mut this = this.borrow_mut();
+  if !this.end_tag() {
+    return cx.throw_error("endTag() was called without a matching beginTag()")
+  }
+  Ok(cx.undefined())
+}
+
+pub fn addDestination(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedContext2D>(0)?;
+  let mut this = this.borrow_mut();
+  let name = string_arg(&mut cx, 1, "name")?;
+  let nums = float_args(&mut cx, 2..4)?;
+  if let [x, y] = nums.as_slice() {
+    this.add_destination(&name, Point::new(*x, *y));
+  }
+  Ok(cx.undefined())
+}
@@ -0,0 +1,1046 @@
+use std::collections::HashMap;
+use neon::{prelude::*, types::buffer::TypedArray};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use skia_safe::{pdf, Data, DateTime, Rect, Size};
+
+use crate::utils::*;
+
+//
+// Document-level metadata (written to the PDF's Info dictionary)
+//
+
+#[derive(Debug, Clone, Default)]
+pub struct DocumentInfo{
+  pub title: String,
+  pub author: String,
+  pub subject: String,
+  pub keywords: String,
+  pub creator: String,
+  pub creation: Option<DateTime>,
+  pub modified: Option<DateTime>,
+}
+
+impl DocumentInfo{
+  pub fn apply_to(&self, meta:&mut pdf::Metadata){
+    meta.title = self.title.clone();
+    meta.author = self.author.clone();
+    meta.subject = self.subject.clone();
+    meta.keywords = self.keywords.clone();
+    meta.creator = self.creator.clone();
+    meta.creation = self.creation;
+    meta.modified = self.modified;
+  }
+}
+
+//
+// Outline entries (a.k.a. bookmarks) pointing to a page or a named destination
+//
+
+#[derive(Debug, Clone)]
+pub enum Target{
+  Page(usize),
+  Named(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Bookmark{
+  pub title: String,
+  pub target: Target,
+  pub children: Vec<Bookmark>,
+}
+
+//
+// Per-page geometry (the finished ‘trim’ size of the sheet plus any bleed area printed beyond it)
+//
+
+#[derive(Debug, Clone, Default)]
+pub struct PageSetup{
+  pub size: Option<Size>,  // defaults to the page's own dimensions
+  pub bleed: f32,
+  pub crop_to_trim: bool,  // whether viewers should hide the bleed area
+}
+
+// the boxes to add to a page's dictionary (in PDF coordinates, with the origin at the bottom left)
+#[derive(Debug, Clone)]
+pub struct PageBoxes{
+  pub trim: Rect,
+  pub bleed: Rect,
+  pub crop: Option<Rect>,
+}
+
+impl PageSetup{
+  fn trim_size(&self, bounds:&Rect) -> Size {
+    self.size.unwrap_or_else(|| bounds.size())
+  }
+
+  pub fn media_size(&self, bounds:&Rect) -> Size {
+    let trim = self.trim_size(bounds);
+    Size::new(trim.width + 2.0 * self.bleed, trim.height + 2.0 * self.bleed)
+  }
+
+  // the page's content is drawn at its natural size, centered on the sheet
+  pub fn content_rect(&self, bounds:&Rect) -> Rect {
+    let media = self.media_size(bounds);
+    Rect::from_xywh((media.width - bounds.width()) / 2.0, (media.height - bounds.height()) / 2.0, bounds.width(), bounds.height())
+  }
+
+  // maps a rect on the page to its position on the sheet (in PDF coordinates, with the origin at the bottom left)
+  pub fn sheet_rect(&self, bounds:&Rect, rect:&Rect) -> Rect {
+    let (media, content) = (self.media_size(bounds), self.content_rect(bounds));
+    Rect::from_ltrb(
+      content.left + rect.left, media.height - content.top - rect.bottom,
+      content.left + rect.right, media.height - content.top - rect.top
+    )
+  }
+
+  pub fn boxes(&self, bounds:&Rect) -> PageBoxes {
+    let trim = Rect::from_point_and_size((self.bleed, self.bleed), self.trim_size(bounds));
+    let bleed = Rect::from_size(self.media_size(bounds));
+    let crop = match self.crop_to_trim {
+      true => Some(trim),
+      false => None
+    };
+    PageBoxes{ trim, bleed, crop }
+  }
+}
+
+//
+// Page numbering styles (e.g., for roman-numeraled front matter) shown by viewers instead of the page index
+//
+
+#[derive(Debug, Clone)]
+pub struct PageLabel{
+  pub page: usize,            // 0-based index of the first page in the range
+  pub style: Option<String>,  // one of the PDF numbering styles (D, r, R, a, or A) or None for just the prefix
+  pub prefix: String,
+  pub start: u32,
+}
+
+//
+// Files embedded in the document (e.g., the structured data behind a ZUGFeRD/Factur-X invoice)
+//
+
+#[derive(Debug, Clone)]
+pub struct Attachment{
+  pub name: String,
+  pub data: Vec<u8>,
+  pub mime_type: Option<String>,
+  pub description: Option<String>,
+  pub relationship: String,  // the AFRelationship value (Source, Data, Alternative, Supplement, or Unspecified)
+}
+
+//
+// Fillable text fields (kept alongside a page's layers since they're added to the PDF once Skia has written it)
+//
+
+#[derive(Debug, Clone)]
+pub struct TextField{
+  pub name: String,
+  pub value: String,
+  pub multiline: bool,
+  pub max_length: Option<u32>,
+  pub font_size: f32,
+}
+
+// a field's location (with the context's transform applied when it was added) and the layer it belongs to
+#[derive(Debug, Clone)]
+pub struct PlacedField{
+  pub field: TextField,
+  pub rect: Rect,
+  pub layer: usize,
+}
+
+impl TextField{
+  fn as_widget(&self, rect:&Rect, page_id:u32) -> PdfObject{
+    let multiline = match self.multiline { true => 1 << 12, false => 0 };
+    let mut widget = PdfObject::dict(vec![
+      ("Type", PdfObject::name("Annot")),
+      ("Subtype", PdfObject::name("Widget")),
+      ("FT", PdfObject::name("Tx")),
+      ("T", PdfObject::text(&self.name)),
+      ("V", PdfObject::text(&self.value)),
+      ("DV", PdfObject::text(&self.value)),
+      ("Rect", rect_array(rect)),
+      ("P", PdfObject::Ref(page_id, 0)),
+      ("F", PdfObject::num(4.0)),  // print the field along with the page
+      ("Ff", PdfObject::num(multiline as f64)),
+      ("DA", PdfObject::text(&format!("/Helv {} Tf 0 g", self.font_size))),
+    ]);
+    if let Some(max_length) = self.max_length {
+      widget.set("MaxLen", PdfObject::num(max_length as f64));
+    }
+    widget
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PdfOptions{
+  pub info: DocumentInfo,
+  pub bookmarks: Vec<Bookmark>,
+  pub pdfa: bool,
+  pub labels: Vec<PageLabel>,
+  pub attachments: Vec<Attachment>,
+}
+
+impl PdfOptions{
+  pub fn new(info:DocumentInfo, bookmarks:Vec<Bookmark>, pdfa:bool) -> Self{
+    PdfOptions{ info, bookmarks, pdfa, ..Default::default() }
+  }
+
+  pub fn with_page_labels(self, labels:Vec<PageLabel>) -> Self{
+    PdfOptions{ labels, ..self }
+  }
+
+  pub fn with_attachments(self, attachments:Vec<Attachment>) -> Self{
+    PdfOptions{ attachments, ..self }
+  }
+
+  // add any features that need to be spliced into the file after Skia has finished writing it (`fields` lists
+  // each page's text fields with their rects already mapped to the sheet by `PageSetup::sheet_rect`)
+  pub fn finish(&self, data:Data, boxes:&[Option<PageBoxes>], fields:&[Vec<PlacedField>]) -> Result<Data, String>{
+    let has_fields = fields.iter().any(|page| !page.is_empty());
+    if self.bookmarks.is_empty() && self.labels.is_empty() && self.attachments.is_empty()
+    && boxes.iter().all(Option::is_none) && !has_fields {
+      return Ok(data)
+    }
+
+    let mut doc = PdfEditor::new(data.as_bytes())?;
+    if !self.bookmarks.is_empty(){
+      add_outline(&mut doc, &self.bookmarks)?;
+    }
+    if !self.labels.is_empty(){
+      add_page_labels(&mut doc, &self.labels)?;
+    }
+    if !self.attachments.is_empty(){
+      add_attachments(&mut doc, &self.attachments, self.pdfa)?;
+      if self.pdfa {
+        // Skia identifies its archival output as PDF/A-2b, but only PDF/A-3 allows embedded files
+        set_pdfa_part(&mut doc, 3)?;
+      }
+    }
+    if has_fields{
+      add_form_fields(&mut doc, fields)?;
+    }
+    add_page_boxes(&mut doc, boxes)?;
+    Ok(doc.finish())
+  }
+}
+
+fn add_page_labels(doc:&mut PdfEditor, labels:&[PageLabel]) -> Result<(), String>{
+  let count = doc.pages()?.len();
+  let mut nums = vec![];
+  for label in labels{
+    if label.page >= count {
+      return Err(format!("Page label refers to page {} but the document has {}", label.page + 1, count))
+    }
+    let mut entry = PdfObject::dict(vec![]);
+    if let Some(style) = &label.style {
+      entry.set("S", PdfObject::name(style));
+    }
+    if !label.prefix.is_empty(){
+      entry.set("P", PdfObject::text(&label.prefix));
+    }
+    if label.start != 1 {
+      entry.set("St", PdfObject::num(label.start as f64));
+    }
+    nums.push(PdfObject::num(label.page as f64));
+    nums.push(entry);
+  }
+
+  let mut catalog = doc.catalog()?;
+  catalog.set("PageLabels", PdfObject::dict(vec![("Nums", PdfObject::Array(nums))]));
+  doc.set(doc.root()?, catalog);
+  Ok(())
+}
+
+fn add_attachments(doc:&mut PdfEditor, attachments:&[Attachment], pdfa:bool) -> Result<(), String>{
+  // entries in the EmbeddedFiles name tree need to be sorted by name
+  let mut attachments = attachments.iter().collect::<Vec<_>>();
+  attachments.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let (mut names, mut specs) = (vec![], vec![]);
+  for file in attachments{
+    let compressed = compress_to_vec_zlib(&file.data, 6);
+    let mut info = PdfObject::dict(vec![
+      ("Type", PdfObject::name("EmbeddedFile")),
+      ("Filter", PdfObject::name("FlateDecode")),
+      ("Length", PdfObject::num(compressed.len() as f64)),
+      ("Params", PdfObject::dict(vec![("Size", PdfObject::num(file.data.len() as f64))])),
+    ]);
+    // PDF/A-3 requires every embedded file to declare its type
+    match &file.mime_type {
+      Some(mime_type) => info.set("Subtype", PdfObject::name(mime_type)),
+      None if pdfa => info.set("Subtype", PdfObject::name("application/octet-stream")),
+      None => {}
+    }
+    let stream_id = doc.reserve();
+    doc.set(stream_id, PdfObject::Stream(Box::new(info), compressed));
+
+    let mut spec = PdfObject::dict(vec![
+      ("Type", PdfObject::name("Filespec")),
+      ("F", PdfObject::text(&file.name)),
+      ("UF", PdfObject::text(&file.name)),
+      ("EF", PdfObject::dict(vec![("F", PdfObject::Ref(stream_id, 0)), ("UF", PdfObject::Ref(stream_id, 0))])),
+      ("AFRelationship", PdfObject::name(&file.relationship)),
+    ]);
+    if let Some(description) = &file.description {
+      spec.set("Desc", PdfObject::text(description));
+    }
+    let spec_id = doc.reserve();
+    doc.set(spec_id, spec);
+
+    names.push(PdfObject::text(&file.name));
+    names.push(PdfObject::Ref(spec_id, 0));
+    specs.push(PdfObject::Ref(spec_id, 0));
+  }
+
+  // add to the catalog's name dictionary (which may be stored separately or inline)
+  let mut catalog = doc.catalog()?;
+  let embedded = PdfObject::dict(vec![("Names", PdfObject::Array(names))]);
+  match catalog.get("Names").cloned() {
+    Some(PdfObject::Ref(id, _)) => {
+      let mut tree = doc.get(id)?;
+      tree.set("EmbeddedFiles", embedded);
+      doc.set(id, tree);
+    },
+    Some(mut tree) => {
+      tree.set("EmbeddedFiles", embedded);
+      catalog.set("Names", tree);
+    },
+    None => catalog.set("Names", PdfObject::dict(vec![("EmbeddedFiles", embedded)]))
+  }
+  catalog.set("AF", PdfObject::Array(specs));
+  doc.set(doc.root()?, catalog);
+  Ok(())
+}
+
+// rewrites the conformance level in the PDF/A identification schema of the document's XMP metadata
+fn set_pdfa_part(doc:&mut PdfEditor, part:u32) -> Result<(), String>{
+  let metadata_id = doc.catalog()?.get("Metadata").and_then(|r| r.as_ref()).ok_or("PDF/A document is missing its XMP metadata")?;
+  let (mut dict, data) = doc.get_stream(metadata_id)?;
+  let xmp = String::from_utf8(data).map_err(|_| "Could not parse the document's XMP metadata")?;
+  let (start, end) = match (xmp.find("<pdfaid:part>"), xmp.find("</pdfaid:part>")) {
+    (Some(start), Some(end)) if dict.get("Filter").is_none() && start < end => (start + "<pdfaid:part>".len(), end),
+    _ => return Err("Could not locate the PDF/A identification in the document's XMP metadata".to_string())
+  };
+  let xmp = format!("{}{}{}", &xmp[..start], part, &xmp[end..]);
+  dict.set("Length", PdfObject::num(xmp.len() as f64));
+  doc.set(metadata_id, PdfObject::Stream(Box::new(dict), xmp.into_bytes()));
+  Ok(())
+}
+
+// adds a widget annotation for each of the pages' text fields and lists them all in the document's AcroForm
+fn add_form_fields(doc:&mut PdfEditor, fields:&[Vec<PlacedField>]) -> Result<(), String>{
+  let mut form = vec![];
+  for (page_id, fields) in doc.pages()?.into_iter().zip(fields){
+    if fields.is_empty(){
+      continue
+    }
+
+    // widgets must be indirect objects so the form's field list can refer to them
+    let mut widgets = vec![];
+    for PlacedField{ field, rect, .. } in fields{
+      let id = doc.reserve();
+      doc.set(id, field.as_widget(rect, page_id));
+      widgets.push(PdfObject::Ref(id, 0));
+    }
+    form.extend(widgets.iter().cloned());
+
+    // add them to the page's annotations (which may be stored separately or inline)
+    let mut page = doc.get(page_id)?;
+    match page.get("Annots").cloned() {
+      Some(PdfObject::Ref(id, _)) => match doc.get(id)? {
+        PdfObject::Array(mut annots) => {
+          annots.extend(widgets);
+          doc.set(id, PdfObject::Array(annots));
+        },
+        _ => return Err(format!("PDF object {} is not an annotation array", id))
+      },
+      Some(PdfObject::Array(mut annots)) => {
+        annots.extend(widgets);
+        page.set("Annots", PdfObject::Array(annots));
+        doc.set(page_id, page);
+      },
+      _ => {
+        page.set("Annots", PdfObject::Array(widgets));
+        doc.set(page_id, page);
+      }
+    }
+  }
+
+  let font_id = doc.reserve();
+  doc.set(font_id, PdfObject::dict(vec![
+    ("Type", PdfObject::name("Font")),
+    ("Subtype", PdfObject::name("Type1")),
+    ("BaseFont", PdfObject::name("Helvetica")),
+    ("Encoding", PdfObject::name("WinAnsiEncoding")),
+  ]));
+
+  // let the viewer generate the fields' appearances (since the values are drawn with its own copy of Helvetica)
+  let mut catalog = doc.catalog()?;
+  catalog.set("AcroForm", PdfObject::dict(vec![
+    ("Fields", PdfObject::Array(form)),
+    ("NeedAppearances", PdfObject::Bool(true)),
+    ("DA", PdfObject::text("/Helv 0 Tf 0 g")),
+    ("DR", PdfObject::dict(vec![("Font", PdfObject::dict(vec![("Helv", PdfObject::Ref(font_id, 0))]))])),
+  ]));
+  doc.set(doc.root()?, catalog);
+  Ok(())
+}
+
+fn rect_array(rect:&Rect) -> PdfObject{
+  PdfObject::Array([rect.left, rect.top, rect.right, rect.bottom].iter().map(|n| PdfObject::num(*n as f64)).collect())
+}
+
+fn add_page_boxes(doc:&mut PdfEditor, boxes:&[Option<PageBoxes>]) -> Result<(), String>{
+  for (page_id, boxes) in doc.pages()?.into_iter().zip(boxes){
+    if let Some(PageBoxes{trim, bleed, crop}) = boxes {
+      let mut page = doc.get(page_id)?;
+      page.set("TrimBox", rect_array(trim));
+      page.set("BleedBox", rect_array(bleed));
+      if let Some(crop) = crop {
+        page.set("CropBox", rect_array(crop));
+      }
+      doc.set(page_id, page);
+    }
+  }
+  Ok(())
+}
+
+fn add_outline(doc:&mut PdfEditor, bookmarks:&[Bookmark]) -> Result<(), String>{
+  let pages = doc.pages()?;
+  let outline_id = doc.reserve();
+  let (first, last, count) = add_outline_items(doc, bookmarks, outline_id, &pages)?;
+  doc.set(outline_id, PdfObject::dict(vec![
+    ("Type", PdfObject::name("Outlines")),
+    ("First", PdfObject::Ref(first, 0)),
+    ("Last", PdfObject::Ref(last, 0)),
+    ("Count", PdfObject::num(count as f64)),
+  ]));
+
+  let mut catalog = doc.catalog()?;
+  catalog.set("Outlines", PdfObject::Ref(outline_id, 0));
+  catalog.set("PageMode", PdfObject::name("UseOutlines"));
+  doc.set(doc.root()?, catalog);
+  Ok(())
+}
+
+fn add_outline_items(doc:&mut PdfEditor, items:&[Bookmark], parent:u32, pages:&[u32]) -> Result<(u32, u32, usize), String>{
+  let ids:Vec<u32> = items.iter().map(|_| doc.reserve()).collect();
+  let mut count = items.len();
+
+  for (i, item) in items.iter().enumerate(){
+    let dest = match &item.target{
+      Target::Named(name) => PdfObject::name(name),
+      Target::Page(idx) => match pages.get(*idx){
+        Some(page_id) => PdfObject::Array(vec![PdfObject::Ref(*page_id, 0), PdfObject::name("Fit")]),
+        None => return Err(format!(
+          "Bookmark \"{}\" refers to page {} but the document has {}", item.title, idx + 1, pages.len()
+        ))
+      }
+    };
+
+    let mut entry = PdfObject::dict(vec![
+      ("Title", PdfObject::text(&item.title)),
+      ("Parent", PdfObject::Ref(parent, 0)),
+      ("Dest", dest),
+    ]);
+    if i > 0 {
+      entry.set("Prev", PdfObject::Ref(ids[i-1], 0));
+    }
+    if i + 1 < ids.len() {
+      entry.set("Next", PdfObject::Ref(ids[i+1], 0));
+    }
+    if !item.children.is_empty(){
+      let (first, last, n) = add_outline_items(doc, &item.children, ids[i], pages)?;
+      entry.set("First", PdfObject::Ref(first, 0));
+      entry.set("Last", PdfObject::Ref(last, 0));
+      entry.set("Count", PdfObject::num(n as f64));
+      count += n;
+    }
+    doc.set(ids[i], entry);
+  }
+
+  Ok((ids[0], ids[ids.len()-1], count))
+}
+
+//
+// Minimal PDF object model (just enough to read Skia's output and write it back out with modifications)
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PdfObject{
+  Null,
+  Bool(bool),
+  Number(String),
+  Name(String),
+  Str(Vec<u8>),  // the raw (still-escaped) contents of a literal string
+  Hex(String),
+  Array(Vec<PdfObject>),
+  Dict(Vec<(String, PdfObject)>),
+  Stream(Box<PdfObject>, Vec<u8>),  // a dictionary followed by its (already encoded) data
+  Ref(u32, u16),
+}
+
+impl PdfObject{
+  pub fn num(n:f64) -> Self{
+    let repr = match n.fract() == 0.0 {
+      true => format!("{}", n as i64),
+      false => format!("{:.4}", n).trim_end_matches('0').to_string()
+    };
+    PdfObject::Number(repr)
+  }
+
+  pub fn name(name:&str) -> Self{
+    let escaped = name.bytes().map(|c| match c {
+      b'!'..=b'~' if !b"#/%()<>[]{}".contains(&c) => (c as char).to_string(),
+      _ => format!("#{:02X}", c)
+    }).collect();
+    PdfObject::Name(escaped)
+  }
+
+  pub fn text(text:&str) -> Self{
+    match text.chars().all(|c| (' '..='~').contains(&c)) {
+      true => {
+        let mut raw = vec![];
+        for c in text.bytes(){
+          if c == b'\\' || c == b'(' || c == b')' { raw.push(b'\\') }
+          raw.push(c);
+        }
+        PdfObject::Str(raw)
+      },
+      // anything beyond printable ascii is stored as big-endian UTF-16 (with a byte order mark)
+      false => {
+        let hex = text.encode_utf16().map(|c| format!("{:04X}", c)).collect::<String>();
+        PdfObject::Hex(format!("FEFF{}", hex))
+      }
+    }
+  }
+
+  pub fn dict(entries:Vec<(&str, PdfObject)>) -> Self{
+    PdfObject::Dict(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+  }
+
+  pub fn get(&self, key:&str) -> Option<&PdfObject>{
+    match self{
+      PdfObject::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None
+    }
+  }
+
+  pub fn set(&mut self, key:&str, val:PdfObject){
+    if let PdfObject::Dict(entries) = self{
+      match entries.iter_mut().find(|(k, _)| k == key){
+        Some(entry) => entry.1 = val,
+        None => entries.push((key.to_string(), val))
+      }
+    }
+  }
+
+  pub fn as_ref(&self) -> Option<u32>{
+    match self{ PdfObject::Ref(id, _) => Some(*id), _ => None }
+  }
+
+  pub fn as_array(&self) -> Option<&[PdfObject]>{
+    match self{ PdfObject::Array(items) => Some(items), _ => None }
+  }
+
+  pub fn as_name(&self) -> Option<&str>{
+    match self{ PdfObject::Name(name) => Some(name), _ => None }
+  }
+
+  pub fn as_num(&self) -> Option<f64>{
+    match self{ PdfObject::Number(repr) => repr.parse().ok(), _ => None }
+  }
+
+  pub fn write(&self, out:&mut Vec<u8>){
+    match self{
+      PdfObject::Null => out.extend(b"null"),
+      PdfObject::Bool(flag) => out.extend(flag.to_string().as_bytes()),
+      PdfObject::Number(repr) => out.extend(repr.as_bytes()),
+      PdfObject::Name(name) => { out.push(b'/'); out.extend(name.as_bytes()) },
+      PdfObject::Str(raw) => { out.push(b'('); out.extend(raw); out.push(b')') },
+      PdfObject::Hex(hex) => { out.push(b'<'); out.extend(hex.as_bytes()); out.push(b'>') },
+      PdfObject::Ref(id, gen) => out.extend(format!("{} {} R", id, gen).as_bytes()),
+      PdfObject::Array(items) => {
+        out.push(b'[');
+        for (i, item) in items.iter().enumerate(){
+          if i > 0 { out.push(b' ') }
+          item.write(out);
+        }
+        out.push(b']');
+      },
+      PdfObject::Dict(entries) => {
+        out.extend(b"<<");
+        for (i, (key, val)) in entries.iter().enumerate(){
+          if i > 0 { out.push(b'\n') }
+          out.push(b'/');
+          out.extend(key.as_bytes());
+          out.push(b' ');
+          val.write(out);
+        }
+        out.extend(b">>");
+      },
+      PdfObject::Stream(dict, data) => {
+        dict.write(out);
+        out.extend(b"\nstream\n");
+        out.extend(data);
+        out.extend(b"\nendstream");
+      }
+    }
+  }
+}
+
+struct Parser<'a>{
+  buf: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Parser<'a>{
+  fn new(buf:&'a [u8], pos:usize) -> Self{
+    Parser{ buf, pos }
+  }
+
+  fn peek(&self) -> Option<u8>{
+    self.buf.get(self.pos).copied()
+  }
+
+  fn skip_whitespace(&mut self){
+    while let Some(c) = self.peek(){
+      match c {
+        b'%' => while !matches!(self.peek(), None | Some(b'\r') | Some(b'\n')) { self.pos += 1 },
+        b' ' | b'\t' | b'\r' | b'\n' | b'\x0C' | b'\0' => self.pos += 1,
+        _ => break
+      }
+    }
+  }
+
+  fn token(&mut self) -> &'a [u8]{
+    self.skip_whitespace();
+    let start = self.pos;
+    while let Some(c) = self.peek(){
+      if b" \t\r\n\x0C\0()<>[]{}/%".contains(&c) { break }
+      self.pos += 1;
+    }
+    &self.buf[start..self.pos]
+  }
+
+  fn integer(&mut self) -> Result<usize, String>{
+    let token = self.token();
+    std::str::from_utf8(token).ok()
+      .and_then(|s| s.parse().ok())
+      .ok_or(format!("Expected an integer at byte {} of PDF", self.pos))
+  }
+
+  fn keyword(&mut self, word:&str) -> Result<(), String>{
+    match self.token() == word.as_bytes() {
+      true => Ok(()),
+      false => Err(format!("Expected `{}` at byte {} of PDF", word, self.pos))
+    }
+  }
+
+  fn object(&mut self) -> Result<PdfObject, String>{
+    self.skip_whitespace();
+    match self.peek(){
+      Some(b'<') if self.buf.get(self.pos + 1) == Some(&b'<') => {
+        self.pos += 2;
+        let mut entries = vec![];
+        loop {
+          self.skip_whitespace();
+          if self.buf[self.pos..].starts_with(b">>"){
+            self.pos += 2;
+            break Ok(PdfObject::Dict(entries))
+          }
+          let key = match self.object()? {
+            PdfObject::Name(key) => key,
+            _ => break Err(format!("Expected a dictionary key at byte {} of PDF", self.pos))
+          };
+          entries.push((key, self.object()?));
+        }
+      },
+      Some(b'<') => {
+        let start = self.pos + 1;
+        while !matches!(self.peek(), None | Some(b'>')) { self.pos += 1 }
+        self.pos += 1;
+        Ok(PdfObject::Hex(String::from_utf8_lossy(&self.buf[start..self.pos-1]).to_string()))
+      },
+      Some(b'[') => {
+        self.pos += 1;
+        let mut items = vec![];
+        loop {
+          self.skip_whitespace();
+          if self.peek() == Some(b']'){
+            self.pos += 1;
+            break Ok(PdfObject::Array(items))
+          }
+          items.push(self.object()?);
+        }
+      },
+      Some(b'(') => {
+        let start = self.pos + 1;
+        let mut depth = 0;
+        while let Some(c) = self.peek(){
+          self.pos += 1;
+          match c {
+            b'\\' => self.pos += 1,
+            b'(' => depth += 1,
+            b')' => { depth -= 1; if depth == 0 { break } },
+            _ => {}
+          }
+        }
+        Ok(PdfObject::Str(self.buf[start..self.pos-1].to_vec()))
+      },
+      Some(b'/') => {
+        self.pos += 1;
+        let name = self.token();
+        Ok(PdfObject::Name(String::from_utf8_lossy(name).to_string()))
+      },
+      Some(_) => {
+        let token = String::from_utf8_lossy(self.token()).to_string();
+        match token.as_str(){
+          "" => Err(format!("Unexpected character at byte {} of PDF", self.pos)),
+          "null" => Ok(PdfObject::Null),
+          "true" => Ok(PdfObject::Bool(true)),
+          "false" => Ok(PdfObject::Bool(false)),
+          _ => {
+            // look ahead for an `id gen R` indirect reference
+            let mark = self.pos;
+            if let Ok(id) = token.parse::<u32>(){
+              if let Ok(gen) = self.integer(){
+                if self.token() == b"R" {
+                  return Ok(PdfObject::Ref(id, gen as u16))
+                }
+              }
+            }
+            self.pos = mark;
+            Ok(PdfObject::Number(token))
+          }
+        }
+      },
+      None => Err("Unexpected end of PDF data".to_string())
+    }
+  }
+}
+
+pub struct PdfEditor{
+  bytes: Vec<u8>,
+  offsets: HashMap<u32, usize>,
+  trailer: PdfObject,
+  startxref: usize,
+  next_id: u32,
+  updates: Vec<(u32, PdfObject)>,
+}
+
+impl PdfEditor{
+  pub fn new(data:&[u8]) -> Result<Self, String>{
+    let startxref = data.windows(9).rposition(|w| w == b"startxref").ok_or("Could not locate PDF cross-reference table")?;
+    let startxref = Parser::new(data, startxref + 9).integer()?;
+
+    let mut parser = Parser::new(data, startxref);
+    let mut offsets = HashMap::new();
+    parser.keyword("xref")?;
+    loop {
+      parser.skip_whitespace();
+      if data[parser.pos..].starts_with(b"trailer"){
+        parser.keyword("trailer")?;
+        break
+      }
+      let (first, count) = (parser.integer()?, parser.integer()?);
+      for id in first..first+count{
+        let (offset, _gen) = (parser.integer()?, parser.integer()?);
+        if parser.token() == b"n" {
+          offsets.insert(id as u32, offset);
+        }
+      }
+    }
+
+    let trailer = parser.object()?;
+    let next_id = trailer.get("Size").and_then(|n| n.as_num()).ok_or("PDF trailer is missing its /Size")? as u32;
+    Ok(PdfEditor{ bytes:data.to_vec(), offsets, trailer, startxref, next_id, updates:vec![] })
+  }
+
+  pub fn get(&self, id:u32) -> Result<PdfObject, String>{
+    if let Some((_, obj)) = self.updates.iter().find(|(n, _)| *n == id){
+      return Ok(obj.clone())
+    }
+    let offset = self.offsets.get(&id).ok_or(format!("PDF object {} not found", id))?;
+    let mut parser = Parser::new(&self.bytes, *offset);
+    parser.integer()?;
+    parser.integer()?;
+    parser.keyword("obj")?;
+    parser.object()
+  }
+
+  // a stream object's dictionary along with its (still encoded) data
+  pub fn get_stream(&self, id:u32) -> Result<(PdfObject, Vec<u8>), String>{
+    if let Some((_, PdfObject::Stream(dict, data))) = self.updates.iter().find(|(n, _)| *n == id){
+      return Ok((*dict.clone(), data.clone()))
+    }
+    let offset = self.offsets.get(&id).ok_or(format!("PDF object {} not found", id))?;
+    let mut parser = Parser::new(&self.bytes, *offset);
+    parser.integer()?;
+    parser.integer()?;
+    parser.keyword("obj")?;
+    let dict = parser.object()?;
+    let length = match dict.get("Length") {
+      Some(PdfObject::Ref(id, _)) => self.get(*id)?.as_num(),
+      Some(length) => length.as_num(),
+      None => None
+    }.ok_or(format!("PDF stream {} is missing its /Length", id))? as usize;
+
+    // the data begins after the end-of-line marker that follows the `stream` keyword
+    parser.keyword("stream")?;
+    let mut start = parser.pos;
+    if self.bytes.get(start) == Some(&b'\r') { start += 1 }
+    if self.bytes.get(start) == Some(&b'\n') { start += 1 }
+    match self.bytes.get(start..start + length) {
+      Some(data) => Ok((dict, data.to_vec())),
+      None => Err(format!("PDF stream {} is truncated", id))
+    }
+  }
+
+  pub fn root(&self) -> Result<u32, String>{
+    self.trailer.get("Root").and_then(|r| r.as_ref()).ok_or("PDF trailer is missing its /Root".to_string())
+  }
+
+  pub fn catalog(&self) -> Result<PdfObject, String>{
+    self.get(self.root()?)
+  }
+
+  // the object ids of every page (in reading order)
+  pub fn pages(&self) -> Result<Vec<u32>, String>{
+    let tree = self.catalog()?.get("Pages").and_then(|r| r.as_ref()).ok_or("PDF catalog is missing its /Pages")?;
+    let mut pages = vec![];
+    self.collect_pages(tree, &mut pages)?;
+    Ok(pages)
+  }
+
+  fn collect_pages(&self, id:u32, pages:&mut Vec<u32>) -> Result<(), String>{
+    let node = self.get(id)?;
+    match node.get("Type").and_then(|t| t.as_name()){
+      Some("Pages") => {
+        let kids = node.get("Kids").and_then(|k| k.as_array()).unwrap_or(&[]);
+        for kid in kids.iter().filter_map(|k| k.as_ref()){
+          self.collect_pages(kid, pages)?;
+        }
+      },
+      _ => pages.push(id)
+    }
+    Ok(())
+  }
+
+  pub fn reserve(&mut self) -> u32{
+    self.next_id += 1;
+    self.next_id - 1
+  }
+
+  pub fn set(&mut self, id:u32, obj:PdfObject){
+    self.updates.retain(|(n, _)| *n != id);
+    self.updates.push((id, obj));
+  }
+
+  // write the file out again with the new & modified objects in place of the originals (rather than appending
+  // them as an incremental update, which would leave the superseded objects in the file)
+  pub fn finish(self) -> Data{
+    let PdfEditor{ bytes, offsets, mut trailer, updates, startxref, next_id } = self;
+    if updates.is_empty(){
+      return Data::new_copy(&bytes)
+    }
+
+    // each of the original objects extends up to the start of the next one (or the cross-reference table)
+    let mut starts:Vec<usize> = offsets.values().copied().chain([startxref]).collect();
+    starts.sort_unstable();
+    let updates:HashMap<u32, PdfObject> = updates.into_iter().collect();
+    let mut ids:Vec<u32> = offsets.keys().chain(updates.keys()).copied().collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    // keep the original header (the version & binary marker comments)
+    let mut output = bytes[..starts[0]].to_vec();
+    let mut xref = vec![];
+    for id in ids{
+      xref.push((id, output.len()));
+      match updates.get(&id) {
+        Some(obj) => {
+          output.extend(format!("{} 0 obj\n", id).as_bytes());
+          obj.write(&mut output);
+          output.extend(b"\nendobj\n");
+        },
+        None => {
+          let start = offsets[&id];
+          let end = starts[starts.partition_point(|pos| *pos <= start)];
+          output.extend(&bytes[start..end]);
+        }
+      }
+    }
+
+    // group consecutive ids into subsections (following the free-list head for object 0)
+    let xref_start = output.len();
+    output.extend(b"xref\n0 1\n0000000000 65535 f \n");
+    let mut start = 0;
+    while start < xref.len(){
+      let mut end = start + 1;
+      while end < xref.len() && xref[end].0 == xref[end-1].0 + 1 { end += 1 }
+      output.extend(format!("{} {}\n", xref[start].0, end - start).as_bytes());
+      for (_, offset) in &xref[start..end]{
+        output.extend(format!("{:010} 00000 n \n", offset).as_bytes());
+      }
+      start = end;
+    }
+
+    trailer.set("Size", PdfObject::num(next_id as f64));
+    output.extend(b"trailer\n");
+    trailer.write(&mut output);
+    output.extend(format!("\nstartxref\n{}\n%%EOF", xref_start).as_bytes());
+    Data::new_copy(&output)
+  }
+}
+
+//
+// Helpers
+//
+
+fn date_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> NeonResult<Option<DateTime>>{
+  let key = cx.string(attr);
+  let val = obj.get_value(cx, key)?;
+  let parts = match val.downcast::<JsArray, _>(cx){
+    Ok(array) => array.to_vec(cx)?,
+    Err(_) => return Ok(None)
+  };
+
+  // js passes dates as [year, month, day, hours, minutes, seconds, weekday] in UTC
+  match floats_in(cx, &parts).as_slice(){
+    [year, month, day, hour, minute, second, weekday] => Ok(Some(DateTime{
+      time_zone_minutes: 0,
+      year: *year as u16,
+      month: *month as u8,
+      day: *day as u8,
+      hour: *hour as u8,
+      minute: *minute as u8,
+      second: *second as u8,
+      day_of_week: *weekday as u8,
+    })),
+    _ => Ok(None)
+  }
+}
+
+pub fn info_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<DocumentInfo>{
+  let obj = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(DocumentInfo::default())
+    },
+    None => return Ok(DocumentInfo::default())
+  };
+
+  Ok(DocumentInfo{
+    title: opt_string_for_key(cx, &obj, "title").unwrap_or_default(),
+    author: opt_string_for_key(cx, &obj, "author").unwrap_or_default(),
+    subject: opt_string_for_key(cx, &obj, "subject").unwrap_or_default(),
+    keywords: opt_string_for_key(cx, &obj, "keywords").unwrap_or_default(),
+    creator: opt_string_for_key(cx, &obj, "creator").unwrap_or_default(),
+    creation: date_for_key(cx, &obj, "creationDate")?,
+    modified: date_for_key(cx, &obj, "modificationDate")?,
+  })
+}
+
+fn bookmarks_in(cx: &mut FunctionContext, list: Handle<JsArray>) -> NeonResult<Vec<Bookmark>>{
+  let mut bookmarks = vec![];
+  for item in list.to_vec(cx)?{
+    let obj = item.downcast_or_throw::<JsObject, _>(cx)?;
+    let title = string_for_key(cx, &obj, "title")?;
+    let target = match opt_string_for_key(cx, &obj, "destination"){
+      Some(name) => Target::Named(name),
+      None => Target::Page(float_for_key(cx, &obj, "page")? as usize)
+    };
+    let children:Handle<JsArray> = obj.get(cx, "children")?;
+    let children = bookmarks_in(cx, children)?;
+    bookmarks.push(Bookmark{ title, target, children });
+  }
+  Ok(bookmarks)
+}
+
+pub fn bookmarks_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Vec<Bookmark>>{
+  match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsArray, _>(cx){
+      Ok(list) => bookmarks_in(cx, list),
+      Err(_) => Ok(vec![])
+    },
+    None => Ok(vec![])
+  }
+}
+
+// reads an array of {size:[width, height], bleed, crop} objects (where the last entry applies to any remaining pages)
+pub fn page_setup_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Vec<PageSetup>>{
+  let list = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsArray, _>(cx){
+      Ok(list) => list.to_vec(cx)?,
+      Err(_) => return Ok(vec![])
+    },
+    None => return Ok(vec![])
+  };
+
+  let mut setup = vec![];
+  for item in list{
+    let obj = item.downcast_or_throw::<JsObject, _>(cx)?;
+    let size = match obj.get_value(cx, "size")?.downcast::<JsArray, _>(cx){
+      Ok(dims) => {
+        let dims = dims.to_vec(cx)?;
+        match floats_in(cx, &dims).as_slice(){
+          [width, height] if *width > 0.0 && *height > 0.0 => Some(Size::new(*width, *height)),
+          _ => return cx.throw_range_error("Page sizes must be a [width, height] pair of positive numbers")
+        }
+      },
+      Err(_) => None
+    };
+    let bleed = opt_float_for_key(cx, &obj, "bleed").unwrap_or(0.0).max(0.0);
+    let crop_to_trim = opt_string_for_key(cx, &obj, "crop").as_deref() == Some("trim");
+    setup.push(PageSetup{ size, bleed, crop_to_trim });
+  }
+  Ok(setup)
+}
+
+// reads an array of {page, style, prefix, start} objects (sorted by page number and starting with page 1)
+pub fn page_labels_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Vec<PageLabel>>{
+  let list = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsArray, _>(cx){
+      Ok(list) => list.to_vec(cx)?,
+      Err(_) => return Ok(vec![])
+    },
+    None => return Ok(vec![])
+  };
+
+  let mut labels = vec![];
+  for item in list{
+    let obj = item.downcast_or_throw::<JsObject, _>(cx)?;
+    let page = float_for_key(cx, &obj, "page")?.max(1.0) as usize - 1;
+    let style = opt_string_for_key(cx, &obj, "style");
+    let prefix = opt_string_for_key(cx, &obj, "prefix").unwrap_or_default();
+    let start = opt_float_for_key(cx, &obj, "start").unwrap_or(1.0).max(1.0) as u32;
+    labels.push(PageLabel{ page, style, prefix, start });
+  }
+  Ok(labels)
+}
+
+// reads an array of {name, data, mimeType, description, relationship} objects (with data as a Buffer)
+pub fn attachments_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Vec<Attachment>>{
+  let list = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsArray, _>(cx){
+      Ok(list) => list.to_vec(cx)?,
+      Err(_) => return Ok(vec![])
+    },
+    None => return Ok(vec![])
+  };
+
+  let mut attachments = vec![];
+  for item in list{
+    let obj = item.downcast_or_throw::<JsObject, _>(cx)?;
+    let name = string_for_key(cx, &obj, "name")?;
+    let data = obj.get::<JsBuffer, _, _>(cx, "data")?.as_slice(cx).to_vec();
+    let mime_type = opt_string_for_key(cx, &obj, "mimeType");
+    let description = opt_string_for_key(cx, &obj, "description");
+    let relationship = opt_string_for_key(cx, &obj, "relationship").unwrap_or_else(|| "Unspecified".to_string());
+    attachments.push(Attachment{ name, data, mime_type, description, relationship });
+  }
+  Ok(attachments)
+}
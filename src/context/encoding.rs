@@ -0,0 +1,483 @@
+//
+// PNG & JPEG encoders offering finer-grained control than Skia's quality setting
+//
+
+use std::collections::HashMap;
+use std::io::Write;
+use neon::prelude::*;
+use skia_safe::{Data, Image as SkImage, ImageInfo, IRect, ColorType, AlphaType, EncodedImageFormat};
+use skia_safe::image::{BitDepth, CachingHint};
+use miniz_oxide::deflate::core::{CompressorOxide, TDEFLFlush, TDEFLStatus, compress_to_output, create_comp_flags_from_zip_params};
+use jpeg_encoder::{Encoder as JpegEncoder, ColorType as JpegColorType, SamplingFactor};
+
+use crate::utils::*;
+use super::animation::{png_chunk, png_chunks, quantize};
+use super::metadata::{ImageMetadata, image_metadata_for_key};
+
+const STRIP_BYTES:usize = 64 * 1024 * 1024; // target size of each strip's pixel buffer when rendering in pieces
+const IDAT_BYTES:usize = 256 * 1024;         // size of the compressed chunks PNGs are written out in
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter{ None, Sub, Up, Average, Paeth }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Subsampling{ S444, S422, S420, S440, S411 }
+
+#[derive(Clone, Debug)]
+pub struct Encoding{
+  pub compression: u8,          // zlib level for PNGs (0–9)
+  pub filters: Vec<Filter>,     // PNG row filters to choose between (empty = pick based on the color type)
+  pub palette: bool,            // write an indexed-color PNG with at most 256 colors
+  pub subsampling: Subsampling, // JPEG chroma subsampling
+  pub progressive: bool,        // write a progressive rather than baseline JPEG
+}
+
+impl Default for Encoding{
+  fn default() -> Self {
+    Encoding{ compression:6, filters:vec![], palette:false, subsampling:Subsampling::S420, progressive:false }
+  }
+}
+
+// Settings for encoding a single bitmap (shared by page exports, thumbnails, tiles, and Image#toBuffer)
+#[derive(Clone, Debug)]
+pub struct ImageOptions{
+  pub quality: f32,
+  pub density: f32,               // for the resolution metadata
+  pub lossless: bool,             // for webp
+  pub bit_depth: BitDepth,        // for png
+  pub encoding: Option<Encoding>, // for png or jpg
+  pub metadata: ImageMetadata,
+}
+
+impl Default for ImageOptions{
+  fn default() -> Self {
+    ImageOptions{ quality:0.92, density:1.0, lossless:false, bit_depth:BitDepth::U8, encoding:None, metadata:ImageMetadata::default() }
+  }
+}
+
+// use our own encoders if any `encoding` options were provided (and Skia's otherwise), then add the metadata
+pub fn write_image(image:&SkImage, format:EncodedImageFormat, options:&ImageOptions, out:&mut dyn Write) -> Result<(), String>{
+  let ImageOptions{ quality, density, lossless, bit_depth, ref encoding, ref metadata } = *options;
+  let data = match (format, encoding) {
+    // (the png encoder writes the metadata chunks itself so its output can be streamed)
+    (EncodedImageFormat::PNG, Some(encoding)) => return write_png(image, bit_depth, encoding, &metadata.png_chunks(density), out),
+    (EncodedImageFormat::JPEG, Some(encoding)) => encode_jpeg(image, quality, encoding)?,
+    _ => {
+      // Skia's WebP encoder switches to lossless compression when the quality is exactly 100
+      let img_quality = match format {
+        EncodedImageFormat::WEBP if lossless => 100,
+        EncodedImageFormat::WEBP => ((quality*100.0) as i32).min(99),
+        _ => (quality*100.0) as i32
+      };
+      image.encode_to_data_with_quality(format, img_quality)
+        .ok_or(format!("Could not encode as {:?}", format))?
+    }
+  };
+  let data = metadata.embed(data, format, density)?;
+  out.write_all(data.as_bytes()).map_err(|err| format!("Could not write image data: {}", err))
+}
+
+pub fn encode_image(image:&SkImage, format:EncodedImageFormat, options:&ImageOptions) -> Result<Data, String>{
+  let mut buffer = vec![];
+  write_image(image, format, options, &mut buffer)?;
+  Ok(Data::new_copy(&buffer))
+}
+
+fn frame_pixels(frame:&SkImage, color_type:ColorType, alpha_type:AlphaType) -> Result<Vec<u8>, String>{
+  // leaving the color space unset reads back the pixels without converting them from the frame's own
+  let info = ImageInfo::new(frame.dimensions(), color_type, alpha_type, None);
+  let mut pixels = vec![0u8; info.compute_min_byte_size()];
+  match frame.read_pixels(&info, &mut pixels, info.min_row_bytes(), (0, 0), CachingHint::Allow){
+    true => Ok(pixels),
+    false => Err("Could not read pixels from rendered page".to_string())
+  }
+}
+
+// let Skia encode a single pixel of the frame so its color profile can be copied into our own output
+fn color_sample(frame:&SkImage, format:EncodedImageFormat) -> Result<Data, String>{
+  frame.new_subset(IRect::from_wh(1, 1))
+    .and_then(|pixel| pixel.encode_to_data(format))
+    .ok_or("Could not determine the image's color profile".to_string())
+}
+
+//
+// PNG
+//
+
+// Both PNG encoders write their output progressively: the header & ancillary chunks first, then the image data
+// as a series of IDAT chunks as it's compressed. The `chunks` argument holds any metadata (see ImageMetadata::png_chunks)
+// to be inserted after the IHDR.
+pub fn write_png(frame:&SkImage, bit_depth:BitDepth, encoding:&Encoding, chunks:&[u8], out:&mut dyn Write) -> Result<(), String>{
+  let (width, height) = (frame.width() as usize, frame.height() as usize);
+  let wide = bit_depth == BitDepth::F16 && !encoding.palette;
+  let mut pixels = match wide {
+    true => frame_pixels(frame, ColorType::R16G16B16A16UNorm, AlphaType::Unpremul)?
+      .chunks_exact(2)
+      .flat_map(|val| u16::from_ne_bytes([val[0], val[1]]).to_be_bytes())
+      .collect(),
+    false => frame_pixels(frame, ColorType::RGBA8888, AlphaType::Unpremul)?
+  };
+
+  // drop the alpha channel entirely if every pixel is opaque
+  let channel = if wide { 2 } else { 1 };
+  let opaque = pixels
+    .chunks_exact(4 * channel)
+    .all(|px| px[3*channel..].iter().all(|val| *val == 255));
+
+  let mut ancillary = vec![];
+  let (color_type, depth, samples) = if encoding.palette {
+    let (palette, indices) = indexed_colors(&pixels);
+    let depth = match palette.len() { 0..=2 => 1, 3..=4 => 2, 5..=16 => 4, _ => 8 };
+    ancillary.extend(png_chunk(b"PLTE", &palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect::<Vec<u8>>()));
+    let alphas:Vec<u8> = palette.iter().map(|c| c[3]).collect();
+    if let Some(last) = alphas.iter().rposition(|a| *a < 255){
+      ancillary.extend(png_chunk(b"tRNS", &alphas[..=last]));
+    }
+    pixels = pack_rows(&indices, width, depth);
+    (3u8, depth, 1)
+  }else if opaque {
+    pixels = pixels.chunks_exact(4 * channel).flat_map(|px| px[..3*channel].to_vec()).collect();
+    (2, 8 * channel as u8, 3)
+  }else{
+    (6, 8 * channel as u8, 4)
+  };
+
+  // index values are arbitrary so filtering rarely helps with palette images
+  let filters = match (encoding.filters.is_empty(), encoding.palette){
+    (true, true) => vec![Filter::None],
+    (true, false) => vec![Filter::None, Filter::Sub, Filter::Up, Filter::Average, Filter::Paeth],
+    _ => encoding.filters.clone()
+  };
+  let bpp = ((depth as usize * samples) / 8).max(1);
+  let row_len = (width * depth as usize * samples).div_ceil(8).max(1);
+
+  // carry over the color space chunks (iCCP, sRGB, etc.) that Skia would have written
+  let sample = color_sample(frame, EncodedImageFormat::PNG)?;
+  let header = [chunks, &color_chunks(&sample)?[..], &ancillary[..]].concat();
+  let mut png = PngWriter::new(out, width, height, depth, color_type, &header, encoding.compression)?;
+
+  // filter & compress the rows in batches so the output can be written as it's produced
+  let mut prior = vec![0u8; row_len];
+  let batch = (IDAT_BYTES / row_len).max(1) * row_len;
+  for (i, rows) in pixels.chunks(batch).enumerate(){
+    let filtered = filter_rows(rows, &prior, row_len, bpp, &filters);
+    prior = rows[rows.len() - row_len..].to_vec();
+    png.write_rows(&filtered, (i + 1) * batch >= pixels.len())?;
+  }
+  png.finish()
+}
+
+// Encode an image that's too large to rasterize all at once by rendering it in horizontal strips, each of
+// which is filtered and fed to the compressor before moving on to the next. Since the pixels are never all
+// in memory at the same time, the palette option is ignored and the alpha channel is always included.
+pub fn write_png_strips<F>(region:&IRect, bit_depth:BitDepth, encoding:&Encoding, chunks:&[u8], out:&mut dyn Write, render:F) -> Result<(), String>
+  where F:Fn(&IRect) -> Result<SkImage, String>
+{
+  let (width, height) = (region.width(), region.height());
+  let wide = bit_depth == BitDepth::F16;
+  let depth:u8 = if wide { 16 } else { 8 };
+  let bpp = 4 * depth as usize / 8;
+  let row_len = width as usize * bpp;
+  let strip_height = (STRIP_BYTES / row_len.max(1)).clamp(1, height.max(1) as usize) as i32;
+  let filters = match encoding.filters.is_empty(){
+    true => vec![Filter::None, Filter::Sub, Filter::Up, Filter::Average, Filter::Paeth],
+    false => encoding.filters.clone()
+  };
+
+  // the header's color space chunks come from the first strip, so render it before anything else
+  let strips:Vec<IRect> = (region.top..region.bottom)
+    .step_by(strip_height as usize)
+    .map(|top| IRect::from_xywh(region.left, top, width, strip_height.min(region.bottom - top)))
+    .collect();
+  let first = render(strips.first().ok_or("Could not encode an empty image")?)?;
+  let sample = color_sample(&first, EncodedImageFormat::PNG)?;
+  let header = [chunks, &color_chunks(&sample)?[..]].concat();
+  let mut png = PngWriter::new(out, width as usize, height as usize, depth, 6, &header, encoding.compression)?;
+
+  let mut first = Some(first);
+  let mut prior = vec![0u8; row_len];
+  for strip in &strips{
+    let frame = match first.take(){
+      Some(frame) => frame,
+      None => render(strip)?
+    };
+    let pixels:Vec<u8> = match wide {
+      true => frame_pixels(&frame, ColorType::R16G16B16A16UNorm, AlphaType::Unpremul)?
+        .chunks_exact(2)
+        .flat_map(|val| u16::from_ne_bytes([val[0], val[1]]).to_be_bytes())
+        .collect(),
+      false => frame_pixels(&frame, ColorType::RGBA8888, AlphaType::Unpremul)?
+    };
+    let filtered = filter_rows(&pixels, &prior, row_len, bpp, &filters);
+    prior = pixels[pixels.len() - row_len..].to_vec();
+    png.write_rows(&filtered, strip.bottom >= region.bottom)?;
+  }
+  png.finish()
+}
+
+fn color_chunks(sample:&Data) -> Result<Vec<u8>, String>{
+  Ok(png_chunks(sample.as_bytes())?
+    .iter()
+    .skip(1)
+    .take_while(|(tag, _)| *tag != b"IDAT")
+    .flat_map(|(tag, body)| png_chunk(tag, body))
+    .collect())
+}
+
+// writes a PNG's chunks to `out` as they become available, compressing the filtered image data
+// and splitting it into IDAT chunks of (at most) IDAT_BYTES each
+struct PngWriter<'a>{
+  out: &'a mut dyn Write,
+  compressor: CompressorOxide,
+  idat: Vec<u8>,
+}
+
+impl<'a> PngWriter<'a>{
+  fn new(out:&'a mut dyn Write, width:usize, height:usize, depth:u8, color_type:u8, chunks:&[u8], compression:u8) -> Result<Self, String>{
+    let ihdr = [
+      &(width as u32).to_be_bytes()[..], &(height as u32).to_be_bytes()[..],
+      &[depth, color_type, 0, 0, 0] // deflate compression, adaptive filtering, no interlacing
+    ].concat();
+    let flags = create_comp_flags_from_zip_params(compression.min(9) as i32, 15, 0); // 15 = zlib wrapper
+    let mut png = PngWriter{ out, compressor:CompressorOxide::new(flags), idat:vec![] };
+    png.write(b"\x89PNG\r\n\x1a\n")?;
+    png.write(&png_chunk(b"IHDR", &ihdr))?;
+    png.write(chunks)?;
+    Ok(png)
+  }
+
+  fn write(&mut self, bytes:&[u8]) -> Result<(), String>{
+    self.out.write_all(bytes).map_err(|err| format!("Could not write PNG data: {}", err))
+  }
+
+  fn write_rows(&mut self, filtered:&[u8], last:bool) -> Result<(), String>{
+    let flush = if last { TDEFLFlush::Finish } else { TDEFLFlush::None };
+    let idat = &mut self.idat;
+    let (status, _) = compress_to_output(&mut self.compressor, filtered, flush, |out|{ idat.extend_from_slice(out); true });
+    match status {
+      TDEFLStatus::Okay | TDEFLStatus::Done => {},
+      _ => return Err("Could not compress PNG image data".to_string())
+    }
+
+    // hold onto any partial chunk until there's enough data to fill it (or the image is complete)
+    let ready = match last {
+      true => self.idat.len(),
+      false => self.idat.len() / IDAT_BYTES * IDAT_BYTES
+    };
+    let data:Vec<u8> = self.idat.drain(..ready).collect();
+    for chunk in data.chunks(IDAT_BYTES){
+      self.write(&png_chunk(b"IDAT", chunk))?;
+    }
+    Ok(())
+  }
+
+  fn finish(mut self) -> Result<(), String>{
+    self.write(&png_chunk(b"IEND", &[]))
+  }
+}
+
+// map each pixel to a palette entry, using the exact colors if there are few enough of them and
+// falling back to the GIF encoder's quantizer (which only supports binary transparency) if not
+fn indexed_colors(pixels:&[u8]) -> (Vec<[u8; 4]>, Vec<u8>){
+  let mut counts:HashMap<[u8; 4], usize> = HashMap::new();
+  for px in pixels.chunks_exact(4){
+    *counts.entry([px[0], px[1], px[2], px[3]]).or_insert(0) += 1;
+    if counts.len() > 256 {
+      let (palette, indices, transparent) = quantize(pixels);
+      let palette = palette.iter().enumerate().map(|(idx, c)|{
+        [c[0], c[1], c[2], if Some(idx as u8) == transparent { 0 } else { 255 }]
+      }).collect();
+      return (palette, indices)
+    }
+  }
+
+  // put translucent colors first so the tRNS chunk can be as short as possible
+  let mut palette:Vec<[u8; 4]> = counts.into_keys().collect();
+  palette.sort_by_key(|c| (c[3], c[0], c[1], c[2]));
+  let lookup:HashMap<[u8; 4], u8> = palette.iter().enumerate().map(|(idx, c)| (*c, idx as u8)).collect();
+  let indices = pixels.chunks_exact(4).map(|px| lookup[&[px[0], px[1], px[2], px[3]]]).collect();
+  (palette, indices)
+}
+
+// pack sub-byte palette indices into rows of bytes (each of which is padded to a byte boundary)
+fn pack_rows(indices:&[u8], width:usize, depth:u8) -> Vec<u8>{
+  if depth == 8 || width == 0 { return indices.to_vec() }
+
+  let per_byte = 8 / depth as usize;
+  indices.chunks(width).flat_map(|row|{
+    row.chunks(per_byte).map(|group|{
+      group.iter().enumerate().fold(0u8, |byte, (i, idx)| byte | idx << (8 - depth as usize * (i + 1)))
+    }).collect::<Vec<u8>>()
+  }).collect()
+}
+
+// prefix each row with the filter type that minimizes the sum of absolute differences
+// (`prior` is the unfiltered row above the first one, or zeroes at the top of the image)
+fn filter_rows(data:&[u8], prior:&[u8], row_len:usize, bpp:usize, filters:&[Filter]) -> Vec<u8>{
+  let mut output = Vec::with_capacity(data.len() + data.len() / row_len.max(1));
+  let mut prior = prior;
+
+  for row in data.chunks(row_len.max(1)){
+    let best = filters.iter().map(|filter|{
+      let line:Vec<u8> = (0..row.len()).map(|i|{
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let above = prior[i];
+        let corner = if i >= bpp { prior[i - bpp] } else { 0 };
+        row[i].wrapping_sub(match filter {
+          Filter::None => 0,
+          Filter::Sub => left,
+          Filter::Up => above,
+          Filter::Average => ((left as u16 + above as u16) / 2) as u8,
+          Filter::Paeth => paeth(left, above, corner),
+        })
+      }).collect();
+      let score:u64 = line.iter().map(|val| (*val as i8).unsigned_abs() as u64).sum();
+      (score, *filter, line)
+    }).min_by_key(|(score, _, _)| *score);
+
+    if let Some((_, filter, line)) = best {
+      output.push(filter as u8);
+      output.extend(line);
+    }
+    prior = row;
+  }
+  output
+}
+
+fn paeth(left:u8, above:u8, corner:u8) -> u8{
+  let estimate = left as i16 + above as i16 - corner as i16;
+  let (dl, da, dc) = ((estimate - left as i16).abs(), (estimate - above as i16).abs(), (estimate - corner as i16).abs());
+  if dl <= da && dl <= dc { left }
+  else if da <= dc { above }
+  else { corner }
+}
+
+//
+// JPEG
+//
+
+fn encode_jpeg(frame:&SkImage, quality:f32, encoding:&Encoding) -> Result<Data, String>{
+  let (width, height) = (frame.width(), frame.height());
+  if width > u16::MAX as i32 || height > u16::MAX as i32 {
+    return Err(format!("JPEG images can be at most {}×{} pixels", u16::MAX, u16::MAX))
+  }
+
+  // like Skia's encoder, treat any transparent regions as though they were composited against black
+  let pixels = frame_pixels(frame, ColorType::RGBA8888, AlphaType::Premul)?;
+  let mut jpeg = vec![];
+  let mut encoder = JpegEncoder::new(&mut jpeg, (quality * 100.0).clamp(1.0, 100.0) as u8);
+  encoder.set_sampling_factor(match encoding.subsampling {
+    Subsampling::S444 => SamplingFactor::R_4_4_4,
+    Subsampling::S422 => SamplingFactor::R_4_2_2,
+    Subsampling::S420 => SamplingFactor::R_4_2_0,
+    Subsampling::S440 => SamplingFactor::R_4_4_0,
+    Subsampling::S411 => SamplingFactor::R_4_1_1,
+  });
+  encoder.set_progressive(encoding.progressive);
+  encoder.encode(&pixels, width as u16, height as u16, JpegColorType::Rgba)
+    .map_err(|e| format!("Could not encode as jpg: {}", e))?;
+
+  // insert the color profile that Skia would have embedded right after the JFIF header
+  let sample = color_sample(frame, EncodedImageFormat::JPEG)?;
+  let profile:Vec<u8> = jpeg_segments(sample.as_bytes())
+    .into_iter()
+    .filter(|(marker, _)| *marker == 0xE2)
+    .flat_map(|(_, segment)| segment.to_vec())
+    .collect();
+  let header_end = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+  jpeg.splice(header_end..header_end, profile);
+  Ok(Data::new_copy(&jpeg))
+}
+
+// split the header of a JPEG file into its (marker, segment) pairs, stopping at the image data
+fn jpeg_segments(data:&[u8]) -> Vec<(u8, &[u8])>{
+  let mut segments = vec![];
+  let mut pos = 2; // skip the SOI marker
+  while pos + 4 <= data.len() && data[pos] == 0xFF && data[pos+1] != 0xDA {
+    let end = pos + 2 + u16::from_be_bytes([data[pos+2], data[pos+3]]) as usize;
+    if end > data.len(){ break }
+    segments.push((data[pos+1], &data[pos..end]));
+    pos = end;
+  }
+  segments
+}
+
+//
+// Helpers
+//
+
+pub fn to_filter(name:&str) -> Option<Filter>{
+  match name{
+    "none" => Some(Filter::None),
+    "sub" => Some(Filter::Sub),
+    "up" => Some(Filter::Up),
+    "average" => Some(Filter::Average),
+    "paeth" => Some(Filter::Paeth),
+    _ => None
+  }
+}
+
+pub fn to_image_format(name:&str) -> Option<EncodedImageFormat>{
+  match name{
+    "jpg" | "jpeg" => Some(EncodedImageFormat::JPEG),
+    "png" => Some(EncodedImageFormat::PNG),
+    "webp" => Some(EncodedImageFormat::WEBP),
+    _ => None
+  }
+}
+
+pub fn to_subsampling(name:&str) -> Option<Subsampling>{
+  match name{
+    "4:4:4" => Some(Subsampling::S444),
+    "4:2:2" => Some(Subsampling::S422),
+    "4:2:0" => Some(Subsampling::S420),
+    "4:4:0" => Some(Subsampling::S440),
+    "4:1:1" => Some(Subsampling::S411),
+    _ => None
+  }
+}
+
+pub fn encoding_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Option<Encoding>>{
+  let obj = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(None)
+    },
+    None => return Ok(None)
+  };
+
+  let defaults = Encoding::default();
+  let filters = strings_at_key(cx, &obj, "filters")?
+    .iter()
+    .map(|name| to_filter(name))
+    .collect::<Option<Vec<Filter>>>();
+  let filters = match filters{
+    Some(filters) => filters,
+    None => return cx.throw_type_error("Expected \"none\", \"sub\", \"up\", \"average\", or \"paeth\" for filters")
+  };
+  let subsampling = match opt_string_for_key(cx, &obj, "chromaSubsampling"){
+    Some(name) => match to_subsampling(&name){
+      Some(subsampling) => subsampling,
+      None => return cx.throw_type_error("Expected \"4:4:4\", \"4:2:2\", \"4:2:0\", \"4:4:0\", or \"4:1:1\" for chromaSubsampling")
+    },
+    None => defaults.subsampling
+  };
+  let compression = opt_float_for_key(cx, &obj, "compression")
+    .map(|level| level.clamp(0.0, 9.0) as u8)
+    .unwrap_or(defaults.compression);
+  let palette = opt_bool_for_key(cx, &obj, "palette").unwrap_or(defaults.palette);
+  let progressive = opt_bool_for_key(cx, &obj, "progressive").unwrap_or(defaults.progressive);
+
+  Ok(Some(Encoding{ compression, filters, palette, subsampling, progressive }))
+}
+
+pub fn image_options_for_keys(cx: &mut FunctionContext, options: &Handle<JsObject>) -> NeonResult<ImageOptions>{
+  let defaults = ImageOptions::default();
+  let quality = opt_float_for_key(cx, options, "quality").unwrap_or(defaults.quality);
+  let density = opt_float_for_key(cx, options, "density").unwrap_or(defaults.density);
+  let lossless = opt_bool_for_key(cx, options, "lossless").unwrap_or(defaults.lossless);
+  let bit_depth = bit_depth_for_key(cx, options, "bitDepth");
+  let encoding = encoding_for_key(cx, options, "encoding")?;
+  let metadata = image_metadata_for_key(cx, options, "metadata")?;
+  Ok(ImageOptions{ quality, density, lossless, bit_depth, encoding, metadata })
+}
@@ -0,0 +1,555 @@
+//
+// Resolution, EXIF, XMP, and text metadata spliced into PNG, JPEG, and WebP files after encoding
+// (and read back out of the files an Image is decoded from)
+//
+
+use neon::prelude::*;
+use skia_safe::{Data, DateTime, EncodedImageFormat};
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+use crate::utils::*;
+use super::animation::{png_chunk, riff_chunk, u24};
+use super::document::{DocumentInfo, info_for_key};
+
+const XMP_KEYWORD:&str = "XML:com.adobe.xmp";
+const XMP_NAMESPACE:&[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const MAX_SEGMENT:usize = 65533; // largest possible JPEG segment (minus its length field)
+
+#[derive(Debug, Clone, Default)]
+pub struct ImageMetadata{
+  pub info: DocumentInfo,          // title, author, creator, & dates (shared with PDF output)
+  pub copyright: String,
+  pub xmp: String,                 // a complete XMP packet
+  pub text: Vec<(String, String)>, // PNG tEXt/iTXt key-value pairs
+}
+
+impl ImageMetadata{
+  pub fn is_empty(&self) -> bool{
+    self.exif().is_none() && self.xmp.is_empty() && self.text.is_empty()
+  }
+
+  // add the resolution & metadata to an encoded image
+  pub fn embed(&self, data:Data, format:EncodedImageFormat, density:f32) -> Result<Data, String>{
+    let data = with_dpi(data, format, density);
+    if self.is_empty() { return Ok(data) }
+
+    let bytes = data.as_bytes();
+    let output = match format {
+      EncodedImageFormat::PNG => self.embed_png(bytes),
+      EncodedImageFormat::JPEG => self.embed_jpeg(bytes)?,
+      EncodedImageFormat::WEBP => self.embed_webp(bytes)?,
+      _ => return Ok(data)
+    };
+    Ok(Data::new_copy(&output))
+  }
+
+  // the chunks that `embed` adds directly after a PNG's IHDR (for encoders that write them in place themselves)
+  pub fn png_chunks(&self, density:f32) -> Vec<u8>{
+    let mut chunks = vec![];
+    if let Some(exif) = self.exif(){
+      chunks.extend(png_chunk(b"eXIf", &exif));
+    }
+    if !self.xmp.is_empty(){
+      chunks.extend(png_text(XMP_KEYWORD, &self.xmp));
+    }
+    for (key, val) in &self.text{
+      chunks.extend(png_text(key, val));
+    }
+    if density as u32 != 1 {
+      chunks.extend(phys_chunk(density));
+    }
+    chunks
+  }
+
+  fn embed_png(&self, bytes:&[u8]) -> Vec<u8>{
+    // insert the chunks directly after the IHDR (the pHYs chunk has already been added by with_dpi)
+    let mut png = bytes.to_vec();
+    png.splice(33..33, self.png_chunks(1.0));
+    png
+  }
+
+  fn embed_jpeg(&self, bytes:&[u8]) -> Result<Vec<u8>, String>{
+    let mut segments = vec![];
+    if let Some(exif) = self.exif(){
+      segments.extend(jpeg_segment(0xE1, &[&b"Exif\0\0"[..], &exif].concat())?);
+    }
+    if !self.xmp.is_empty(){
+      segments.extend(jpeg_segment(0xE1, &[XMP_NAMESPACE, self.xmp.as_bytes()].concat())?);
+    }
+
+    // insert the segments directly after the JFIF header
+    let mut jpeg = bytes.to_vec();
+    let header_end = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+    jpeg.splice(header_end..header_end, segments);
+    Ok(jpeg)
+  }
+
+  fn embed_webp(&self, bytes:&[u8]) -> Result<Vec<u8>, String>{
+    let mut chunks = riff_chunks(bytes)?;
+    let exif = self.exif();
+    let flags = exif.as_ref().map_or(0, |_| 0x08) | if self.xmp.is_empty() { 0 } else { 0x04 };
+
+    // metadata requires the ‘extended’ format, so convert simple files to it by adding a VP8X header
+    match chunks.first().cloned() {
+      Some((tag, mut vp8x)) if &tag == b"VP8X" => {
+        vp8x[0] |= flags;
+        chunks[0] = (tag, vp8x);
+      },
+      Some((tag, body)) => {
+        let (width, height, alpha) = webp_dimensions(&tag, &body)?;
+        let flags = flags | if alpha { 0x10 } else { 0 };
+        let vp8x = [&[flags, 0, 0, 0][..], &u24(width - 1), &u24(height - 1)].concat();
+        chunks.insert(0, (*b"VP8X", vp8x));
+      },
+      None => return Err("Could not parse WebP-encoded image".to_string())
+    }
+
+    chunks.retain(|(tag, _)| tag != b"EXIF" && tag != b"XMP ");
+    if let Some(exif) = exif{
+      chunks.push((*b"EXIF", exif));
+    }
+    if !self.xmp.is_empty(){
+      chunks.push((*b"XMP ", self.xmp.as_bytes().to_vec()));
+    }
+
+    let body:Vec<u8> = chunks.iter().flat_map(|(tag, body)| riff_chunk(tag, body)).collect();
+    Ok(riff_chunk(b"RIFF", &[&b"WEBP"[..], &body].concat()))
+  }
+
+  // a big-endian TIFF structure with the image's descriptive tags (in IFD0) and its creation date (in the Exif IFD)
+  fn exif(&self) -> Option<Vec<u8>>{
+    let info = &self.info;
+    let mut ifd0 = vec![];
+    let mut sub_ifd = vec![];
+    if !info.title.is_empty(){ ifd0.push(ExifTag::ascii(0x010E, &info.title)) }     // ImageDescription
+    if !info.creator.is_empty(){ ifd0.push(ExifTag::ascii(0x0131, &info.creator)) } // Software
+    if let Some(date) = info.modified.or(info.creation){
+      ifd0.push(ExifTag::ascii(0x0132, &exif_date(&date)));                       // DateTime
+      sub_ifd.push(ExifTag::ascii(0x9010, "+00:00"));                              // OffsetTime
+    }
+    if !info.author.is_empty(){ ifd0.push(ExifTag::ascii(0x013B, &info.author)) }   // Artist
+    if !self.copyright.is_empty(){ ifd0.push(ExifTag::ascii(0x8298, &self.copyright)) }
+    if let Some(date) = info.creation{
+      sub_ifd.insert(0, ExifTag::ascii(0x9003, &exif_date(&date)));                // DateTimeOriginal
+      sub_ifd.push(ExifTag::ascii(0x9011, "+00:00"));                              // OffsetTimeOriginal
+    }
+
+    if ifd0.is_empty() && sub_ifd.is_empty() { return None }
+
+    let mut tiff = [&b"MM\0\x2a"[..], &8u32.to_be_bytes()].concat();
+    if sub_ifd.is_empty(){
+      tiff.extend(exif_ifd(&ifd0, 8));
+    }else{
+      // the Exif IFD follows IFD0, whose size doesn't depend on the pointer's value
+      ifd0.push(ExifTag::long(0x8769, 0));
+      let sub_offset = 8 + exif_ifd(&ifd0, 8).len();
+      ifd0.pop();
+      ifd0.push(ExifTag::long(0x8769, sub_offset as u32));
+      tiff.extend(exif_ifd(&ifd0, 8));
+      tiff.extend(exif_ifd(&sub_ifd, sub_offset));
+    }
+    Some(tiff)
+  }
+}
+
+// add the pHYs chunk or JFIF density values reflecting the export's `density` option
+pub fn with_dpi(data:Data, format:EncodedImageFormat, density:f32) -> Data{
+  if density as u32 == 1 { return data }
+
+  let mut bytes = data.as_bytes().to_vec();
+  match format{
+    EncodedImageFormat::JPEG => {
+      let [l, r] = (72 * density as u16).to_be_bytes();
+      bytes.splice(13..18, [1, l, r, l, r].iter().cloned());
+      Data::new_copy(&bytes)
+    }
+    EncodedImageFormat::PNG => {
+      bytes.splice(33..33, phys_chunk(density));
+      Data::new_copy(&bytes)
+    }
+    _ => data
+  }
+}
+
+fn phys_chunk(density:f32) -> Vec<u8>{
+  let ppm = ((72.0 * density * 39.3701) as u32).to_be_bytes();
+  png_chunk(b"pHYs", &[&ppm[..], &ppm[..], &[1]].concat()) // x & y resolution in dots per meter
+}
+
+//
+// EXIF
+//
+
+const ASCII:u16 = 2;
+const LONG:u16 = 4;
+
+struct ExifTag{ id:u16, kind:u16, count:u32, value:Vec<u8> }
+
+impl ExifTag{
+  fn ascii(id:u16, text:&str) -> Self{
+    let value = [text.as_bytes(), &[0]].concat();
+    ExifTag{ id, kind:ASCII, count:value.len() as u32, value }
+  }
+
+  fn long(id:u16, val:u32) -> Self{
+    ExifTag{ id, kind:LONG, count:1, value:val.to_be_bytes().to_vec() }
+  }
+}
+
+// serialize an IFD located at `start`, followed by any values too large to fit in its entries
+fn exif_ifd(tags:&[ExifTag], start:usize) -> Vec<u8>{
+  let data_start = start + 2 + 12 * tags.len() + 4;
+  let mut entries = (tags.len() as u16).to_be_bytes().to_vec();
+  let mut data = vec![];
+  for tag in tags{
+    entries.extend(tag.id.to_be_bytes());
+    entries.extend(tag.kind.to_be_bytes());
+    entries.extend(tag.count.to_be_bytes());
+    if tag.value.len() <= 4 {
+      let mut value = tag.value.clone();
+      value.resize(4, 0);
+      entries.extend(value);
+    }else{
+      entries.extend(((data_start + data.len()) as u32).to_be_bytes());
+      data.extend(&tag.value);
+      if data.len() % 2 == 1 { data.push(0) } // keep offsets word-aligned
+    }
+  }
+  entries.extend(0u32.to_be_bytes()); // no further IFDs
+  [entries, data].concat()
+}
+
+fn exif_date(date:&DateTime) -> String{
+  format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}", date.year, date.month, date.day, date.hour, date.minute, date.second)
+}
+
+//
+// Container formats
+//
+
+// latin-1 text can use a tEXt chunk but anything else needs to be stored as UTF-8 in an iTXt
+// (keywords are always latin-1, see `is_png_keyword`)
+fn png_text(key:&str, val:&str) -> Vec<u8>{
+  let latin1 = |text:&str| -> Vec<u8> { text.chars().map(|c| c as u8).collect() };
+  match val.chars().all(|c| (c as u32) < 256) && key != XMP_KEYWORD {
+    true => png_chunk(b"tEXt", &[latin1(key), vec![0], latin1(val)].concat()),
+    // uncompressed, with empty language tag & translated keyword
+    false => png_chunk(b"iTXt", &[latin1(key), vec![0, 0, 0, 0, 0], val.as_bytes().to_vec()].concat())
+  }
+}
+
+// the png spec limits keywords to 1–79 bytes of latin-1
+fn is_png_keyword(key:&str) -> bool{
+  (1..80).contains(&key.chars().count()) && key.chars().all(|c| (1..256).contains(&(c as u32)))
+}
+
+fn jpeg_segment(marker:u8, body:&[u8]) -> Result<Vec<u8>, String>{
+  if body.len() > MAX_SEGMENT {
+    return Err(format!("Metadata is too large to embed in a JPEG file ({} bytes; max is {})", body.len(), MAX_SEGMENT))
+  }
+  Ok([&[0xFF, marker][..], &((body.len() + 2) as u16).to_be_bytes(), body].concat())
+}
+
+// split a WebP file into its (tag, body) chunks
+fn riff_chunks(data:&[u8]) -> Result<Vec<([u8; 4], Vec<u8>)>, String>{
+  if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+    return Err("Could not parse WebP-encoded image".to_string())
+  }
+
+  let mut chunks = vec![];
+  let mut pos = 12;
+  while pos + 8 <= data.len() {
+    let tag = [data[pos], data[pos+1], data[pos+2], data[pos+3]];
+    let len = u32::from_le_bytes([data[pos+4], data[pos+5], data[pos+6], data[pos+7]]) as usize;
+    let end = (pos + 8 + len).min(data.len());
+    chunks.push((tag, data[pos+8..end].to_vec()));
+    pos = end + len % 2;
+  }
+  Ok(chunks)
+}
+
+// read the canvas size (and whether it has an alpha channel) from a simple-format WebP bitstream
+fn webp_dimensions(tag:&[u8], body:&[u8]) -> Result<(u32, u32, bool), String>{
+  match tag {
+    b"VP8 " if body.len() >= 10 => {
+      let width = u16::from_le_bytes([body[6], body[7]]) & 0x3FFF;
+      let height = u16::from_le_bytes([body[8], body[9]]) & 0x3FFF;
+      Ok((width as u32, height as u32, false))
+    },
+    b"VP8L" if body.len() >= 5 => {
+      let bits = u32::from_le_bytes([body[1], body[2], body[3], body[4]]);
+      Ok(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, (bits >> 28) & 1 == 1))
+    },
+    _ => Err("Could not parse WebP-encoded image".to_string())
+  }
+}
+
+//
+// Decoding
+//
+
+#[derive(Debug, Clone)]
+pub struct SourceMetadata{
+  pub format: Option<String>,
+  pub orientation: u16,             // EXIF orientation (1–8)
+  pub dpi: Option<(f32, f32)>,
+  pub icc: Option<Vec<u8>>,         // embedded color profile
+  pub exif: Option<Vec<u8>>,        // the TIFF structure following the `Exif` header
+  pub xmp: Option<String>,
+}
+
+impl Default for SourceMetadata{
+  fn default() -> Self{
+    SourceMetadata{ format:None, orientation:1, dpi:None, icc:None, exif:None, xmp:None }
+  }
+}
+
+impl SourceMetadata{
+  pub fn read(bytes:&[u8]) -> Self{
+    let format = sniff_format(bytes);
+    let mut meta = SourceMetadata{ format:format.map(str::to_string), ..Default::default() };
+    match format {
+      Some("png") => meta.read_png(bytes),
+      Some("jpeg") => meta.read_jpeg(bytes),
+      Some("webp") => meta.read_webp(bytes),
+      _ => {}
+    }
+
+    if let Some(exif) = meta.exif.as_ref().and_then(|exif| ExifReader::new(exif)){
+      if let Some(orientation) = exif.short(0x0112).filter(|n| (1..=8).contains(n)){
+        meta.orientation = orientation;
+      }
+      // container-level resolution values take precedence over EXIF's
+      if meta.dpi.is_none(){
+        let to_inch = match exif.short(0x0128) { Some(3) => 2.54, _ => 1.0 }; // ResolutionUnit
+        if let (Some(x), Some(y)) = (exif.rational(0x011A), exif.rational(0x011B)){
+          meta.dpi = Some((x * to_inch, y * to_inch));
+        }
+      }
+    }
+    meta
+  }
+
+  // the name stored in the profile's description tag (if any)
+  pub fn icc_name(&self) -> Option<String>{
+    let icc = self.icc.as_ref()?;
+    let count = be_u32(icc, 128)? as usize;
+    let (offset, size) = (0..count).map(|i| 132 + 12 * i).find_map(|entry|
+      match icc.get(entry..entry + 4)? == b"desc" {
+        true => Some((be_u32(icc, entry + 4)? as usize, be_u32(icc, entry + 8)? as usize)),
+        false => None
+      }
+    )?;
+    let tag = icc.get(offset..offset + size)?;
+    match tag.get(0..4)? {
+      b"desc" => {
+        let len = be_u32(tag, 8)? as usize;
+        let text = tag.get(12..12 + len)?;
+        Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string())
+      },
+      b"mluc" => {
+        let (len, start) = (be_u32(tag, 20)? as usize, be_u32(tag, 24)? as usize);
+        let utf16 = tag.get(start..start + len)?.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<_>>();
+        Some(String::from_utf16_lossy(&utf16).trim_end_matches('\0').to_string())
+      },
+      _ => None
+    }
+  }
+
+  fn read_png(&mut self, bytes:&[u8]){
+    let mut pos = 8;
+    while let (Some(len), Some(tag)) = (be_u32(bytes, pos), bytes.get(pos + 4..pos + 8)) {
+      let body = match bytes.get(pos + 8..pos + 8 + len as usize) {
+        Some(body) => body,
+        None => break
+      };
+      match tag {
+        b"pHYs" if body.len() >= 9 && body[8] == 1 => {
+          let (x, y) = (be_u32(body, 0).unwrap_or(0), be_u32(body, 4).unwrap_or(0));
+          self.dpi = Some((x as f32 * 0.0254, y as f32 * 0.0254));
+        },
+        b"iCCP" => {
+          // profile name, compression method, and zlib stream
+          let start = body.iter().position(|c| *c == 0).map(|i| i + 2);
+          self.icc = start.and_then(|i| body.get(i..)).and_then(|zlib| decompress_to_vec_zlib(zlib).ok());
+        },
+        b"eXIf" => self.exif = Some(body.to_vec()),
+        b"iTXt" if body.starts_with(XMP_KEYWORD.as_bytes()) => {
+          // keyword, compression flag & method, language, translated keyword, and text
+          let flags = XMP_KEYWORD.len() + 1;
+          let compressed = body.get(flags) == Some(&1);
+          let mut nuls = body.iter().enumerate().skip(flags + 2).filter(|(_, c)| **c == 0).map(|(i, _)| i);
+          if let Some(start) = nuls.nth(1).map(|i| i + 1) {
+            let text = match compressed {
+              true => decompress_to_vec_zlib(&body[start..]).ok(),
+              false => Some(body[start..].to_vec())
+            };
+            self.xmp = text.map(|text| String::from_utf8_lossy(&text).to_string());
+          }
+        },
+        b"IEND" => break,
+        _ => {}
+      }
+      pos += 12 + len as usize;
+    }
+  }
+
+  fn read_jpeg(&mut self, bytes:&[u8]){
+    let mut icc_parts = vec![];
+    let mut pos = 2;
+    while bytes.get(pos) == Some(&0xFF) {
+      let marker = match bytes.get(pos + 1) {
+        Some(0xDA) | Some(0xD9) | None => break, // the image data follows the SOS segment
+        Some(marker) => *marker
+      };
+      let len = match bytes.get(pos + 2..pos + 4) {
+        Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+        None => break
+      };
+      let body = match bytes.get(pos + 4..pos + 2 + len) {
+        Some(body) => body,
+        None => break
+      };
+      match marker {
+        0xE0 if body.starts_with(b"JFIF\0") && body.len() >= 12 => {
+          let (x, y) = (u16::from_be_bytes([body[8], body[9]]) as f32, u16::from_be_bytes([body[10], body[11]]) as f32);
+          match body[7] {
+            1 => self.dpi = Some((x, y)),
+            2 => self.dpi = Some((x * 2.54, y * 2.54)),
+            _ => {} // only an aspect ratio
+          }
+        },
+        0xE1 if body.starts_with(b"Exif\0\0") => self.exif = Some(body[6..].to_vec()),
+        0xE1 if body.starts_with(XMP_NAMESPACE) => {
+          self.xmp = Some(String::from_utf8_lossy(&body[XMP_NAMESPACE.len()..]).to_string())
+        },
+        // profiles too large for a single segment are split into numbered chunks
+        0xE2 if body.starts_with(b"ICC_PROFILE\0") && body.len() > 14 => icc_parts.push((body[12], &body[14..])),
+        _ => {}
+      }
+      pos += 2 + len;
+    }
+
+    if !icc_parts.is_empty(){
+      icc_parts.sort_by_key(|(seq, _)| *seq);
+      self.icc = Some(icc_parts.into_iter().flat_map(|(_, part)| part.to_vec()).collect());
+    }
+  }
+
+  fn read_webp(&mut self, bytes:&[u8]){
+    for (tag, body) in riff_chunks(bytes).unwrap_or_default(){
+      match &tag {
+        b"ICCP" => self.icc = Some(body),
+        // some encoders include the JPEG-style header, others start with the TIFF structure
+        b"EXIF" => self.exif = Some(body.strip_prefix(b"Exif\0\0").unwrap_or(&body).to_vec()),
+        b"XMP " => self.xmp = Some(String::from_utf8_lossy(&body).to_string()),
+        _ => {}
+      }
+    }
+  }
+}
+
+fn sniff_format(bytes:&[u8]) -> Option<&'static str>{
+  let format = match bytes {
+    [0x89, b'P', b'N', b'G', ..] => "png",
+    [0xFF, 0xD8, 0xFF, ..] => "jpeg",
+    [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+    [b'G', b'I', b'F', b'8', ..] => "gif",
+    [b'B', b'M', ..] => "bmp",
+    [0, 0, 1, 0, ..] => "ico",
+    [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f', ..] => "avif",
+    [_, _, _, _, b'f', b't', b'y', b'p', b'h', b'e', b'i', b'c', ..] => "heic",
+    _ if is_svg(bytes) => "svg",
+    _ => return None
+  };
+  Some(format)
+}
+
+// look for a root element in the first few kB of markup (after any XML prolog, doctype, or comments)
+fn is_svg(bytes:&[u8]) -> bool{
+  let head = String::from_utf8_lossy(&bytes[..bytes.len().min(4096)]);
+  head.trim_start_matches(|c:char| c.is_whitespace() || c == '\u{feff}').starts_with('<') && head.contains("<svg")
+}
+
+fn be_u32(bytes:&[u8], pos:usize) -> Option<u32>{
+  bytes.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// looks up values in IFD0 of a TIFF structure (in either byte order)
+struct ExifReader<'a>{ data:&'a [u8], little_endian:bool, ifd:usize }
+
+impl<'a> ExifReader<'a>{
+  fn new(data:&'a [u8]) -> Option<Self>{
+    let little_endian = match data.get(0..4)? {
+      b"II\x2a\0" => true,
+      b"MM\0\x2a" => false,
+      _ => return None
+    };
+    let mut reader = ExifReader{ data, little_endian, ifd:0 };
+    reader.ifd = reader.u32(4)? as usize;
+    Some(reader)
+  }
+
+  fn u16(&self, pos:usize) -> Option<u16>{
+    let b = self.data.get(pos..pos + 2)?;
+    Some(match self.little_endian { true => u16::from_le_bytes([b[0], b[1]]), false => u16::from_be_bytes([b[0], b[1]]) })
+  }
+
+  fn u32(&self, pos:usize) -> Option<u32>{
+    let b = self.data.get(pos..pos + 4)?;
+    Some(match self.little_endian {
+      true => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+      false => u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    })
+  }
+
+  // the position of the tag's 4-byte value field
+  fn entry(&self, id:u16) -> Option<usize>{
+    let count = self.u16(self.ifd)? as usize;
+    (0..count).map(|i| self.ifd + 2 + 12 * i).find(|pos| self.u16(*pos) == Some(id)).map(|pos| pos + 8)
+  }
+
+  fn short(&self, id:u16) -> Option<u16>{
+    self.u16(self.entry(id)?)
+  }
+
+  fn rational(&self, id:u16) -> Option<f32>{
+    let offset = self.u32(self.entry(id)?)? as usize;
+    let (num, denom) = (self.u32(offset)?, self.u32(offset + 4)?);
+    match denom {
+      0 => None,
+      _ => Some(num as f32 / denom as f32)
+    }
+  }
+}
+
+//
+// Helpers
+//
+
+pub fn image_metadata_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<ImageMetadata>{
+  let info = info_for_key(cx, options, attr)?;
+  let obj = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(ImageMetadata::default())
+    },
+    None => return Ok(ImageMetadata::default())
+  };
+
+  // js passes the text entries as a flat list of alternating keys & values
+  let text:Vec<(String, String)> = strings_at_key(cx, &obj, "text")?
+    .chunks_exact(2)
+    .map(|pair| (pair[0].clone(), pair[1].clone()))
+    .collect();
+  if let Some((key, _)) = text.iter().find(|(key, _)| !is_png_keyword(key)){
+    return cx.throw_type_error(format!("Text metadata keys must be 1–79 Latin-1 characters (got \"{}\")", key))
+  }
+
+  Ok(ImageMetadata{
+    info,
+    copyright: opt_string_for_key(cx, &obj, "copyright").unwrap_or_default(),
+    xmp: opt_string_for_key(cx, &obj, "xmp").unwrap_or_default(),
+    text,
+  })
+}
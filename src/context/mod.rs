@@ -16,8 +16,20 @@ use skia_safe::textlayout::{ParagraphStyle, TextStyle};
 use skia_safe::canvas::SrcRectConstraint::Strict;
 use skia_safe::path::FillType;
 
+pub mod animation;
 pub mod api;
+pub mod document;
+pub mod encoding;
+pub mod metadata;
 pub mod page;
+pub mod progress;
+pub mod raster;
+pub mod raw;
+pub mod structure;
+pub mod svg;
+pub mod thumbnails;
+pub mod tiles;
+pub mod transform;
 
 use crate::FONT_LIBRARY;
 use crate::utils::*;
@@ -27,6 +39,8 @@ use crate::gradient::{CanvasGradient, BoxedCanvasGradient};
 use crate::pattern::{CanvasPattern, BoxedCanvasPattern};
 use crate::texture::{CanvasTexture, BoxedCanvasTexture};
 use page::{PageRecorder, Page};
+use document::TextField;
+use structure::Tag;
 
 const BLACK:Color = Color::BLACK;
 const TRANSPARENT:Color = Color::TRANSPARENT;
@@ -400,7 +414,51 @@ impl Context2D{
     }
   }
 
-  pub fn draw_picture(&mut self, picture:&Option<Picture>, src_rect:&Rect, dst_rect:&Rect){
+  pub fn add_link(&mut self, rect:&Rect, url:&str){
+    // written as links by the PDF & SVG backends and ignored by the rest (PDF expects null-terminated strings)
+    let (target, internal) = match url.strip_prefix('#'){
+      Some(name) => (name, true),
+      None => (url, false)
+    };
+    let data = Data::new_copy(&[target.as_bytes(), &[0]].concat());
+    self.with_canvas(|canvas| {
+      match internal {
+        true => canvas.annotate_link_to_destination(rect, &data),
+        false => canvas.annotate_rect_with_url(rect, &data),
+      };
+    });
+  }
+
+  pub fn add_text_field(&mut self, rect:&Rect, field:TextField){
+    // kept alongside the page's layers (rather than recorded in them) until it's added to a PDF as a form widget
+    let rect = self.state.matrix.map_rect(rect).0;
+    self.with_recorder(|mut recorder|{
+      recorder.add_field(field, rect);
+    });
+  }
+
+  pub fn begin_tag(&mut self, tag:Tag){
+    self.with_recorder(|mut recorder|{
+      recorder.begin_tag(tag);
+    });
+  }
+
+  pub fn end_tag(&mut self) -> bool {
+    let mut closed = false;
+    self.with_recorder(|mut recorder|{
+      closed = recorder.end_tag();
+    });
+    closed
+  }
+
+  pub fn add_destination(&mut self, name:&str, point:Point){
+    let data = Data::new_copy(&[name.as_bytes(), &[0]].concat());
+    self.with_canvas(|canvas| {
+      canvas.annotate_named_destination(point, &data);
+    });
+  }
+
+  pub fn draw_picture(&mut self, picture:&Option<Picture>, src_rect:&Rect, dst_rect:&Rect, clip:bool){
     let paint = self.paint_for_image();
     let size = ISize::new(dst_rect.width() as i32, dst_rect.height() as i32);
     let mag = Point::new(dst_rect.width()/src_rect.width(), dst_rect.height()/src_rect.height());
@@ -416,7 +474,13 @@ impl Context2D{
           (Some(BlendMode::SrcOver), 255, None) => None,
           _ => Some(paint)
         };
+        // optionally clip to the destination in case the source rect was smaller than the picture
+        canvas.save();
+        if clip {
+          canvas.clip_rect(dst_rect, ClipOp::Intersect, true);
+        }
         canvas.draw_picture(&picture, Some(&matrix), paint);
+        canvas.restore();
       });
     }
   }
@@ -447,6 +511,14 @@ impl Context2D{
     self.get_page().get_picture(None, None)
   }
 
+  pub fn load_picture(&mut self, picture:Picture){
+    // replace the context's contents with a previously recorded picture (and match its dimensions)
+    self.reset_size(picture.cull_rect().size());
+    self.with_recorder(|mut recorder| {
+      recorder.add_layer(picture);
+    });
+  }
+
   pub fn get_pixels(&mut self, buffer: &mut [u8], origin: impl Into<IPoint>, size: impl Into<ISize>){
     let origin = origin.into();
     let size = size.into();
@@ -1,21 +1,29 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io::{Write, BufWriter};
 use std::path::Path as FilePath;
+use std::ops::RangeInclusive;
 use rayon::prelude::*;
 use neon::prelude::*;
 use skia_safe::{Canvas as SkCanvas,
                 Path, Matrix, Rect, IRect, Size, ISize, IPoint,
-                ClipOp, Data, Color, ColorSpace, ColorType,
+                ClipOp, Data, Color, ColorSpace, ColorType, AlphaType,
                 PictureRecorder, Picture, EncodedImageFormat, Image as SkImage,
-                svg::{self, canvas::Flags}, pdf, Document, ImageInfo, RoundOut,
+                svg::{self, canvas::Flags}, pdf, ImageInfo, RoundOut,
                 image::BitDepth, image::CachingHint};
 
-use crc::{Crc, CRC_32_ISO_HDLC};
-const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-
 use crate::canvas::BoxedCanvas;
 use crate::context::BoxedContext2D;
 use crate::gpu::RenderingEngine;
-use crate::utils::make_raw_image_info;
+use crate::utils::*;
+use super::animation::{self, Animation, animation_for_key};
+use super::raster;
+use super::document::{PdfOptions, PageSetup, TextField, PlacedField, bookmarks_for_key, page_setup_for_key, page_labels_for_key, attachments_for_key};
+use super::encoding::{self, Encoding, ImageOptions, encoding_for_key};
+use super::metadata::{ImageMetadata, image_metadata_for_key};
+use super::svg::{self as svg_output, SvgOptions, SvgImages, svg_options_for_key};
+use super::progress::{Progress, progress_for_keys};
+use super::structure::{self, Tag, StructureTree, PdfDocument};
+use super::transform::transform_for_key;
 
 //
 // Deferred canvas (records drawing commands for later replay on an output surface)
@@ -24,6 +32,9 @@ use crate::utils::make_raw_image_info;
 pub struct PageRecorder{
   current: PictureRecorder,
   layers: Vec<Picture>,
+  fields: Vec<PlacedField>,
+  tags: Vec<Tag>,
+  open_tags: Vec<Tag>,
   cache: Option<SkImage>,
   bounds: Rect,
   matrix: Matrix,
@@ -36,7 +47,7 @@ impl PageRecorder{
     let mut rec = PictureRecorder::new();
     rec.begin_recording(bounds, None);
     rec.recording_canvas().unwrap().save(); // start at depth 2
-    PageRecorder{ current:rec, changed:false, layers:vec![], cache:None, matrix:Matrix::default(), clip:None, bounds }
+    PageRecorder{ current:rec, changed:false, layers:vec![], fields:vec![], tags:vec![], open_tags:vec![], cache:None, matrix:Matrix::default(), clip:None, bounds }
   }
 
   pub fn append<F>(&mut self, f:F)
@@ -89,12 +100,73 @@ impl PageRecorder{
       self.changed = false;
       self.cache = None;
       self.restore();
+      // content in the next layer still belongs to whichever tag is open
+      if !self.open_tags.is_empty(){
+        self.mark_content();
+      }
     }
 
     Page{
       layers: self.layers.clone(),
       bounds: self.bounds,
+      fields: self.fields.clone(),
+      tags: self.tag_tree(),
+    }
+  }
+
+  pub fn begin_tag(&mut self, tag:Tag){
+    self.open_tags.push(tag);
+    self.mark_content();
+  }
+
+  // closes the innermost open tag (returning false if there wasn't one)
+  pub fn end_tag(&mut self) -> bool {
+    let tag = match self.open_tags.pop() {
+      Some(tag) => tag,
+      None => return false
+    };
+    match self.open_tags.last_mut() {
+      Some(parent) => parent.children.push(tag),
+      None => self.tags.push(tag)
+    }
+    self.mark_content();
+    true
+  }
+
+  fn mark_content(&mut self){
+    let node_id = self.open_tags.last().map(|tag| tag.id).unwrap_or(0);
+    if let Some(canvas) = self.current.recording_canvas() {
+      structure::mark_content(canvas, node_id);
+    }
+  }
+
+  // the page's tags (including any that are still open)
+  fn tag_tree(&self) -> Vec<Tag> {
+    let mut tags = self.tags.clone();
+    let mut open = self.open_tags.clone();
+    while let Some(tag) = open.pop() {
+      match open.last_mut() {
+        Some(parent) => parent.children.push(tag),
+        None => tags.push(tag)
+      }
     }
+    tags
+  }
+
+  pub fn add_field(&mut self, field:TextField, rect:Rect){
+    // the field belongs to the layer currently being recorded (or to the last one if nothing has been drawn since)
+    let layer = match self.changed {
+      true => self.layers.len(),
+      false => self.layers.len().saturating_sub(1)
+    };
+    self.fields.push(PlacedField{ field, rect, layer });
+  }
+
+  pub fn add_layer(&mut self, picture:Picture){
+    // commit anything drawn so far so the new layer is stacked above it
+    self.get_page();
+    self.layers.push(picture);
+    self.cache = None;
   }
 
   pub fn get_image(&mut self) -> Option<SkImage>{
@@ -117,6 +189,8 @@ impl PageRecorder{
 pub struct Page{
   pub layers: Vec<Picture>,
   pub bounds: Rect,
+  pub fields: Vec<PlacedField>,  // only included in PDF output
+  pub tags: Vec<Tag>,            // ditto
 }
 
 impl Page{
@@ -134,84 +208,170 @@ impl Page{
     compositor.finish_recording_as_picture(Some(bounds))
   }
 
+  // resolves a 1-based range of layer numbers (where negative values count back from the last layer)
+  pub fn layer_range(&self, (first, last):(i32, i32)) -> Result<RangeInclusive<usize>, String> {
+    let count = self.layers.len() as i32;
+    let index = |num:i32| if num < 0 { count + num } else { num - 1 };
+    let (start, end) = (index(first), index(last));
+    match count > 0 && 0 <= start && start <= end && end < count {
+      true => Ok(start as usize..=end as usize),
+      false => Err(match count {
+        0 => "Page has no layers to export (nothing has been drawn to it)".to_string(),
+        1 => format!("Page only has a ‘layer 1’ (layers {}–{} are out of bounds)", first, last),
+        _ => format!("Page has layers 1–{} (layers {}–{} are out of bounds)", count, first, last),
+      })
+    }
+  }
+
+  // a page with just the given layers (and the fields that were added along with them)
+  fn subset(&self, range:RangeInclusive<usize>) -> Page {
+    let fields = self.fields.iter()
+      .filter(|placed| range.contains(&placed.layer))
+      .map(|placed| PlacedField{ layer:placed.layer - range.start(), ..placed.clone() })
+      .collect();
+    Page{ layers:self.layers[range].to_vec(), bounds:self.bounds, fields, tags:self.tags.clone() }
+  }
+
   pub fn get_image(&self, picture: &Picture, color_space: impl Into<Option<ColorSpace>>, bit_depth: Option<BitDepth>) -> Result<SkImage, String> {
     SkImage::from_picture(picture, self.bounds.size().to_floor(), None, None, bit_depth.unwrap_or(BitDepth::U8), color_space)
     .ok_or("Error generating image".to_string())
   }
 
-  pub fn get_pixels(&self, buffer: &mut [u8], picture: &Picture, info: &ImageInfo/* , origin: impl Into<IPoint> */) -> bool {
+  pub fn get_pixels(&self, buffer: &mut [u8], picture: &Picture, info: &ImageInfo, row_bytes: usize/* , origin: impl Into<IPoint> */) -> bool {
     if let Ok(img) = self.get_image(picture, info.color_space(), None) {
       let bounds: IRect = picture.cull_rect().round_in();
       // println!("get_pixels() bounds {:?}; imgBounds: {:?}; infoBounds: {:?}", bounds, img.bounds(), info.bounds());
-      return img.read_pixels(&info, buffer, info.min_row_bytes(), (bounds.left, bounds.top), CachingHint::Allow);
+      return img.read_pixels(&info, buffer, row_bytes, (bounds.left, bounds.top), CachingHint::Allow);
     }
     false
   }
 
-  #[allow(clippy::too_many_arguments)]
-  pub fn encoded_as(&self,
-      format:&str,
-      quality:f32,
-      density:f32,
-      outline:bool,
-      matte:Option<Color>,
-      bounds: Option<Rect>,           // clip area, for raster or raw
-      premultiplied:Option<bool>,     // for raw
-      color_type: Option<ColorType>,  // for raw
-      engine:RenderingEngine
-  ) -> Result<Data, String> {
+  pub fn render_bounds(&self, bounds:Option<Rect>) -> Result<Rect, String> {
     let render_bounds = bounds.unwrap_or(self.bounds);
-    if render_bounds.is_empty() || !self.bounds.intersects(render_bounds) {
-      return Err(
+    match render_bounds.is_empty() || !self.bounds.intersects(render_bounds) {
+      true => Err(
         format!("Render bounds ({:?}) must be non-empty and intersect the canvas bounds ({:?}).", render_bounds, self.bounds)
-      );
+      ),
+      false => Ok(render_bounds)
     }
+  }
+
+  pub fn rasterize(&self, picture:&Picture, render_bounds:&Rect, density:f32, engine:RenderingEngine) -> Result<SkImage, String> {
+    self.rasterize_as(picture, render_bounds, density, ColorType::N32, ColorSpace::new_srgb(), engine)
+  }
+
+  pub fn rasterize_as(&self, picture:&Picture, render_bounds:&Rect, density:f32, color_type:ColorType, color_space:ColorSpace, engine:RenderingEngine) -> Result<SkImage, String> {
+    let img_scale = Matrix::scale((density, density));
+    // start with full `self.bounds` size since image_snapshot_with_bounds() will crop as needed
+    let img_dims = Size::new(self.bounds.width() * density, self.bounds.height() * density).to_floor();
+    let img_info = ImageInfo::new(img_dims, color_type, AlphaType::Premul, Some(color_space));
+    // let img_info = make_raw_image_info(img_dims, premultiplied, color_type);  // let surface dictate color type?
+
+    if let Some(mut surface) = engine.get_surface(&img_info){
+      surface
+        .canvas()
+        .set_matrix(&img_scale.into())
+        .draw_picture(&picture, None, None);
+      // This unwrap() should never panic because the caller has validated the render bounds already.
+      let crop = img_scale.map_rect(render_bounds).0;
+      Ok(surface.image_snapshot_with_bounds(&crop.round_in()).unwrap())
+    }else{
+      Err(format!("Could not allocate new {}×{} bitmap", img_dims.width, img_dims.height))
+    }
+  }
+
+  // render just a portion of the page (in output pixel coordinates) so that very large exports can be
+  // assembled from strips or tiles rather than allocating a single bitmap for the whole thing
+  #[allow(clippy::too_many_arguments)]
+  pub fn rasterize_region(&self, picture:&Picture, region:&IRect, scale:f32, color_type:ColorType, color_space:ColorSpace, engine:RenderingEngine) -> Result<SkImage, String> {
+    let img_info = ImageInfo::new(region.size(), color_type, AlphaType::Premul, Some(color_space));
+    let mut matrix = Matrix::translate((-region.left as f32, -region.top as f32));
+    matrix.pre_scale((scale, scale), None);
+
+    if let Some(mut surface) = engine.get_surface(&img_info){
+      surface
+        .canvas()
+        .set_matrix(&matrix.into())
+        .draw_picture(&picture, None, None);
+      Ok(surface.image_snapshot())
+    }else{
+      Err(format!("Could not allocate new {}×{} bitmap", region.width(), region.height()))
+    }
+  }
+
+  pub fn as_frame(&self, density:f32, matte:Option<Color>, bounds:Option<Rect>, engine:RenderingEngine) -> Result<SkImage, String> {
+    let render_bounds = self.render_bounds(bounds)?;
     let picture = self.get_picture(matte, Some(&render_bounds)).ok_or("Could not generate picture")?;
+    self.rasterize(&picture, &render_bounds, density, engine)
+  }
 
-    let img_format = match format {
-      "jpg" | "jpeg" => Some(EncodedImageFormat::JPEG),
-      "png" => Some(EncodedImageFormat::PNG),
-      _ => None
-    };
+  pub fn as_icon_frames(&self, matte:Option<Color>, bounds:Option<Rect>, engine:RenderingEngine) -> Result<Vec<SkImage>, String> {
+    let render_bounds = self.render_bounds(bounds)?;
+    let longest_side = render_bounds.width().max(render_bounds.height());
+    raster::ICON_SIZES
+      .iter()
+      // (nudge the scale factor up slightly so rounding errors can't leave an icon a pixel short)
+      .map(|size| self.as_frame((*size as f32 + 0.001) / longest_side, matte, bounds, engine))
+      .collect()
+  }
 
-    if let Some(img_format) = img_format {
-      let img_scale = Matrix::scale((density, density));
-      // start with full `self.bounds` size since image_snapshot_with_bounds() will crop as needed
-      let img_dims = Size::new(self.bounds.width() * density, self.bounds.height() * density).to_floor();
-      let img_info = ImageInfo::new_n32_premul(img_dims, Some(ColorSpace::new_srgb()));
-      // let img_info = make_raw_image_info(img_dims, premultiplied, color_type);  // let surface dictate color type?
-
-      if let Some(mut surface) = engine.get_surface(&img_info){
-        surface
-          .canvas()
-          .set_matrix(&img_scale.into())
-          .draw_picture(&picture, None, None);
-        // This unwrap() should never panic because we validated the render bounds already.
-        surface.image_snapshot_with_bounds(&render_bounds.round_in()).unwrap()
-          .encode_to_data_with_quality(img_format, (quality*100.0) as i32)
-          .map(|data| with_dpi(data, img_format, density))
-          .ok_or(format!("Could not encode as {}", format))
-      }else{
-        Err(format!("Could not allocate new {}×{} bitmap", img_dims.width, img_dims.height))
-      }
+  pub fn encoded_as(&self, options:&ExportOptions, engine:RenderingEngine) -> Result<Data, String> {
+    let ExportOptions{ ref format, quality, density, outline, matte, bounds, premultiplied, color_type, lossless,
+                       ref color_space, bit_depth, ref metadata, svg:ref svg_options, .. } = *options;
+    let format = format.as_str();
+    let render_bounds = self.render_bounds(bounds)?;
+    let picture = self.get_picture(matte, Some(&render_bounds)).ok_or("Could not generate picture")?;
+
+    if format == "png" {
+      let mut png = vec![];
+      self.write_png(&mut png, &picture, &render_bounds, options, engine)?;
+      Ok(Data::new_copy(&png))
+    }
+    else if let Some(img_format) = encoding::to_image_format(format) {
+      // the encoders embed an ICC profile for the image's color space
+      let color_type = match bit_depth {
+        BitDepth::F16 => ColorType::RGBAF16,
+        _ => ColorType::N32
+      };
+      let image = self.rasterize_as(&picture, &render_bounds, density, color_type, color_space.clone(), engine)?;
+      encoding::encode_image(&image, img_format, &options.image())
+    }
+    else if format == "gif" || format == "apng" {
+      let frame = self.rasterize(&picture, &render_bounds, density, engine)?;
+      animation::encode(&[frame], format, quality, lossless, &Animation::default())
+        .and_then(|data| match format {
+          "apng" => metadata.embed(data, EncodedImageFormat::PNG, density),
+          _ => Ok(data)
+        })
+    }
+    else if format == "tiff" || format == "bmp" {
+      let frame = self.rasterize(&picture, &render_bounds, density, engine)?;
+      raster::encode(&[frame], format, density)
+    }
+    else if format == "ico" {
+      raster::encode(&self.as_icon_frames(matte, bounds, engine)?, format, density)
     }
     else if format == "pdf" {
-      let mut document = pdf_document(quality, density).begin_page(self.bounds.size().to_floor(), None);
-      let canvas = document.canvas();
+      let mut document = pdf_document(quality, density, &PdfOptions::default(), StructureTree::new(&[self.tags.as_slice()]))?;
+      let canvas = document.begin_page(self.bounds.size().to_floor(), None)?;
       canvas.draw_picture(&picture, None, None);
-      Ok(document.end_page().close())
+      document.end_page();
+      Ok(document.close())
     }
     else if format == "svg" {
       let flags = outline.then(|| Flags::CONVERT_TEXT_TO_PATHS);
       let mut canvas = svg::Canvas::new(Rect::from_size(self.bounds.size()), flags);
       canvas.draw_picture(&picture, None, None);
-      Ok(canvas.end())
+      svg_options.apply(canvas.end())
+    }
+    else if format == "skp" {
+      Ok(picture.serialize())
     }
     else if format == "raw" {
       let img_dims = render_bounds.size().to_floor();
       let info = make_raw_image_info(img_dims, premultiplied, color_type);
       let mut buffer: Vec<u8> = vec![0; info.compute_min_byte_size()];
-      if self.get_pixels(&mut buffer, &picture, &info /*, (bounds.left.floor() as i32, bounds.top.floor() as i32) */) {
+      if self.get_pixels(&mut buffer, &picture, &info, info.min_row_bytes() /*, (bounds.left.floor() as i32, bounds.top.floor() as i32) */) {
         return Ok(Data::new_copy(&buffer));
       }
       return Err("Could not encode as raw bytes".to_string());
@@ -221,34 +381,66 @@ impl Page{
     }
   }
 
-  #[allow(clippy::too_many_arguments)]
-  pub fn write(&self,
-      filename: &str,
-      file_format:&str,
-      quality:f32,
-      density:f32,
-      outline:bool,
-      matte:Option<Color>,
-      bounds: Option<Rect>,
-      premultiplied:Option<bool>,
-      color_type: Option<ColorType>,
-      engine:RenderingEngine
-  ) -> Result<(), String> {
+  // PNGs are written to `out` as they're compressed (unless Skia is doing the encoding), everything else in one piece
+  pub fn write_as(&self, out:&mut dyn Write, options:&ExportOptions, engine:RenderingEngine) -> Result<(), String> {
+    if options.format == "png" {
+      let render_bounds = self.render_bounds(options.bounds)?;
+      let picture = self.get_picture(options.matte, Some(&render_bounds)).ok_or("Could not generate picture")?;
+      self.write_png(out, &picture, &render_bounds, options, engine)
+    }else{
+      let data = self.encoded_as(options, engine)?;
+      out.write_all(data.as_bytes()).map_err(|err| err.to_string())
+    }
+  }
+
+  fn write_png(&self, out:&mut dyn Write, picture:&Picture, render_bounds:&Rect, options:&ExportOptions, engine:RenderingEngine) -> Result<(), String> {
+    let ExportOptions{ density, ref color_space, bit_depth, ref encoding, ref metadata, .. } = *options;
+
+    // the encoders embed an ICC profile for the image's color space (and write 16-bit PNGs from F16 images)
+    let color_type = match bit_depth {
+      BitDepth::F16 => ColorType::RGBAF16,
+      _ => ColorType::N32
+    };
+    match self.rasterize_as(picture, render_bounds, density, color_type, color_space.clone(), engine) {
+      Ok(image) => encoding::write_image(&image, EncodedImageFormat::PNG, &options.image(), out),
+      // if the output is too large for a single bitmap, it can still be rendered and compressed a strip at a time
+      Err(_) => {
+        let region = Matrix::scale((density, density)).map_rect(render_bounds).0.round_in();
+        let encoding = encoding.clone().unwrap_or_default();
+        encoding::write_png_strips(&region, bit_depth, &encoding, &metadata.png_chunks(density), out, |strip|
+          self.rasterize_region(picture, strip, density, color_type, color_space.clone(), engine)
+        )
+      }
+    }
+  }
+
+  pub fn write(&self, filename:&str, options:&ExportOptions, engine:RenderingEngine) -> Result<(), String> {
     let path = FilePath::new(&filename);
-    let data = self.encoded_as(file_format, quality, density, outline, matte, bounds, premultiplied, color_type, engine)?;
-    fs::write(path, data.as_bytes()).map_err(|why|
-      format!("{}: \"{}\"", why, path.display())
-    )
+    let io_error = |why:std::io::Error| format!("{}: \"{}\"", why, path.display());
+    if options.format == "svg" && options.svg.images == SvgImages::Files {
+      let data = svg_output::write_images(self.encoded_as(options, engine)?, filename)?;
+      return fs::write(path, data.as_bytes()).map_err(io_error)
+    }
+
+    // write the file as it's encoded, deleting it if the export fails partway through
+    let mut file = BufWriter::new(File::create(path).map_err(io_error)?);
+    let result = self.write_as(&mut file, options, engine).and_then(|_| file.flush().map_err(io_error));
+    drop(file);
+    if result.is_err(){
+      fs::remove_file(path).ok();
+    }
+    result
   }
 
-  fn append_to(&self, doc:Document, matte:Option<Color>) -> Result<Document, String>{
+  fn append_to(&self, doc:&mut PdfDocument, matte:Option<Color>, setup:Option<&PageSetup>) -> Result<(), String>{
     if !self.bounds.is_empty(){
-      let mut doc = doc.begin_page(self.bounds.size(), None);
-      let canvas = doc.canvas();
+      let setup = setup.cloned().unwrap_or_default();
+      let canvas = doc.begin_page(setup.media_size(&self.bounds), Some(&setup.content_rect(&self.bounds)))?;
       if let Some(picture) = self.get_picture(matte, None){
         canvas.draw_picture(&picture, None, None);
       }
-      Ok(doc.end_page())
+      doc.end_page();
+      Ok(())
     }else{
       Err("Width and height must be non-zero to generate a PDF page".to_string())
     }
@@ -256,18 +448,91 @@ impl Page{
 }
 
 
+//
+// Settings for encoding a sequence of pages (shared by the toBuffer, toStream, and save methods)
+//
+
+pub struct ExportOptions{
+  pub format: String,
+  pub quality: f32,
+  pub density: f32,
+  pub outline: bool,                  // for svg
+  pub matte: Option<Color>,
+  pub bounds: Option<Rect>,           // clip area, for raster or raw
+  pub premultiplied: Option<bool>,    // for raw
+  pub color_type: Option<ColorType>,  // for raw
+  pub lossless: bool,                 // for webp
+  pub animation: Option<Animation>,   // for gif, apng, or webp
+  pub color_space: ColorSpace,        // for png, jpg, or webp
+  pub bit_depth: BitDepth,            // for png
+  pub encoding: Option<Encoding>,     // for png or jpg
+  pub metadata: ImageMetadata,        // for png, jpg, webp, or apng
+  pub pdf: PdfOptions,                // for pdf
+  pub svg: SvgOptions,                // for svg
+}
+
+impl ExportOptions{
+  // the settings that apply to encoding a bitmap rendered from the page
+  pub fn image(&self) -> ImageOptions{
+    let ExportOptions{ quality, density, lossless, bit_depth, ref encoding, ref metadata, .. } = *self;
+    ImageOptions{ quality, density, lossless, bit_depth, encoding:encoding.clone(), metadata:metadata.clone() }
+  }
+}
+
 //
 // Container for a canvas's entire stack of page contexts
 //
 
 pub struct PageSequence{
   pub pages: Vec<Page>,
-  pub engine: RenderingEngine
+  pub engine: RenderingEngine,
+  pub progress: Progress,
+  pub setup: Vec<PageSetup>,
 }
 
 impl PageSequence{
   pub fn from(pages:Vec<Page>, engine:RenderingEngine) -> Self{
-    PageSequence { pages, engine }
+    PageSequence { pages, engine, progress:Progress::default(), setup:vec![] }
+  }
+
+  // sets the sheet size & print boxes for PDF output (with the last entry applying to any remaining pages)
+  pub fn with_page_setup(self, setup:Vec<PageSetup>) -> Self{
+    PageSequence { setup, ..self }
+  }
+
+  fn page_setup(&self, pp:usize) -> Option<&PageSetup>{
+    self.setup.get(pp).or_else(|| self.setup.last())
+  }
+
+  // lets a background export be cancelled between pages and report each one as it's completed
+  pub fn with_progress(self, progress:Progress) -> Self{
+    let progress = progress.with_total(self.pages.len());
+    PageSequence { progress, ..self }
+  }
+
+  // narrows each page down to a range of its layers, optionally splitting every layer off into a page of its own
+  pub fn with_layers(self, range:Option<(i32, i32)>, flatten:bool) -> Result<Self, String>{
+    if range.is_none() && flatten {
+      return Ok(self)
+    }
+
+    let mut pages = vec![];
+    for page in self.pages {
+      let range = match range {
+        Some(range) => page.layer_range(range)?,
+        None if page.layers.is_empty() => continue,
+        None => 0..=page.layers.len() - 1
+      };
+      match flatten {
+        true => pages.push(page.subset(range)),
+        false => pages.extend(range.map(|idx| page.subset(idx..=idx)))
+      }
+    }
+
+    match pages.is_empty() {
+      true => Err("Page has no layers to export (nothing has been drawn to it)".to_string()),
+      false => Ok(PageSequence::from(pages, self.engine))
+    }
   }
 
   pub fn first(&self) -> &Page {
@@ -278,41 +543,97 @@ impl PageSequence{
     self.pages.len()
   }
 
-  pub fn as_pdf(&self, quality:f32, density:f32, matte:Option<Color>) -> Result<Data, String>{
-    self.pages
+  pub fn as_pdf(&self, quality:f32, density:f32, matte:Option<Color>, options:&PdfOptions) -> Result<Data, String>{
+    let tags:Vec<_> = self.pages.iter().map(|page| page.tags.as_slice()).collect();
+    let data = self.pages
+      .iter()
+      .enumerate()
+      .try_fold(pdf_document(quality, density, options, StructureTree::new(&tags))?, |mut doc, (pp, page)| -> Result<PdfDocument, String> {
+        self.progress.check()?;
+        page.append_to(&mut doc, matte, self.page_setup(pp))?;
+        self.progress.advance();
+        Ok(doc)
+      })
+      .map(|doc| doc.close())?;
+
+    let boxes:Vec<_> = self.pages
       .iter()
-      .try_fold(pdf_document(quality, density), |doc, page| page.append_to(doc, matte))
-      .map(|doc| doc.close())
+      .enumerate()
+      .map(|(pp, page)| self.page_setup(pp).map(|setup| setup.boxes(&page.bounds)))
+      .collect();
+    let fields:Vec<_> = self.pages
+      .iter()
+      .enumerate()
+      .map(|(pp, page)| {
+        let setup = self.page_setup(pp).cloned().unwrap_or_default();
+        page.fields.iter().map(|placed| PlacedField{ rect:setup.sheet_rect(&page.bounds, &placed.rect), ..placed.clone() }).collect()
+      })
+      .collect();
+    options.finish(data, &boxes, &fields)
   }
 
-  #[allow(clippy::too_many_arguments)]
-  pub fn write_image(&self,
-      pattern:&str,
-      format:&str,
-      quality:f32,
-      density:f32,
-      outline:bool,
-      matte:Option<Color>,
-      bounds: Option<Rect>,
-      premultiplied:Option<bool>,
-      color_type: Option<ColorType>
-    ) -> Result<(), String>{
-    self.first().write(&pattern, &format, quality, density, outline, matte, bounds, premultiplied, color_type, self.engine)
+  // encodes the pages as a single file in whichever form the options call for
+  pub fn encoded(&self, options:&ExportOptions) -> Result<Data, String>{
+    let ExportOptions{ ref format, quality, density, matte, bounds, ref pdf, .. } = *options;
+    if let Some(animation) = &options.animation {
+      self.as_animation(animation, options)
+    }else if format == "pdf" {
+      self.as_pdf(quality, density, matte, pdf)
+    }else if format == "tiff" || format == "ico" {
+      self.as_multipage(format, density, matte, bounds)
+    }else{
+      self.as_image(options)
+    }
   }
 
-  #[allow(clippy::too_many_arguments)]
-  pub fn write_sequence(&self,
-      pattern:&str,
-      format:&str,
-      padding:f32,
-      quality:f32,
-      density:f32,
-      outline:bool,
-      matte:Option<Color>,
-      bounds: Option<Rect>,
-      premultiplied:Option<bool>,
-      color_type: Option<ColorType>
-  ) -> Result<(), String>{
+  // like `encoded` but writes to `out`, which single-page PNGs are streamed to as they're compressed
+  pub fn write_to(&self, out:&mut dyn Write, options:&ExportOptions) -> Result<(), String>{
+    let ExportOptions{ ref format, .. } = *options;
+    if options.animation.is_some() || format == "pdf" || format == "tiff" || format == "ico" {
+      let data = self.encoded(options)?;
+      out.write_all(data.as_bytes()).map_err(|err| err.to_string())
+    }else{
+      self.progress.check()?;
+      self.first().write_as(out, options, self.engine)?;
+      self.progress.advance();
+      Ok(())
+    }
+  }
+
+  // writes the pages to a single file (or, if `padding` is defined, to one file per page using `path` as a pattern)
+  pub fn write(&self, path:&str, padding:Option<f32>, options:&ExportOptions) -> Result<(), String>{
+    let ExportOptions{ ref format, quality, density, matte, bounds, ref pdf, .. } = *options;
+    if let Some(padding) = padding {
+      self.write_sequence(path, padding, options)
+    }else if let Some(animation) = &options.animation {
+      self.write_animation(path, animation, options)
+    }else if format == "pdf" {
+      self.write_pdf(path, quality, density, matte, pdf)
+    }else if format == "tiff" || format == "ico" {
+      self.write_multipage(path, format, density, matte, bounds)
+    }else{
+      self.write_image(path, options)
+    }
+  }
+
+  pub fn write_image(&self, pattern:&str, options:&ExportOptions) -> Result<(), String>{
+    self.progress.check()?;
+    self.first().write(pattern, options, self.engine)?;
+    self.progress.advance();
+    Ok(())
+  }
+
+  pub fn as_image(&self, options:&ExportOptions) -> Result<Data, String>{
+    self.progress.check()?;
+    let data = self.first().encoded_as(options, self.engine)?;
+    self.progress.advance();
+    Ok(data)
+  }
+
+  pub fn write_sequence(&self, pattern:&str, padding:f32, options:&ExportOptions) -> Result<(), String>{
+    let ExportOptions{ ref format, quality, density, matte, .. } = *options;
+    // bookmarks and page labels refer to pages of the whole document, so they are dropped from single-page files
+    let pdf_options = PdfOptions{ bookmarks:vec![], labels:vec![], ..options.pdf.clone() };
     let padding = match padding as i32{
       -1 => (1.0 + (self.pages.len() as f32).log10().floor()) as usize,
       pad => pad as usize
@@ -322,15 +643,76 @@ impl PageSequence{
       .par_iter()
       .enumerate()
       .try_for_each(|(pp, page)|{
+        self.progress.check()?;
         let folio = format!("{:0width$}", pp+1, width=padding);
         let filename = pattern.replace("{}", folio.as_str());
-        page.write(&filename, format, quality, density, outline, matte, bounds, premultiplied, color_type, self.engine)
+        match format.as_str() {
+          "pdf" => PageSequence::from(vec![page.clone()], self.engine)
+            .with_page_setup(self.page_setup(pp).cloned().into_iter().collect())
+            .write_pdf(&filename, quality, density, matte, &pdf_options),
+          _ => page.write(&filename, options, self.engine)
+        }?;
+        self.progress.advance();
+        Ok(())
+      })
+  }
+
+  fn as_frames(&self, density:f32, matte:Option<Color>, bounds:Option<Rect>) -> Result<Vec<SkImage>, String>{
+    self.pages
+      .par_iter()
+      .map(|page|{
+        self.progress.check()?;
+        let frame = page.as_frame(density, matte, bounds, self.engine)?;
+        self.progress.advance();
+        Ok(frame)
+      })
+      .collect()
+  }
+
+  pub fn as_multipage(&self, format:&str, density:f32, matte:Option<Color>, bounds:Option<Rect>) -> Result<Data, String>{
+    // a single-page icon is rendered at multiple sizes rather than just the page's own
+    let frames = match format == "ico" && self.len() == 1 {
+      true => {
+        self.progress.check()?;
+        let frames = self.first().as_icon_frames(matte, bounds, self.engine)?;
+        self.progress.advance();
+        frames
+      },
+      false => self.as_frames(density, matte, bounds)?
+    };
+    raster::encode(&frames, format, density)
+  }
+
+  pub fn write_multipage(&self, path:&str, format:&str, density:f32, matte:Option<Color>, bounds:Option<Rect>) -> Result<(), String>{
+    let path = FilePath::new(&path);
+    let data = self.as_multipage(format, density, matte, bounds)?;
+    fs::write(path, data.as_bytes()).map_err(|why|
+      format!("{}: \"{}\"", why, path.display())
+    )
+  }
+
+  pub fn as_animation(&self, animation:&Animation, options:&ExportOptions) -> Result<Data, String>{
+    let ExportOptions{ ref format, quality, density, matte, bounds, lossless, ref metadata, .. } = *options;
+    let frames = self.as_frames(density, matte, bounds)?;
+    animation::encode(&frames, format, quality, lossless, animation)
+      .and_then(|data| match format.as_str() {
+        "apng" => metadata.embed(data, EncodedImageFormat::PNG, density),
+        "webp" => metadata.embed(data, EncodedImageFormat::WEBP, density),
+        _ => Ok(data)
       })
   }
 
-  pub fn write_pdf(&self, path:&str, quality:f32, density:f32, matte:Option<Color>) -> Result<(), String>{
+  pub fn write_animation(&self, path:&str, animation:&Animation, options:&ExportOptions) -> Result<(), String>{
     let path = FilePath::new(&path);
-    match self.as_pdf(quality, density, matte){
+    let data = self.as_animation(animation, options)?;
+    fs::write(path, data.as_bytes()).map_err(|why|
+      format!("{}: \"{}\"", why, path.display())
+    )
+  }
+
+  pub fn write_pdf(&self, path:&str, quality:f32, density:f32, matte:Option<Color>, options:&PdfOptions) -> Result<(), String>{
+    let path = FilePath::new(&path);
+    match self.as_pdf(quality, density, matte, options){
       Ok(document) => fs::write(path, document.as_bytes()).map_err(|why|
         format!("{}: \"{}\"", why, path.display())
       ),
@@ -344,6 +726,39 @@ impl PageSequence{
 // Helpers
 //
 
+pub fn layers_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Option<(i32, i32)>> {
+  let range = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsArray, _>(cx){
+      Ok(array) => array.to_vec(cx)?,
+      Err(_) => return Ok(None)
+    },
+    None => return Ok(None)
+  };
+
+  match floats_in(cx, &range).as_slice() {
+    [first, last] => Ok(Some((*first as i32, *last as i32))),
+    _ => cx.throw_type_error("Expected a [first, last] pair of layer numbers")
+  }
+}
+
+// deserializes an .skp file, shifting its contents so the top-left corner of its cull rect lies at the origin
+pub fn picture_from_data(data:&[u8]) -> Option<Picture> {
+  let picture = Picture::from_bytes(data)?;
+  let cull = picture.cull_rect();
+  if cull.is_empty() {
+    return None
+  }else if cull.left == 0.0 && cull.top == 0.0 {
+    return Some(picture)
+  }
+
+  let mut recorder = PictureRecorder::new();
+  let canvas = recorder.begin_recording(Rect::from_size(cull.size()), None);
+  canvas.clip_rect(Rect::from_size(cull.size()), None, None);
+  canvas.translate((-cull.left, -cull.top));
+  canvas.draw_picture(&picture, None, None);
+  recorder.finish_recording_as_picture(None)
+}
+
 pub fn pages_arg(cx: &mut FunctionContext, idx: i32, canvas:&BoxedCanvas) -> NeonResult<PageSequence> {
   let engine = canvas.borrow().engine;
   let pages = cx.argument::<JsArray>(idx)?
@@ -356,40 +771,58 @@ pub fn pages_arg(cx: &mut FunctionContext, idx: i32, canvas:&BoxedCanvas) -> Neo
   Ok(PageSequence::from(pages, engine))
 }
 
-fn pdf_document(quality:f32, density:f32) -> Document{
+// reads the options object passed to the export methods, applying its layer, transform, page setup,
+// and progress settings to the pages (which may change the number of pages or their bounds)
+pub fn export_options_arg(cx: &mut FunctionContext, idx: usize, pages:PageSequence, size:Size) -> NeonResult<(PageSequence, ExportOptions)> {
+  let obj = cx.argument::<JsObject>(idx as i32)?;
+  let format = string_for_key(cx, &obj, "format")?;
+  let quality = float_for_key(cx, &obj, "quality")?;
+  let density = float_for_key(cx, &obj, "density")?;
+  let outline = opt_bool_for_key(cx, &obj, "outline").unwrap_or(false);
+  let matte = opt_color_for_key(cx, &obj, "matte");
+  let bounds = opt_rect_for_key(cx, &obj, "bounds", size);
+  let premultiplied = opt_bool_for_key(cx, &obj, "premultiplied");
+  let color_type = opt_color_type_for_key(cx, &obj, "colorType");
+  let lossless = opt_bool_for_key(cx, &obj, "lossless").unwrap_or(false);
+  let animation = animation_for_key(cx, &obj, "animation")?;
+  let color_space = color_space_for_key(cx, &obj, "colorSpace");
+  let bit_depth = bit_depth_for_key(cx, &obj, "bitDepth");
+  let encoding = encoding_for_key(cx, &obj, "encoding")?;
+  let metadata = image_metadata_for_key(cx, &obj, "metadata")?;
+  let bookmarks = bookmarks_for_key(cx, &obj, "bookmarks")?;
+  let pdfa = opt_bool_for_key(cx, &obj, "pdfa").unwrap_or(false);
+  let pdf = PdfOptions::new(metadata.info.clone(), bookmarks, pdfa)
+    .with_page_labels(page_labels_for_key(cx, &obj, "pageLabels")?)
+    .with_attachments(attachments_for_key(cx, &obj, "attachments")?);
+  let svg = svg_options_for_key(cx, &obj, "svg")?;
+
+  let layers = layers_for_key(cx, &obj, "layers")?;
+  let flatten = opt_bool_for_key(cx, &obj, "flatten").unwrap_or(true);
+  let pages = pages.with_layers(layers, flatten)
+    .or_else(|err| cx.throw_range_error(err))?;
+  let (pages, bounds) = pages.with_transform(transform_for_key(cx, &obj, "transform")?, bounds)
+    .or_else(|err| cx.throw_range_error(err))?;
+  let pages = pages
+    .with_page_setup(page_setup_for_key(cx, &obj, "pageSetup")?)
+    .with_progress(progress_for_keys(cx, &obj)?);
+
+  // a field's contents are drawn by the viewer using a font that isn't embedded in the file, which PDF/A forbids
+  if pdfa && format == "pdf" && pages.pages.iter().any(|page| !page.fields.is_empty()) {
+    return cx.throw_type_error("Text fields can't be used in PDF/A documents (set `pdfa` to false or export the pages without their fields)")
+  }
+
+  Ok((pages, ExportOptions{
+    format, quality, density, outline, matte, bounds, premultiplied, color_type, lossless, animation,
+    color_space, bit_depth, encoding, metadata, pdf, svg
+  }))
+}
+
+fn pdf_document(quality:f32, density:f32, options:&PdfOptions, tree:Option<StructureTree>) -> Result<PdfDocument, String>{
   let mut meta = pdf::Metadata::default();
+  options.info.apply_to(&mut meta);
+  meta.pdfa = options.pdfa;
   meta.producer = "Skia Canvas <https://github.com/samizdatco/skia-canvas>".to_string();
   meta.encoding_quality = Some((quality*100.0) as i32);
   meta.raster_dpi = Some(density * 72.0);
-  pdf::new_document(Some(&meta))
-}
-
-fn with_dpi(data:Data, format:EncodedImageFormat, density:f32) -> Data{
-  if density as u32 == 1 { return data }
-
-  let mut bytes = data.as_bytes().to_vec();
-  match format{
-    EncodedImageFormat::JPEG => {
-      let [l, r] = (72 * density as u16).to_be_bytes();
-      bytes.splice(13..18, [1, l, r, l, r].iter().cloned());
-      Data::new_copy(&bytes)
-    }
-    EncodedImageFormat::PNG => {
-      let mut digest = CRC32.digest();
-      let [a, b, c, d] = ((72.0 * density * 39.3701) as u32).to_be_bytes();
-      let phys = vec![
-        b'p', b'H', b'Y', b's',
-        a, b, c, d, // x-dpi
-        a, b, c, d, // y-dpi
-        1, // dots per meter
-      ];
-      digest.update(&phys);
-
-      let length = 9u32.to_be_bytes().to_vec();
-      let checksum = digest.finalize().to_be_bytes().to_vec();
-      bytes.splice(33..33, [length, phys, checksum].concat());
-      Data::new_copy(&bytes)
-    }
-    _ => data
-  }
+  PdfDocument::new(&meta, tree)
 }
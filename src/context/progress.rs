@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use neon::prelude::*;
+use crate::utils::opt_value_for_key;
+
+//
+// Cancellation & progress reporting for background exports
+//
+
+// a flag shared between js (which sets it when an AbortSignal fires) and the export task (which checks it)
+pub type BoxedCancelToken = JsBox<CancelToken>;
+impl Finalize for CancelToken {}
+
+#[derive(Clone, Default)]
+pub struct CancelToken{
+  cancelled: Arc<AtomicBool>
+}
+
+#[derive(Clone)]
+struct Reporter{
+  callback: Arc<Root<JsFunction>>,
+  channel: Channel,
+  done: Arc<AtomicUsize>,
+}
+
+#[derive(Clone, Default)]
+pub struct Progress{
+  token: Option<CancelToken>,
+  reporter: Option<Reporter>,
+  total: usize,
+}
+
+impl Progress{
+  pub fn with_total(self, total:usize) -> Self {
+    Progress{ total, ..self }
+  }
+
+  // called before starting work on each page so a cancelled export can bail out early
+  pub fn check(&self) -> Result<(), String> {
+    match &self.token {
+      Some(token) if token.cancelled.load(Ordering::Relaxed) => Err("The export was cancelled".to_string()),
+      _ => Ok(())
+    }
+  }
+
+  // called once each page is complete (the count is updated on the js thread so it always increases monotonically)
+  pub fn advance(&self) {
+    if let Some(Reporter{callback, channel, done}) = &self.reporter {
+      let (callback, done, total) = (Arc::clone(callback), Arc::clone(done), self.total);
+      channel.send(move |mut cx| {
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        let callback = callback.to_inner(&mut cx);
+        let this = cx.undefined();
+        let args = vec![cx.number(done as f64).upcast::<JsValue>(), cx.number(total as f64).upcast()];
+        callback.call(&mut cx, this, args)?;
+        Ok(())
+      });
+    }
+  }
+}
+
+//
+// -- Javascript Methods --------------------------------------------------------------------------
+//
+
+pub fn new(mut cx: FunctionContext) -> JsResult<BoxedCancelToken> {
+  Ok(cx.boxed(CancelToken::default()))
+}
+
+pub fn cancel(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedCancelToken>(0)?;
+  this.cancelled.store(true, Ordering::Relaxed);
+  Ok(cx.undefined())
+}
+
+//
+// Argument parsing
+//
+
+// reads an optional cancel token and progress callback from an options object's `token` & `onProgress` entries
+pub fn progress_for_keys(cx: &mut FunctionContext, options: &Handle<JsObject>) -> NeonResult<Progress> {
+  let token = opt_value_for_key(cx, options, "token")
+    .and_then(|val| val.downcast::<BoxedCancelToken, _>(cx).ok())
+    .map(|token| (**token).clone());
+
+  let reporter = opt_value_for_key(cx, options, "onProgress")
+    .and_then(|val| val.downcast::<JsFunction, _>(cx).ok())
+    .map(|callback| Reporter{
+      callback: Arc::new(callback.root(cx)),
+      channel: cx.channel(),
+      done: Arc::new(AtomicUsize::new(0)),
+    });
+
+  Ok(Progress{ token, reporter, total:0 })
+}
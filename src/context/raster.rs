@@ -0,0 +1,309 @@
+//
+// TIFF, BMP, and ICO files written from rendered pages
+//
+
+use std::collections::HashMap;
+use skia_safe::{Data, Image as SkImage, ImageInfo, ColorType, AlphaType, EncodedImageFormat};
+use skia_safe::image::CachingHint;
+
+// the resolutions generated when making an icon from a single page
+pub const ICON_SIZES:[i32; 6] = [256, 128, 64, 48, 32, 16];
+
+pub fn encode(frames:&[SkImage], format:&str, density:f32) -> Result<Data, String>{
+  if frames.is_empty(){
+    return Err(format!("Cannot create a {} file without any pages", format.to_uppercase()))
+  }
+
+  match format {
+    "tiff" => encode_tiff(frames, density),
+    "bmp" => encode_bmp(&frames[0], density),
+    "ico" => encode_ico(frames),
+    _ => Err(format!("Unsupported file format {}", format))
+  }
+}
+
+// read back a frame's unpremultiplied pixels in the specified channel order
+pub fn unpremultiplied_pixels(frame:&SkImage, color_type:ColorType) -> Result<Vec<u8>, String>{
+  let info = ImageInfo::new(frame.dimensions(), color_type, AlphaType::Unpremul, None);
+  let mut pixels = vec![0u8; info.compute_min_byte_size()];
+  match frame.read_pixels(&info, &mut pixels, info.min_row_bytes(), (0, 0), CachingHint::Allow){
+    true => Ok(pixels),
+    false => Err("Could not read pixels from rendered page".to_string())
+  }
+}
+
+fn pixels_per_meter(density:f32) -> u32{
+  (72.0 * density * 39.3701).round() as u32
+}
+
+//
+// TIFF
+//
+
+const SHORT:u16 = 3;
+const LONG:u16 = 4;
+const RATIONAL:u16 = 5;
+
+struct IfdEntry{ tag:u16, kind:u16, values:Vec<u32> }
+
+impl IfdEntry{
+  fn new(tag:u16, kind:u16, values:&[u32]) -> Self{
+    IfdEntry{ tag, kind, values:values.to_vec() }
+  }
+
+  fn bytes(&self) -> Vec<u8>{
+    self.values.iter().flat_map(|val| match self.kind {
+      SHORT => (*val as u16).to_le_bytes().to_vec(),
+      _ => val.to_le_bytes().to_vec()
+    }).collect()
+  }
+
+  fn count(&self) -> u32{
+    match self.kind{
+      RATIONAL => self.values.len() as u32 / 2,
+      _ => self.values.len() as u32
+    }
+  }
+}
+
+fn encode_tiff(frames:&[SkImage], density:f32) -> Result<Data, String>{
+  let mut tiff = b"II*\0".to_vec();
+  let mut next_ifd_ptr = tiff.len();
+  tiff.extend(0u32.to_le_bytes());
+
+  let dpi = (72.0 * density).round() as u32;
+  let multipage = frames.len() > 1;
+  for (idx, frame) in frames.iter().enumerate(){
+    let (width, height) = (frame.width() as u32, frame.height() as u32);
+    let row_bytes = width as usize * 4;
+    let rows_per_strip = (65536 / row_bytes).max(1);
+
+    // LZW-compress the pixels in strips (with horizontal differencing to improve the compression ratio)
+    let mut pixels = unpremultiplied_pixels(frame, ColorType::RGBA8888)?;
+    for row in pixels.chunks_exact_mut(row_bytes){
+      for i in (4..row.len()).rev(){
+        row[i] = row[i].wrapping_sub(row[i-4]);
+      }
+    }
+    let (mut offsets, mut counts) = (vec![], vec![]);
+    for strip in pixels.chunks(rows_per_strip * row_bytes){
+      let compressed = lzw_compress(strip);
+      offsets.push(tiff.len() as u32);
+      counts.push(compressed.len() as u32);
+      tiff.extend(compressed);
+      if tiff.len() % 2 == 1 { tiff.push(0) }
+    }
+
+    let mut entries = vec![
+      IfdEntry::new(254, LONG, &[if multipage { 2 } else { 0 }]), // NewSubfileType
+      IfdEntry::new(256, LONG, &[width]),                          // ImageWidth
+      IfdEntry::new(257, LONG, &[height]),                         // ImageLength
+      IfdEntry::new(258, SHORT, &[8, 8, 8, 8]),                    // BitsPerSample
+      IfdEntry::new(259, SHORT, &[5]),                             // Compression = LZW
+      IfdEntry::new(262, SHORT, &[2]),                             // PhotometricInterpretation = RGB
+      IfdEntry::new(273, LONG, &offsets),                          // StripOffsets
+      IfdEntry::new(277, SHORT, &[4]),                             // SamplesPerPixel
+      IfdEntry::new(278, LONG, &[rows_per_strip as u32]),          // RowsPerStrip
+      IfdEntry::new(279, LONG, &counts),                           // StripByteCounts
+      IfdEntry::new(282, RATIONAL, &[dpi, 1]),                     // XResolution
+      IfdEntry::new(283, RATIONAL, &[dpi, 1]),                     // YResolution
+      IfdEntry::new(284, SHORT, &[1]),                             // PlanarConfiguration = interleaved
+      IfdEntry::new(296, SHORT, &[2]),                             // ResolutionUnit = inches
+    ];
+    if multipage {
+      entries.push(IfdEntry::new(297, SHORT, &[idx as u32, frames.len() as u32])); // PageNumber
+    }
+    entries.push(IfdEntry::new(317, SHORT, &[2]));                 // Predictor = horizontal differencing
+    entries.push(IfdEntry::new(338, SHORT, &[2]));                 // ExtraSamples = unassociated alpha
+
+    // values that don't fit in the 4-byte slot are written ahead of the directory
+    let mut slots = vec![];
+    for entry in &entries{
+      let bytes = entry.bytes();
+      if bytes.len() > 4 {
+        slots.push((tiff.len() as u32).to_le_bytes().to_vec());
+        tiff.extend(bytes);
+      }else{
+        slots.push([bytes, vec![0; 4]].concat()[..4].to_vec());
+      }
+    }
+
+    let ifd_offset = tiff.len() as u32;
+    tiff[next_ifd_ptr..next_ifd_ptr+4].copy_from_slice(&ifd_offset.to_le_bytes());
+    tiff.extend((entries.len() as u16).to_le_bytes());
+    for (entry, slot) in entries.iter().zip(slots){
+      tiff.extend(entry.tag.to_le_bytes());
+      tiff.extend(entry.kind.to_le_bytes());
+      tiff.extend(entry.count().to_le_bytes());
+      tiff.extend(slot);
+    }
+    next_ifd_ptr = tiff.len();
+    tiff.extend(0u32.to_le_bytes());
+  }
+
+  Ok(Data::new_copy(&tiff))
+}
+
+// TIFF-flavored LZW (MSB-first codes that widen one entry ‘early’ compared to GIF)
+fn lzw_compress(data:&[u8]) -> Vec<u8>{
+  const CLEAR_CODE:u16 = 256;
+  const END_CODE:u16 = 257;
+  const FIRST_CODE:u16 = 258;
+  const LAST_CODE:u16 = 4094;
+  let mut next_code = FIRST_CODE;
+  let mut code_size = 9u8;
+  let mut dict:HashMap<(u16, u8), u16> = HashMap::new();
+
+  let mut output:Vec<u8> = vec![];
+  let (mut acc, mut acc_bits) = (0u32, 0u8);
+  let mut emit = |code:u16, size:u8|{
+    acc = (acc << size) | code as u32;
+    acc_bits += size;
+    while acc_bits >= 8 {
+      acc_bits -= 8;
+      output.push((acc >> acc_bits) as u8);
+    }
+    acc &= (1 << acc_bits) - 1;
+  };
+
+  emit(CLEAR_CODE, code_size);
+  let mut prefix:Option<u16> = None;
+  for &k in data {
+    prefix = Some(match prefix {
+      None => k as u16,
+      Some(p) => match dict.get(&(p, k)) {
+        Some(&code) => code,
+        None => {
+          emit(p, code_size);
+          dict.insert((p, k), next_code);
+          next_code += 1;
+          if next_code == LAST_CODE {
+            // the code table is full, so start over with a fresh one
+            emit(CLEAR_CODE, code_size);
+            dict.clear();
+            next_code = FIRST_CODE;
+            code_size = 9;
+          }else if next_code >= 1 << code_size {
+            code_size += 1;
+          }
+          k as u16
+        }
+      }
+    });
+  }
+  if let Some(p) = prefix {
+    emit(p, code_size);
+    // the decoder adds one last table entry after reading the final code (and may widen its codes)
+    next_code += 1;
+    if next_code == LAST_CODE {
+      emit(CLEAR_CODE, code_size);
+      code_size = 9;
+    }else if next_code >= 1 << code_size {
+      code_size += 1;
+    }
+  }
+  emit(END_CODE, code_size);
+  if acc_bits > 0 {
+    output.push((acc << (8 - acc_bits)) as u8); // flush any remaining bits
+  }
+  output
+}
+
+//
+// BMP & ICO
+//
+
+// 32-bit BGRA pixels with the rows in bottom-to-top order
+fn bottom_up_pixels(frame:&SkImage) -> Result<Vec<u8>, String>{
+  let row_bytes = frame.width() as usize * 4;
+  Ok(unpremultiplied_pixels(frame, ColorType::BGRA8888)?.chunks_exact(row_bytes).rev().flatten().copied().collect())
+}
+
+fn encode_bmp(frame:&SkImage, density:f32) -> Result<Data, String>{
+  let pixels = bottom_up_pixels(frame)?;
+  let ppm = pixels_per_meter(density);
+  let header_size = 14 + 108;
+
+  // file header
+  let mut bmp = b"BM".to_vec();
+  bmp.extend((header_size + pixels.len() as u32).to_le_bytes());
+  bmp.extend([0u8; 4]);
+  bmp.extend(header_size.to_le_bytes());
+
+  // BITMAPV4HEADER
+  bmp.extend(108u32.to_le_bytes());
+  bmp.extend(frame.width().to_le_bytes());
+  bmp.extend(frame.height().to_le_bytes());
+  bmp.extend(1u16.to_le_bytes());              // planes
+  bmp.extend(32u16.to_le_bytes());             // bits per pixel
+  bmp.extend(3u32.to_le_bytes());              // BI_BITFIELDS
+  bmp.extend((pixels.len() as u32).to_le_bytes());
+  bmp.extend(ppm.to_le_bytes());
+  bmp.extend(ppm.to_le_bytes());
+  bmp.extend([0u8; 8]);                        // palette size & important colors
+  for mask in [0x00FF0000u32, 0x0000FF00, 0x000000FF, 0xFF000000]{
+    bmp.extend(mask.to_le_bytes());
+  }
+  bmp.extend(b"BGRs");                         // LCS_sRGB
+  bmp.extend([0u8; 48]);                       // unused endpoints & gamma
+
+  bmp.extend(pixels);
+  Ok(Data::new_copy(&bmp))
+}
+
+fn encode_ico(frames:&[SkImage]) -> Result<Data, String>{
+  if frames.iter().any(|frame| frame.width() > 256 || frame.height() > 256){
+    return Err("ICO images can't be larger than 256×256 pixels".to_string())
+  }
+
+  // full-size icons are stored as PNGs, smaller ones as DIBs (for the sake of older readers)
+  let images = frames.iter().map(|frame|{
+    if frame.width() == 256 || frame.height() == 256 {
+      return frame.encode_to_data(EncodedImageFormat::PNG)
+        .map(|data| data.as_bytes().to_vec())
+        .ok_or("Could not encode icon as PNG".to_string())
+    }
+
+    let (width, height) = (frame.width(), frame.height());
+    let pixels = bottom_up_pixels(frame)?;
+    let mask_row_bytes = (width as usize).div_ceil(32) * 4;
+    let mut mask = vec![0u8; mask_row_bytes * height as usize];
+    for (i, px) in pixels.chunks_exact(4).enumerate(){
+      let (x, y) = (i % width as usize, i / width as usize);
+      if px[3] == 0 {
+        mask[y * mask_row_bytes + x / 8] |= 0x80 >> (x % 8);
+      }
+    }
+
+    // BITMAPINFOHEADER (with the height doubled to account for the transparency mask)
+    let mut dib = 40u32.to_le_bytes().to_vec();
+    dib.extend(width.to_le_bytes());
+    dib.extend((height * 2).to_le_bytes());
+    dib.extend(1u16.to_le_bytes());
+    dib.extend(32u16.to_le_bytes());
+    dib.extend(0u32.to_le_bytes());            // BI_RGB
+    dib.extend(((pixels.len() + mask.len()) as u32).to_le_bytes());
+    dib.extend([0u8; 16]);                     // resolution & palette size
+    dib.extend(pixels);
+    dib.extend(mask);
+    Ok(dib)
+  }).collect::<Result<Vec<Vec<u8>>, String>>()?;
+
+  let mut ico = vec![0, 0, 1, 0];
+  ico.extend((frames.len() as u16).to_le_bytes());
+  let mut offset = 6 + 16 * frames.len() as u32;
+  for (frame, image) in frames.iter().zip(&images){
+    ico.push(frame.width() as u8);             // 256 wraps around to 0, as it should
+    ico.push(frame.height() as u8);
+    ico.extend([0u8, 0]);                      // palette size & reserved
+    ico.extend(1u16.to_le_bytes());            // color planes
+    ico.extend(32u16.to_le_bytes());           // bits per pixel
+    ico.extend((image.len() as u32).to_le_bytes());
+    ico.extend(offset.to_le_bytes());
+    offset += image.len() as u32;
+  }
+  for image in images{
+    ico.extend(image);
+  }
+  Ok(Data::new_copy(&ico))
+}
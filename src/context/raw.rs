@@ -0,0 +1,202 @@
+use rayon::prelude::*;
+use neon::prelude::*;
+use skia_safe::{Picture, ImageInfo, ColorType, AlphaType, ColorSpace};
+
+use crate::utils::*;
+use super::page::Page;
+
+//
+// Raw pixel output (interleaved rows with an arbitrary stride, or planar YUV for video pipelines)
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout{ Interleaved, Yuv420, Nv12 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct RawLayout{
+  pub layout: Layout,
+  pub row_bytes: Option<usize>,
+}
+
+impl Default for RawLayout{
+  fn default() -> Self {
+    RawLayout{ layout:Layout::Interleaved, row_bytes:None }
+  }
+}
+
+impl RawLayout{
+  // the narrowest allowable stride for the (luma) rows: nv12 rows have to fit an interleaved u/v pair per 2 pixels
+  fn min_row_bytes(&self, info:&ImageInfo) -> usize {
+    let width = info.width() as usize;
+    match self.layout {
+      Layout::Interleaved => info.min_row_bytes(),
+      Layout::Yuv420 => width,
+      Layout::Nv12 => width.next_multiple_of(2),
+    }
+  }
+
+  pub fn row_bytes(&self, info:&ImageInfo) -> usize {
+    self.row_bytes.unwrap_or_else(|| self.min_row_bytes(info))
+  }
+
+  fn chroma_row_bytes(&self, info:&ImageInfo) -> usize {
+    match self.layout {
+      Layout::Interleaved => 0,
+      Layout::Yuv420 => self.row_bytes(info).div_ceil(2),
+      Layout::Nv12 => self.row_bytes(info),
+    }
+  }
+
+  pub fn byte_size(&self, info:&ImageInfo) -> usize {
+    let height = info.height() as usize;
+    let chroma_height = height.div_ceil(2);
+    match self.layout {
+      Layout::Interleaved => self.row_bytes(info) * height,
+      Layout::Yuv420 => self.row_bytes(info) * height + 2 * self.chroma_row_bytes(info) * chroma_height,
+      Layout::Nv12 => self.row_bytes(info) * height + self.chroma_row_bytes(info) * chroma_height,
+    }
+  }
+
+  pub fn validate(&self, info:&ImageInfo) -> Result<(), String> {
+    let min_row_bytes = self.min_row_bytes(info);
+    match self.row_bytes {
+      Some(row_bytes) if row_bytes < min_row_bytes => Err(
+        format!("rowBytes must be at least {} for a {}-pixel-wide image", min_row_bytes, info.width())
+      ),
+      Some(row_bytes) if self.layout == Layout::Interleaved && row_bytes % info.bytes_per_pixel() != 0 => Err(
+        format!("rowBytes must be a multiple of the color type’s {}-byte pixel size", info.bytes_per_pixel())
+      ),
+      _ => Ok(())
+    }
+  }
+}
+
+// the memory of a js buffer that a task renders into directly. it must come from an ArrayBuffer that was
+// transferred away from the caller (see `transfer_buffer` in io.js) and stay rooted until the task settles,
+// so nothing on the main thread can detach, resize, or collect it in the meantime
+pub struct TransferredBuffer{
+  ptr: *mut u8,
+  len: usize,
+}
+
+unsafe impl Send for TransferredBuffer {}
+
+impl TransferredBuffer{
+  pub fn new(memory:&mut [u8]) -> Self {
+    TransferredBuffer{ ptr:memory.as_mut_ptr(), len:memory.len() }
+  }
+
+  pub fn as_mut_slice(&mut self) -> &mut [u8] {
+    unsafe{ std::slice::from_raw_parts_mut(self.ptr, self.len) }
+  }
+}
+
+impl Page{
+  // renders the picture into `buffer` (which must be at least `layout.byte_size(info)` bytes long)
+  pub fn write_raw(&self, picture:&Picture, info:&ImageInfo, layout:&RawLayout, buffer:&mut [u8]) -> Result<(), String> {
+    let buffer = &mut buffer[..layout.byte_size(info)];
+    match layout.layout {
+      Layout::Interleaved => match self.get_pixels(buffer, picture, info, layout.row_bytes(info)) {
+        true => Ok(()),
+        false => Err("Could not generate raw image".to_string())
+      },
+      _ => {
+        // transparent areas end up black (or the matte color if one was drawn beneath the page)
+        let rgba_info = ImageInfo::new(info.dimensions(), ColorType::RGBA8888, AlphaType::Premul, Some(ColorSpace::new_srgb()));
+        let mut rgba = vec![0u8; rgba_info.compute_min_byte_size()];
+        if !self.get_pixels(&mut rgba, picture, &rgba_info, rgba_info.min_row_bytes()) {
+          return Err("Could not generate raw image".to_string())
+        }
+        to_yuv(&rgba, info.width() as usize, layout, layout.row_bytes(info), layout.chroma_row_bytes(info), buffer);
+        Ok(())
+      }
+    }
+  }
+}
+
+//
+// BT.601 ‘video range’ conversion (matching libyuv's ARGBToI420 & ARGBToNV12)
+//
+
+fn luma(r:i32, g:i32, b:i32) -> u8 {
+  (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8
+}
+
+fn chroma(r:i32, g:i32, b:i32) -> (u8, u8) {
+  let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+  let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+  (u as u8, v as u8)
+}
+
+// the averaged color of the (up to) 2×2 block of pixels that share a chroma sample
+fn block_chroma(rgba:&[u8], width:usize, height:usize, col:usize, row:usize) -> (u8, u8) {
+  let (mut sum, mut count) = ([0i32; 3], 0);
+  for y in (row * 2)..(row * 2 + 2).min(height) {
+    for x in (col * 2)..(col * 2 + 2).min(width) {
+      let px = &rgba[(y * width + x) * 4..];
+      (0..3).for_each(|c| sum[c] += px[c] as i32);
+      count += 1;
+    }
+  }
+  chroma(sum[0] / count, sum[1] / count, sum[2] / count)
+}
+
+fn to_yuv(rgba:&[u8], width:usize, layout:&RawLayout, row_bytes:usize, chroma_row_bytes:usize, buffer:&mut [u8]){
+  let height = rgba.len() / 4 / width;
+  let chroma_width = width.div_ceil(2);
+  let (luma_plane, chroma_planes) = buffer.split_at_mut(row_bytes * height);
+
+  luma_plane.par_chunks_mut(row_bytes).zip(rgba.par_chunks(width * 4)).for_each(|(dst, src)|{
+    dst.iter_mut().zip(src.chunks_exact(4)).for_each(|(y, px)|{
+      *y = luma(px[0] as i32, px[1] as i32, px[2] as i32);
+    })
+  });
+
+  match layout.layout {
+    Layout::Yuv420 => {
+      let (u_plane, v_plane) = chroma_planes.split_at_mut(chroma_planes.len() / 2);
+      u_plane.par_chunks_mut(chroma_row_bytes).zip(v_plane.par_chunks_mut(chroma_row_bytes)).enumerate()
+        .for_each(|(row, (u_row, v_row))|{
+          for col in 0..chroma_width {
+            (u_row[col], v_row[col]) = block_chroma(rgba, width, height, col, row);
+          }
+        });
+    },
+    Layout::Nv12 => {
+      chroma_planes.par_chunks_mut(chroma_row_bytes).enumerate()
+        .for_each(|(row, uv_row)|{
+          for col in 0..chroma_width {
+            (uv_row[col * 2], uv_row[col * 2 + 1]) = block_chroma(rgba, width, height, col, row);
+          }
+        });
+    },
+    Layout::Interleaved => {}
+  }
+}
+
+//
+// Argument parsing
+//
+
+fn to_layout(layout_name:&str) -> Option<Layout>{
+  let layout = match layout_name.to_lowercase().as_str(){
+    "interleaved" => Layout::Interleaved,
+    "yuv420" | "i420" => Layout::Yuv420,
+    "nv12" => Layout::Nv12,
+    _ => return None
+  };
+  Some(layout)
+}
+
+// reads the layout name & row stride from an options object's `layout` & `rowBytes` entries
+pub fn raw_layout_for_keys(cx: &mut FunctionContext, options: &Handle<JsObject>) -> NeonResult<RawLayout>{
+  let layout = match opt_string_for_key(cx, options, "layout") {
+    Some(name) => match to_layout(&name){
+      Some(layout) => layout,
+      None => return cx.throw_type_error("Expected \"interleaved\", \"yuv420\", or \"nv12\" for layout")
+    },
+    None => Layout::Interleaved
+  };
+  let row_bytes = opt_float_for_key(cx, options, "rowBytes").map(|n| n as usize);
+  Ok(RawLayout{ layout, row_bytes })
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::mem::{self, MaybeUninit};
+use std::sync::atomic::{AtomicI32, Ordering};
+use skia_bindings as sb;
+use skia_safe::{pdf, Canvas, Data, Rect, Size};
+
+//
+// Structure tags (marking a page's content as headings, paragraphs, figures, etc. for tagged PDFs)
+//
+
+// the standard structure types from the PDF spec (ISO 32000-1 §14.8.4)
+const STRUCTURE_TYPES:[&str; 46] = [
+  "Document", "Part", "Art", "Sect", "Div", "BlockQuote", "Caption", "TOC", "TOCI", "Index", "NonStruct",
+  "Private", "P", "H", "H1", "H2", "H3", "H4", "H5", "H6", "L", "LI", "Lbl", "LBody", "Table", "TR", "TH",
+  "TD", "THead", "TBody", "TFoot", "Span", "Quote", "Note", "Reference", "BibEntry", "Code", "Link", "Annot",
+  "Ruby", "Warichu", "Figure", "Formula", "Form", "RB", "RT",
+];
+
+// node ids have to be unique within a document (which can combine pages from several canvases)
+static NEXT_NODE_ID:AtomicI32 = AtomicI32::new(1);
+
+#[derive(Debug, Clone)]
+pub struct Tag{
+  pub id: i32,
+  pub kind: String,
+  pub alt: Option<String>,
+  pub lang: Option<String>,
+  pub children: Vec<Tag>,
+}
+
+impl Tag{
+  pub fn new(kind:&str, alt:Option<String>, lang:Option<String>) -> Result<Self, String> {
+    if !STRUCTURE_TYPES.contains(&kind) {
+      return Err(format!("Unknown structure type \"{}\" (expected one of {})", kind, STRUCTURE_TYPES.join(", ")))
+    }
+    let id = NEXT_NODE_ID.fetch_add(1, Ordering::Relaxed);
+    Ok(Tag{ id, kind:kind.to_string(), alt, lang, children:vec![] })
+  }
+}
+
+// assigns the drawing commands that follow to a tag (or to none if `node_id` is 0), as `SkPDF::SetNodeId` would
+pub fn mark_content(canvas:&mut Canvas, node_id:i32){
+  let data = Data::new_copy(&node_id.to_ne_bytes());
+  canvas.draw_annotation(Rect::new_empty(), "PDF_Node_Key", &data);
+}
+
+//
+// Skia's representation of a document's structure tree
+//
+
+// every page's tags gathered beneath a single Document element (which Skia copies when the document is created)
+pub struct StructureTree{
+  root: *mut sb::SkPDF_StructureElementNode,
+}
+
+impl StructureTree{
+  pub fn new(pages:&[&[Tag]]) -> Option<Self> {
+    if pages.iter().all(|tags| tags.is_empty()) {
+      return None
+    }
+
+    // a page that's been exported more than once (e.g., split into layers) only adds its tags the first time
+    let mut seen = HashSet::new();
+    let root = new_node("Document", NEXT_NODE_ID.fetch_add(1, Ordering::Relaxed), None, None);
+    for tag in pages.iter().flat_map(|tags| tags.iter()) {
+      if let Some(node) = node_for(tag, &mut seen) {
+        unsafe{ sb::C_SkPDF_StructElementNode_appendChild(root, node) }
+      }
+    }
+    Some(StructureTree{ root })
+  }
+}
+
+impl Drop for StructureTree{
+  fn drop(&mut self){
+    // the root owns its children
+    unsafe{ sb::C_SkPDF_StructureElementNode_delete(self.root) }
+  }
+}
+
+fn new_node(kind:&str, id:i32, alt:Option<&str>, lang:Option<&str>) -> *mut sb::SkPDF_StructureElementNode {
+  unsafe{
+    let node = sb::C_SkPDF_StructureElementNode_New();
+    set_string(&mut (*node).fTypeString, kind);
+    (*node).fNodeId = id;
+    if let Some(alt) = alt {
+      set_string(&mut (*node).fAlt, alt);
+    }
+    if let Some(lang) = lang {
+      set_string(&mut (*node).fLang, lang);
+    }
+    node
+  }
+}
+
+fn node_for(tag:&Tag, seen:&mut HashSet<i32>) -> Option<*mut sb::SkPDF_StructureElementNode> {
+  if !seen.insert(tag.id) {
+    return None
+  }
+  let node = new_node(&tag.kind, tag.id, tag.alt.as_deref(), tag.lang.as_deref());
+  for child in &tag.children {
+    if let Some(child) = node_for(child, seen) {
+      unsafe{ sb::C_SkPDF_StructElementNode_appendChild(node, child) }
+    }
+  }
+  Some(node)
+}
+
+fn set_string(string:&mut sb::SkString, text:&str){
+  unsafe{ string.set1(text.as_ptr() as _, text.len()) }
+}
+
+//
+// PDF documents (created through skia-bindings since skia-safe's `pdf::new_document` can't be given a structure
+// tree yet: its `Metadata` keeps the tree root private and panics if one is set)
+//
+
+pub struct PdfDocument{
+  document: *mut sb::SkDocument,
+  stream: Box<MaybeUninit<sb::SkDynamicMemoryWStream>>,
+  tree: Option<StructureTree>,  // dropped after the document that refers to it
+}
+
+impl PdfDocument{
+  pub fn new(meta:&pdf::Metadata, tree:Option<StructureTree>) -> Result<Self, String> {
+    let mut stream = Box::new(MaybeUninit::<sb::SkDynamicMemoryWStream>::uninit());
+    let document = unsafe{
+      let mut md = MaybeUninit::<sb::SkPDF_Metadata>::uninit();
+      sb::C_SkPDF_Metadata_Construct(md.as_mut_ptr());
+      let internal = &mut *md.as_mut_ptr();
+      set_string(&mut internal.fTitle, &meta.title);
+      set_string(&mut internal.fAuthor, &meta.author);
+      set_string(&mut internal.fSubject, &meta.subject);
+      set_string(&mut internal.fKeywords, &meta.keywords);
+      set_string(&mut internal.fCreator, &meta.creator);
+      set_string(&mut internal.fProducer, &meta.producer);
+      if let Some(creation) = meta.creation {
+        internal.fCreation = mem::transmute(creation);
+      }
+      if let Some(modified) = meta.modified {
+        internal.fModified = mem::transmute(modified);
+      }
+      if let Some(raster_dpi) = meta.raster_dpi {
+        internal.fRasterDPI = raster_dpi;
+      }
+      if let Some(encoding_quality) = meta.encoding_quality {
+        internal.fEncodingQuality = encoding_quality;
+      }
+      internal.fPDFA = meta.pdfa;
+      if let Some(tree) = &tree {
+        internal.fStructureElementTreeRoot = tree.root;
+      }
+
+      sb::C_SkDynamicMemoryWStream_Construct(stream.as_mut_ptr());
+      let document = sb::C_SkPDF_MakeDocument(stream.as_mut_ptr() as *mut sb::SkWStream, internal);
+      sb::C_SkPDF_Metadata_destruct(internal);
+      document
+    };
+
+    match document.is_null() {
+      true => {
+        unsafe{ sb::C_SkWStream_destruct(stream.as_mut_ptr() as *mut sb::SkWStream) }
+        Err("Could not create PDF document".to_string())
+      },
+      false => Ok(PdfDocument{ document, stream, tree })
+    }
+  }
+
+  // starts a new page, returning the canvas its content should be drawn to
+  pub fn begin_page(&mut self, size:impl Into<Size>, content:Option<&Rect>) -> Result<&mut Canvas, String> {
+    let size = size.into();
+    let content = content.map_or(std::ptr::null(), |rect| rect as *const Rect as *const sb::SkRect);
+    unsafe{
+      let canvas = (*self.document).beginPage(size.width, size.height, content);
+      (canvas as *mut Canvas).as_mut().ok_or("Could not add page to PDF".to_string())
+    }
+  }
+
+  pub fn end_page(&mut self){
+    unsafe{ (*self.document).endPage() }
+  }
+
+  pub fn close(mut self) -> Data {
+    unsafe{
+      (*self.document).close();
+      let data = sb::C_SkDynamicMemoryWStream_detachAsData(self.stream.as_mut_ptr());
+      let output = Data::new_copy(std::slice::from_raw_parts((*data).fPtr as *const u8, (*data).fSize));
+      sb::C_SkData_unref(data);
+      output
+    }
+  }
+}
+
+impl Drop for PdfDocument{
+  fn drop(&mut self){
+    unsafe{
+      sb::C_SkRefCntBase_unref(self.document as *const sb::SkRefCntBase);
+      sb::C_SkWStream_destruct(self.stream.as_mut_ptr() as *mut sb::SkWStream);
+    }
+  }
+}
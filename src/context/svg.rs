@@ -0,0 +1,569 @@
+use std::fs;
+use std::path::Path as FilePath;
+use std::collections::{BTreeMap, BTreeSet};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use neon::prelude::*;
+use skia_safe::{Data, Typeface};
+use skia_safe::font_style::{FontStyle, Weight, Width};
+
+use crate::FONT_LIBRARY;
+use crate::typography::to_slant;
+use crate::utils::*;
+
+//
+// Post-processing for the output of Skia's SVG canvas
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvgFonts{ System, Embed }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvgImages{ Inline, Files }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvgSizing{ Absolute, ViewBox }
+
+#[derive(Debug, Clone)]
+pub struct SvgOptions{
+  pub fonts: SvgFonts,
+  pub images: SvgImages,
+  pub sizing: SvgSizing,
+}
+
+impl Default for SvgOptions{
+  fn default() -> Self {
+    SvgOptions{ fonts:SvgFonts::System, images:SvgImages::Inline, sizing:SvgSizing::Absolute }
+  }
+}
+
+impl SvgOptions{
+  // applies the font and sizing options (images are dealt with separately since they need a path to be written to)
+  pub fn apply(&self, data:Data) -> Result<Data, String> {
+    if self.fonts == SvgFonts::System && self.sizing == SvgSizing::Absolute {
+      return Ok(data)
+    }
+
+    let mut svg = String::from_utf8(data.as_bytes().to_vec()).map_err(|_| "Could not parse SVG output")?;
+    if self.fonts == SvgFonts::Embed {
+      svg = embed_fonts(&svg);
+    }
+    if self.sizing == SvgSizing::ViewBox {
+      svg = unitless(&svg);
+    }
+    Ok(Data::new_copy(svg.as_bytes()))
+  }
+}
+
+// moves any data-URI bitmaps into separate files alongside the svg (named `<basename>-<n>.<ext>`)
+pub fn write_images(data:Data, path:&str) -> Result<Data, String> {
+  let svg = String::from_utf8(data.as_bytes().to_vec()).map_err(|_| "Could not parse SVG output")?;
+  let file = FilePath::new(path);
+  let dir = file.parent().unwrap_or_else(|| FilePath::new(""));
+  let stem = file.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+
+  let mut output = String::with_capacity(svg.len());
+  let mut rest = svg.as_str();
+  let mut count = 0;
+  while let Some(start) = rest.find("\"data:image/") {
+    let (before, after) = rest.split_at(start + 1);
+    let end = after.find('"').unwrap_or(after.len());
+    let uri = &after[..end];
+    output.push_str(before);
+
+    match uri["data:image/".len()..].split_once(";base64,").and_then(|(kind, b64)| Some((kind, from_base64(b64)?))) {
+      Some((kind, bytes)) => {
+        count += 1;
+        let ext = if kind == "jpeg" { "jpg" } else { kind };
+        let filename = format!("{}-{}.{}", stem, count, ext);
+        let sidecar = dir.join(&filename);
+        fs::write(&sidecar, bytes).map_err(|why|
+          format!("{}: \"{}\"", why, sidecar.display())
+        )?;
+        output.push_str(&escape_xml(&url_escape(&filename)));
+      },
+      None => output.push_str(uri)
+    }
+    rest = &after[end..];
+  }
+  output.push_str(rest);
+  Ok(Data::new_copy(output.as_bytes()))
+}
+
+//
+// Sizing
+//
+
+// replaces the root element's width & height with an equivalent viewBox so the image scales to fit its container
+fn unitless(svg:&str) -> String {
+  let (start, end) = match root_tag(svg) {
+    Some(span) => span,
+    None => return svg.to_string()
+  };
+  let tag = &svg[start..end];
+  let (tag, closing) = match tag.strip_suffix('/') {
+    Some(open) => (open, "/"),
+    None => (tag, "")
+  };
+
+  let mut new_tag = strip_attr(&strip_attr(tag, "width"), "height");
+  if attr(tag, "viewBox").is_none() {
+    if let (Some(width), Some(height)) = (attr(tag, "width"), attr(tag, "height")) {
+      new_tag.push_str(&format!(" viewBox=\"0 0 {} {}\"", width, height));
+    }
+  }
+  format!("{}{}{}{}", &svg[..start], new_tag, closing, &svg[end..])
+}
+
+//
+// Font embedding
+//
+
+// adds an @font-face rule (with a WOFF subset of the font as its source) for each face used by a <text> element
+fn embed_fonts(svg:&str) -> String {
+  let mut faces:BTreeMap<(&str, &str, &str), BTreeSet<char>> = BTreeMap::new();
+  let mut rest = svg;
+  while let Some(start) = rest.find("<text") {
+    let elt = &rest[start..];
+    let close = match elt.find('>') {
+      Some(idx) => idx,
+      None => break
+    };
+    let tag = &elt[..close];
+    let content = match elt.find("</text>") {
+      Some(end) if end > close => &elt[close+1..end],
+      _ => ""
+    };
+    if let Some(family) = attr(tag, "font-family") {
+      let weight = attr(tag, "font-weight").unwrap_or("normal");
+      let style = attr(tag, "font-style").unwrap_or("normal");
+      faces.entry((family, weight, style)).or_default().extend(unescape_xml(content).chars());
+    }
+    rest = &elt[close..];
+  }
+
+  let mut css = String::new();
+  let mut library = FONT_LIBRARY.lock().unwrap();
+  for ((family, weight, style), chars) in faces {
+    let family = unescape_xml(family.split(',').next().unwrap_or_default().trim());
+    let numeric_weight = match weight {
+      "normal" => 400,
+      "bold" => 700,
+      wght => wght.parse().unwrap_or(400)
+    };
+    let font_style = FontStyle::new(Weight::from(numeric_weight), Width::NORMAL, to_slant(style));
+    if let Some(woff) = library.collection.find_typefaces(&[&family], font_style).first().and_then(|face| woff(face, &chars)) {
+      css.push_str(&format!(
+        "@font-face{{font-family:\"{}\";font-weight:{};font-style:{};src:url(data:font/woff;base64,{}) format(\"woff\")}}",
+        escape_xml(&family.replace('\\', "\\\\").replace('"', "\\\"")), weight, style, to_base64(&woff)
+      ));
+    }
+  }
+
+  match root_tag(svg) {
+    Some((_, end)) if !css.is_empty() => format!("{}><style>{}</style>{}", &svg[..end], css, &svg[end+1..]),
+    _ => svg.to_string()
+  }
+}
+
+fn woff(face:&Typeface, chars:&BTreeSet<char>) -> Option<Vec<u8>> {
+  let mut tables:Vec<(u32, Vec<u8>)> = face.table_tags()?
+    .iter()
+    .filter_map(|tag| face.copy_table_data(*tag).map(|data| (*tag, data.as_bytes().to_vec())) )
+    .collect();
+
+  let unichars:Vec<i32> = chars.iter().map(|c| *c as i32).collect();
+  let mut glyphs = vec![0u16; unichars.len()];
+  face.unichars_to_glyphs(&unichars, &mut glyphs);
+  subset_glyphs(&mut tables, &glyphs);
+  Some(woff_container(tables))
+}
+
+// blanks out the outlines of every glyph that isn't needed, leaving glyph ids and all other tables intact
+// (fonts with CFF outlines rather than a `glyf` table, or whose GSUB table can't be parsed, are embedded in full)
+fn subset_glyphs(tables:&mut [(u32, Vec<u8>)], glyphs:&[u16]) {
+  let find = |tag:&[u8; 4]| tables.iter().position(|(t, _)| *t == u32::from_be_bytes(*tag));
+  let (head, loca, glyf) = match (find(b"head"), find(b"loca"), find(b"glyf")) {
+    (Some(head), Some(loca), Some(glyf)) => (head, loca, glyf),
+    _ => return
+  };
+  let long_offsets = match tables[head].1.get(50..52) {
+    Some(fmt) => fmt == [0, 1],
+    None => return
+  };
+  let offsets:Vec<usize> = match long_offsets {
+    true => tables[loca].1.chunks_exact(4).map(|o| u32::from_be_bytes([o[0], o[1], o[2], o[3]]) as usize).collect(),
+    false => tables[loca].1.chunks_exact(2).map(|o| u16::from_be_bytes([o[0], o[1]]) as usize * 2).collect(),
+  };
+
+  let outlines = &tables[glyf].1;
+  let glyph_data = |id:u16| glyph_outline(outlines, &offsets, id);
+
+  // keep the .notdef glyph, any ligatures & alternates the viewer might substitute for the text's glyphs, and
+  // the components used by composite glyphs
+  let mut keep:BTreeSet<u16> = glyphs.iter().copied().chain([0]).collect();
+  if let Some(gsub) = find(b"GSUB") {
+    if gsub_closure(&tables[gsub].1, &mut keep).is_none() {
+      return
+    }
+  }
+  let mut queue:Vec<u16> = keep.iter().copied().collect();
+  while let Some(id) = queue.pop() {
+    for component in glyph_components(glyph_data(id)) {
+      if keep.insert(component) {
+        queue.push(component)
+      }
+    }
+  }
+
+  let mut new_glyf = vec![];
+  let mut new_loca = vec![];
+  let align = if long_offsets { 4 } else { 2 };
+  for id in 0..offsets.len().saturating_sub(1) {
+    match long_offsets {
+      true => new_loca.extend((new_glyf.len() as u32).to_be_bytes()),
+      false => new_loca.extend(((new_glyf.len() / 2) as u16).to_be_bytes()),
+    }
+    if keep.contains(&(id as u16)) {
+      new_glyf.extend(glyph_data(id as u16));
+      new_glyf.resize(new_glyf.len().next_multiple_of(align), 0);
+    }
+  }
+  match long_offsets {
+    true => new_loca.extend((new_glyf.len() as u32).to_be_bytes()),
+    false => new_loca.extend(((new_glyf.len() / 2) as u16).to_be_bytes()),
+  }
+
+  tables[glyf].1 = new_glyf;
+  tables[loca].1 = new_loca;
+}
+
+// adds every glyph that a substitution lookup could replace the kept glyphs with, applying all the lookups
+// (regardless of script, language, or feature) until no new glyphs turn up
+fn gsub_closure(gsub:&[u8], keep:&mut BTreeSet<u16>) -> Option<()> {
+  let subtables = gsub_subtables(gsub)?;
+  loop {
+    let count = keep.len();
+    for (kind, table) in &subtables {
+      substitutes(*kind, table, keep)?;
+    }
+    if keep.len() == count {
+      return Some(())
+    }
+  }
+}
+
+// the lookup type & data of every subtable in the lookup list (with extension subtables replaced by their targets)
+fn gsub_subtables(gsub:&[u8]) -> Option<Vec<(u16, &[u8])>> {
+  let lookups = gsub.get(u16_at(gsub, 8)? as usize..)?;
+  let mut subtables = vec![];
+  for i in 0..u16_at(lookups, 0)? as usize {
+    let lookup = lookups.get(u16_at(lookups, 2 + 2 * i)? as usize..)?;
+    let kind = u16_at(lookup, 0)?;
+    for j in 0..u16_at(lookup, 4)? as usize {
+      let table = lookup.get(u16_at(lookup, 6 + 2 * j)? as usize..)?;
+      match kind {
+        7 => subtables.push((u16_at(table, 2)?, table.get(u32_at(table, 4)? as usize..)?)),
+        _ => subtables.push((kind, table))
+      }
+    }
+  }
+  Some(subtables)
+}
+
+// adds the output glyphs of a single, multiple, alternate, ligature, or reverse-chaining substitution subtable
+// (contextual subtables only refer to other lookups, which are applied in their own right)
+fn substitutes(kind:u16, table:&[u8], keep:&mut BTreeSet<u16>) -> Option<()> {
+  if ![1, 2, 3, 4, 8].contains(&kind) {
+    return Some(())
+  }
+  let format = u16_at(table, 0)?;
+  let covered = coverage(table.get(u16_at(table, 2)? as usize..)?)?;
+  let indices:Vec<usize> = covered.iter().enumerate()
+    .filter(|(_, glyph)| keep.contains(*glyph))
+    .map(|(idx, _)| idx)
+    .collect();
+
+  let mut found = vec![];
+  match (kind, format) {
+    (1, 1) => {
+      let delta = u16_at(table, 4)?;
+      found.extend(indices.iter().map(|idx| covered[*idx].wrapping_add(delta)));
+    },
+    (1, 2) => for idx in indices {
+      found.push(u16_at(table, 6 + 2 * idx)?);
+    },
+    (2, 1) | (3, 1) => for idx in indices {
+      let sequence = table.get(u16_at(table, 6 + 2 * idx)? as usize..)?;
+      for k in 0..u16_at(sequence, 0)? as usize {
+        found.push(u16_at(sequence, 2 + 2 * k)?);
+      }
+    },
+    (4, 1) => for idx in indices {
+      // a ligature is only needed if all of its components are
+      let set = table.get(u16_at(table, 6 + 2 * idx)? as usize..)?;
+      for k in 0..u16_at(set, 0)? as usize {
+        let ligature = set.get(u16_at(set, 2 + 2 * k)? as usize..)?;
+        let components = u16_at(ligature, 2)? as usize;
+        let mut used = true;
+        for c in 1..components {
+          used &= keep.contains(&u16_at(ligature, 2 + 2 * c)?);
+        }
+        if used {
+          found.push(u16_at(ligature, 0)?);
+        }
+      }
+    },
+    (8, 1) => {
+      // skip past the backtrack & lookahead coverage offsets to the substitutes
+      let lookahead = 6 + 2 * u16_at(table, 4)? as usize;
+      let substitutes = lookahead + 2 + 2 * u16_at(table, lookahead)? as usize;
+      for idx in indices {
+        found.push(u16_at(table, substitutes + 2 + 2 * idx)?);
+      }
+    },
+    _ => {}
+  }
+  keep.extend(found);
+  Some(())
+}
+
+// the glyphs listed in a coverage table (in coverage-index order)
+fn coverage(table:&[u8]) -> Option<Vec<u16>> {
+  match u16_at(table, 0)? {
+    1 => (0..u16_at(table, 2)? as usize).map(|i| u16_at(table, 4 + 2 * i)).collect(),
+    2 => {
+      let mut glyphs = vec![];
+      for i in 0..u16_at(table, 2)? as usize {
+        glyphs.extend(u16_at(table, 4 + 6 * i)?..=u16_at(table, 6 + 6 * i)?);
+      }
+      Some(glyphs)
+    },
+    _ => None
+  }
+}
+
+fn u16_at(data:&[u8], pos:usize) -> Option<u16> {
+  data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data:&[u8], pos:usize) -> Option<u32> {
+  data.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn glyph_outline<'a>(outlines:&'a [u8], offsets:&[usize], id:u16) -> &'a [u8] {
+  match (offsets.get(id as usize), offsets.get(id as usize + 1)) {
+    (Some(&start), Some(&end)) if start <= end => outlines.get(start..end).unwrap_or_default(),
+    _ => &[]
+  }
+}
+
+fn glyph_components(data:&[u8]) -> Vec<u16> {
+  let mut ids = vec![];
+  let word = |pos:usize| u16::from_be_bytes([data[pos], data[pos+1]]);
+  if data.len() < 10 || (word(0) as i16) >= 0 {
+    return ids // not a composite glyph
+  }
+
+  let mut pos = 10;
+  while pos + 4 <= data.len() {
+    let flags = word(pos);
+    ids.push(word(pos + 2));
+    pos += 4 + if flags & 0x0001 != 0 { 4 } else { 2 }; // ARG_1_AND_2_ARE_WORDS
+    pos += if flags & 0x0008 != 0 { 2 }                 // WE_HAVE_A_SCALE
+      else if flags & 0x0040 != 0 { 4 }                 // WE_HAVE_AN_X_AND_Y_SCALE
+      else if flags & 0x0080 != 0 { 8 }                 // WE_HAVE_A_TWO_BY_TWO
+      else { 0 };
+    if flags & 0x0020 == 0 { break }                    // MORE_COMPONENTS
+  }
+  ids
+}
+
+fn woff_container(mut tables:Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+  // the digital signature (if any) is no longer valid after subsetting
+  tables.retain(|(tag, _)| *tag != u32::from_be_bytes(*b"DSIG"));
+  tables.sort_by_key(|(tag, _)| *tag);
+
+  let flavor = match tables.iter().any(|(tag, _)| *tag == u32::from_be_bytes(*b"CFF ")) {
+    true => *b"OTTO",
+    false => [0, 1, 0, 0]
+  };
+  let header_len = 44 + 20 * tables.len();
+  let sfnt_size = 12 + 16 * tables.len() + tables.iter().map(|(_, data)| (data.len() + 3) & !3).sum::<usize>();
+
+  let mut directory = vec![];
+  let mut body = vec![];
+  for (tag, data) in &tables {
+    let compressed = compress_to_vec_zlib(data, 9);
+    let stored = if compressed.len() < data.len() { &compressed } else { data };
+    let checksum = data.chunks(4).fold(0u32, |sum, word| {
+      let mut padded = [0u8; 4];
+      padded[..word.len()].copy_from_slice(word);
+      sum.wrapping_add(u32::from_be_bytes(padded))
+    });
+
+    directory.extend(tag.to_be_bytes());
+    directory.extend(((header_len + body.len()) as u32).to_be_bytes());
+    directory.extend((stored.len() as u32).to_be_bytes());
+    directory.extend((data.len() as u32).to_be_bytes());
+    directory.extend(checksum.to_be_bytes());
+    body.extend(stored);
+    body.resize((body.len() + 3) & !3, 0);
+  }
+
+  let mut woff = Vec::with_capacity(header_len + body.len());
+  woff.extend(b"wOFF");
+  woff.extend(flavor);
+  woff.extend(((header_len + body.len()) as u32).to_be_bytes());
+  woff.extend((tables.len() as u16).to_be_bytes());
+  woff.extend([0, 0]);                          // reserved
+  woff.extend((sfnt_size as u32).to_be_bytes());
+  woff.extend([0, 1, 0, 0]);                    // font version 1.0
+  woff.extend([0; 20]);                         // no metadata or private data blocks
+  woff.extend(directory);
+  woff.extend(body);
+  woff
+}
+
+//
+// XML & encoding helpers
+//
+
+// the span of the root <svg …> tag (from its opening `<` up to but not including its closing `>`)
+fn root_tag(svg:&str) -> Option<(usize, usize)> {
+  let start = svg.find("<svg")?;
+  let end = start + svg[start..].find('>')?;
+  Some((start, end))
+}
+
+fn attr_span(tag:&str, name:&str) -> Option<(usize, usize)> {
+  let pattern = format!("{}=\"", name);
+  let mut from = 0;
+  while let Some(idx) = tag[from..].find(&pattern) {
+    let start = from + idx;
+    let value = start + pattern.len();
+    if tag[..start].ends_with(char::is_whitespace) {
+      let end = value + tag[value..].find('"')?;
+      return Some((start, end + 1))
+    }
+    from = value;
+  }
+  None
+}
+
+fn attr<'a>(tag:&'a str, name:&str) -> Option<&'a str> {
+  attr_span(tag, name).map(|(start, end)| &tag[start + name.len() + 2..end - 1])
+}
+
+fn strip_attr(tag:&str, name:&str) -> String {
+  match attr_span(tag, name) {
+    Some((start, end)) => format!("{}{}", tag[..start].trim_end(), &tag[end..]),
+    None => tag.to_string()
+  }
+}
+
+fn escape_xml(text:&str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn unescape_xml(text:&str) -> String {
+  let mut output = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(amp) = rest.find('&') {
+    output.push_str(&rest[..amp]);
+    rest = &rest[amp..];
+    let entity = rest.find(';').map(|semi| (&rest[1..semi], semi));
+    let decoded = entity.and_then(|(name, _)| match name {
+      "amp" => Some('&'),
+      "lt" => Some('<'),
+      "gt" => Some('>'),
+      "quot" => Some('"'),
+      "apos" => Some('\''),
+      _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+        None => name.strip_prefix('#').and_then(|dec| dec.parse().ok()).and_then(char::from_u32)
+      }
+    });
+    match (decoded, entity) {
+      (Some(chr), Some((_, semi))) => { output.push(chr); rest = &rest[semi+1..]; },
+      _ => { output.push('&'); rest = &rest[1..]; }
+    }
+  }
+  output.push_str(rest);
+  output
+}
+
+fn url_escape(text:&str) -> String {
+  text.bytes().map(|b| match b {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+    _ => format!("%{:02X}", b)
+  }).collect()
+}
+
+const BASE64:&[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(data:&[u8]) -> String {
+  let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+  for chunk in data.chunks(3) {
+    let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      match i <= chunk.len() {
+        true => text.push(BASE64[(bits >> (18 - 6 * i) & 0x3F) as usize] as char),
+        false => text.push('=')
+      }
+    }
+  }
+  text
+}
+
+fn from_base64(text:&str) -> Option<Vec<u8>> {
+  let mut data = Vec::with_capacity(text.len() / 4 * 3);
+  let mut bits = 0u32;
+  let mut count = 0;
+  for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+    bits = bits << 6 | BASE64.iter().position(|b| *b == c)? as u32;
+    count += 1;
+    if count % 4 == 0 {
+      data.extend(&bits.to_be_bytes()[1..]);
+      bits = 0;
+    }
+  }
+  match count % 4 {
+    2 => data.push((bits >> 4) as u8),
+    3 => data.extend(&((bits >> 2) as u16).to_be_bytes()),
+    0 => {},
+    _ => return None
+  }
+  Some(data)
+}
+
+//
+// Argument parsing
+//
+
+pub fn svg_options_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<SvgOptions>{
+  let obj = match opt_value_for_key(cx, options, attr) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(SvgOptions::default())
+    },
+    None => return Ok(SvgOptions::default())
+  };
+
+  let fonts = match opt_string_for_key(cx, &obj, "fonts").as_deref() {
+    Some("embed") => SvgFonts::Embed,
+    Some("system") | None => SvgFonts::System,
+    _ => return cx.throw_type_error("Expected \"system\" or \"embed\" for svg.fonts")
+  };
+  let images = match opt_string_for_key(cx, &obj, "images").as_deref() {
+    Some("files") => SvgImages::Files,
+    Some("inline") | None => SvgImages::Inline,
+    _ => return cx.throw_type_error("Expected \"inline\" or \"files\" for svg.images")
+  };
+  let sizing = match opt_string_for_key(cx, &obj, "sizing").as_deref() {
+    Some("viewbox") => SvgSizing::ViewBox,
+    Some("absolute") | None => SvgSizing::Absolute,
+    _ => return cx.throw_type_error("Expected \"absolute\" or \"viewbox\" for svg.sizing")
+  };
+
+  Ok(SvgOptions{ fonts, images, sizing })
+}
@@ -0,0 +1,153 @@
+use rayon::prelude::*;
+use neon::prelude::*;
+use skia_safe::{Image as SkImage, ISize, Rect, Paint, Data, Color, ColorSpace, ColorType, AlphaType,
+                ImageInfo, CubicResampler, SamplingOptions, image::BitDepth};
+
+use crate::gpu::RenderingEngine;
+use crate::utils::*;
+use super::page::Page;
+use super::encoding::{ImageOptions, encode_image, image_options_for_keys, to_image_format};
+
+//
+// Thumbnails (a set of downscaled renditions of a page, each encoded separately)
+//
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail{
+  pub width: Option<f32>,
+  pub height: Option<f32>,
+  pub format: String,
+  pub quality: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Thumbnails{
+  pub sizes: Vec<Thumbnail>,
+  pub resampler: CubicResampler,
+  pub color_space: ColorSpace,
+  pub options: ImageOptions, // shared by all the sizes (apart from their quality)
+}
+
+impl Thumbnail{
+  // fit within whichever of the width & height constraints were specified, preserving the aspect ratio
+  fn dimensions(&self, source:ISize) -> ISize {
+    let (src_w, src_h) = (source.width as f32, source.height as f32);
+    let scale = match (self.width, self.height) {
+      (Some(w), Some(h)) => (w / src_w).min(h / src_h),
+      (Some(w), None) => w / src_w,
+      (None, Some(h)) => h / src_h,
+      (None, None) => 1.0,
+    };
+    ISize::new(
+      ((src_w * scale).round() as i32).max(1),
+      ((src_h * scale).round() as i32).max(1)
+    )
+  }
+
+  fn encode(&self, image:&SkImage, options:&ImageOptions) -> Result<Data, String> {
+    let img_format = to_image_format(&self.format)
+      .ok_or(format!("Unsupported thumbnail format {} (expected png, jpg, or webp)", self.format))?;
+    encode_image(image, img_format, &ImageOptions{ quality:self.quality, ..options.clone() })
+  }
+}
+
+fn resample(image:&SkImage, dims:ISize, resampler:CubicResampler, engine:RenderingEngine) -> Result<SkImage, String> {
+  let img_info = ImageInfo::new(dims, image.color_type(), AlphaType::Premul, image.image_info().color_space());
+  if let Some(mut surface) = engine.get_surface(&img_info){
+    let sampling:SamplingOptions = resampler.into();
+    surface.canvas().draw_image_rect_with_sampling_options(image, None, Rect::from_isize(dims), sampling, &Paint::default());
+    Ok(surface.image_snapshot())
+  }else{
+    Err(format!("Could not allocate new {}×{} bitmap", dims.width, dims.height))
+  }
+}
+
+impl Page{
+  pub fn as_thumbnails(&self, matte:Option<Color>, thumbnails:&Thumbnails, engine:RenderingEngine) -> Result<Vec<Data>, String> {
+    let Thumbnails{ ref color_space, ref options, .. } = *thumbnails;
+    let color_type = match options.bit_depth {
+      BitDepth::F16 => ColorType::RGBAF16,
+      _ => ColorType::N32
+    };
+    let render_bounds = self.render_bounds(None)?;
+    let picture = self.get_picture(matte, Some(&render_bounds)).ok_or("Could not generate picture")?;
+    let frame = self.rasterize_as(&picture, &render_bounds, options.density, color_type, color_space.clone(), engine)?;
+    let targets:Vec<ISize> = thumbnails.sizes.iter().map(|thumb| thumb.dimensions(frame.dimensions())).collect();
+
+    // a cubic filter only samples a 4×4 neighborhood, so large reductions are made in a series of halvings
+    // (a mip pyramid shared by all the outputs) before the final resize to each thumbnail's exact dimensions
+    let (min_w, min_h) = targets.iter().fold((i32::MAX, i32::MAX), |(w, h), dims| (w.min(dims.width), h.min(dims.height)));
+    let mut levels = vec![frame];
+    loop {
+      let last = levels.last().unwrap().dimensions();
+      let half = ISize::new(last.width / 2, last.height / 2);
+      if half.width < min_w || half.height < min_h { break }
+      let level = resample(levels.last().unwrap(), half, thumbnails.resampler, engine)?;
+      levels.push(level);
+    }
+
+    thumbnails.sizes
+      .par_iter()
+      .zip(targets.par_iter())
+      .map(|(thumb, dims)|{
+        // start from the smallest level that is still at least as large as the thumbnail
+        let source = levels.iter().rev()
+          .find(|level| level.width() >= dims.width && level.height() >= dims.height)
+          .unwrap_or(&levels[0]);
+        match source.dimensions() == *dims {
+          true => thumb.encode(source, options),
+          false => thumb.encode(&resample(source, *dims, thumbnails.resampler, engine)?, options)
+        }
+      })
+      .collect()
+  }
+}
+
+//
+// Argument parsing
+//
+
+fn to_resampler(filter_name:&str) -> Option<CubicResampler>{
+  let resampler = match filter_name.to_lowercase().as_str(){
+    "mitchell" => CubicResampler::mitchell(),
+    "catmull-rom" | "catmullrom" => CubicResampler::catmull_rom(),
+    _ => return None
+  };
+  Some(resampler)
+}
+
+pub fn thumbnails_arg(cx: &mut FunctionContext, idx: usize) -> NeonResult<Thumbnails>{
+  let specs = cx.argument::<JsArray>(idx as i32)?.to_vec(cx)?;
+  let mut sizes = vec![];
+  for spec in specs {
+    let obj = match spec.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return cx.throw_type_error("Expected an object for each thumbnail")
+    };
+    let width = opt_float_for_key(cx, &obj, "width");
+    let height = opt_float_for_key(cx, &obj, "height");
+    let format = opt_string_for_key(cx, &obj, "format").unwrap_or_else(|| "png".to_string());
+    let quality = opt_float_for_key(cx, &obj, "quality").unwrap_or(0.92);
+    if width.is_none() && height.is_none() {
+      return cx.throw_type_error("Each thumbnail needs a width, a height, or both")
+    }
+    if width.unwrap_or(1.0) < 1.0 || height.unwrap_or(1.0) < 1.0 {
+      return cx.throw_range_error("Thumbnail dimensions must be at least 1 pixel")
+    }
+    sizes.push(Thumbnail{ width, height, format, quality })
+  }
+
+  let resampler = match opt_string_arg(cx, idx + 1) {
+    Some(name) => match to_resampler(&name){
+      Some(resampler) => resampler,
+      None => return cx.throw_type_error("Expected \"mitchell\" or \"catmull-rom\" for filter")
+    },
+    None => CubicResampler::mitchell()
+  };
+
+  let options = cx.argument::<JsObject>(idx as i32 + 2)?;
+  let color_space = color_space_for_key(cx, &options, "colorSpace");
+  let options = image_options_for_keys(cx, &options)?;
+
+  Ok(Thumbnails{ sizes, resampler, color_space, options })
+}
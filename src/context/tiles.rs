@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::Path as FilePath;
+use rayon::prelude::*;
+use neon::prelude::*;
+use skia_safe::{Size, IRect, Color, ColorSpace, ColorType};
+
+use crate::gpu::RenderingEngine;
+use crate::utils::*;
+use super::page::Page;
+use super::encoding::{ImageOptions, encode_image, to_image_format};
+
+//
+// Tile pyramids (for pan-and-zoom viewers that load a very large image a piece at a time)
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout{ Xyz, DeepZoom }
+
+#[derive(Debug, Clone)]
+pub struct Tiling{
+  pub size: i32,
+  pub overlap: i32,
+  pub layout: Layout,
+}
+
+impl Default for Tiling{
+  fn default() -> Self {
+    Tiling{ size:256, overlap:0, layout:Layout::Xyz }
+  }
+}
+
+struct Tile{
+  path: String,
+  region: IRect,
+  scale: f32,
+}
+
+impl Tiling{
+  // the number of times the full-sized image has to be halved before it fits within a single tile (for xyz)
+  // or is reduced to a single pixel (for deepzoom)
+  fn max_level(&self, width:i32, height:i32) -> u32 {
+    let longest = width.max(height) as f32;
+    let base = match self.layout {
+      Layout::Xyz => self.size as f32,
+      Layout::DeepZoom => 1.0,
+    };
+    (longest / base).log2().ceil().max(0.0) as u32
+  }
+
+  fn tiles(&self, path:&str, ext:&str, width:i32, height:i32, density:f32) -> Vec<Tile> {
+    let max_level = self.max_level(width, height);
+    let mut tiles = vec![];
+
+    for level in 0..=max_level {
+      let reduction = (1 << (max_level - level)) as f32;
+      let scale = density / reduction;
+      let level_w = (width as f32 / reduction).ceil() as i32;
+      let level_h = (height as f32 / reduction).ceil() as i32;
+      let cols = (level_w as u32).div_ceil(self.size as u32) as i32;
+      let rows = (level_h as u32).div_ceil(self.size as u32) as i32;
+
+      for col in 0..cols {
+        for row in 0..rows {
+          let (left, top) = (col * self.size, row * self.size);
+          let (path, region) = match self.layout {
+            // slippy-map tiles are always full-sized (with any area beyond the image left transparent)
+            Layout::Xyz => (
+              format!("{}/{}/{}/{}.{}", path, level, col, row, ext),
+              IRect::from_xywh(left, top, self.size, self.size)
+            ),
+            // deepzoom tiles are trimmed to the image's edges and extended by `overlap` pixels on their interior sides
+            Layout::DeepZoom => (
+              format!("{}_files/{}/{}_{}.{}", path, level, col, row, ext),
+              IRect::from_ltrb(
+                (left - self.overlap).max(0),
+                (top - self.overlap).max(0),
+                (left + self.size + self.overlap).min(level_w),
+                (top + self.size + self.overlap).min(level_h),
+              )
+            ),
+          };
+          tiles.push(Tile{ path, region, scale })
+        }
+      }
+    }
+    tiles
+  }
+
+  fn descriptor(&self, ext:&str, width:i32, height:i32) -> String {
+    format!(concat!(
+      r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+      r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" Format="{}" Overlap="{}" TileSize="{}">"#, "\n",
+      r#"  <Size Width="{}" Height="{}"/>"#, "\n",
+      r#"</Image>"#, "\n"
+    ), ext, self.overlap, self.size, width, height)
+  }
+}
+
+impl Page{
+  #[allow(clippy::too_many_arguments)]
+  pub fn write_tiles(&self,
+      path:&str,
+      format:&str,
+      quality:f32,
+      density:f32,
+      matte:Option<Color>,
+      tiling:&Tiling,
+      engine:RenderingEngine
+  ) -> Result<(), String> {
+    let img_format = to_image_format(format)
+      .ok_or(format!("Unsupported tile format {} (expected png, jpg, or webp)", format))?;
+    let ext = if format == "jpeg" { "jpg" } else { format };
+    let options = ImageOptions{ quality, ..ImageOptions::default() };
+
+    let picture = self.get_picture(matte, None).ok_or("Could not generate picture")?;
+    let dims = Size::new(self.bounds.width() * density, self.bounds.height() * density).to_floor();
+    let path = path.trim_end_matches('/');
+
+    tiling.tiles(path, ext, dims.width, dims.height, density)
+      .par_iter()
+      .try_for_each(|tile|{
+        let image = self.rasterize_region(&picture, &tile.region, tile.scale, ColorType::N32, ColorSpace::new_srgb(), engine)?;
+        let data = encode_image(&image, img_format, &options)?;
+        let file = FilePath::new(&tile.path);
+        if let Some(dir) = file.parent(){
+          fs::create_dir_all(dir).map_err(|why| format!("{}: \"{}\"", why, dir.display()))?;
+        }
+        fs::write(file, data.as_bytes()).map_err(|why| format!("{}: \"{}\"", why, file.display()))
+      })?;
+
+    match tiling.layout {
+      Layout::DeepZoom => {
+        let dzi = format!("{}.dzi", path);
+        fs::write(&dzi, tiling.descriptor(ext, dims.width, dims.height)).map_err(|why|
+          format!("{}: \"{}\"", why, dzi)
+        )
+      },
+      Layout::Xyz => Ok(())
+    }
+  }
+}
+
+//
+// Argument parsing
+//
+
+fn to_layout(layout_name:&str) -> Option<Layout>{
+  let layout = match layout_name.to_lowercase().as_str(){
+    "xyz" => Layout::Xyz,
+    "deepzoom" | "dzi" => Layout::DeepZoom,
+    _ => return None
+  };
+  Some(layout)
+}
+
+pub fn tiling_arg(cx: &mut FunctionContext, idx: usize) -> NeonResult<Tiling>{
+  let obj = match cx.argument_opt(idx as i32) {
+    Some(arg) => match arg.downcast::<JsObject, _>(cx){
+      Ok(obj) => obj,
+      Err(_) => return Ok(Tiling::default())
+    },
+    None => return Ok(Tiling::default())
+  };
+
+  let defaults = Tiling::default();
+  let size = opt_float_for_key(cx, &obj, "size").map(|n| n as i32).unwrap_or(defaults.size);
+  let overlap = opt_float_for_key(cx, &obj, "overlap").map(|n| n as i32).unwrap_or(defaults.overlap);
+  let layout = match opt_string_for_key(cx, &obj, "layout") {
+    Some(name) => match to_layout(&name){
+      Some(layout) => layout,
+      None => return cx.throw_type_error("Expected \"xyz\" or \"deepzoom\" for layout")
+    },
+    None => defaults.layout
+  };
+
+  if size < 1 {
+    return cx.throw_range_error("Tile size must be at least 1 pixel")
+  }
+  Ok(Tiling{ size, overlap:overlap.max(0), layout })
+}
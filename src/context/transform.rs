@@ -0,0 +1,157 @@
+use neon::prelude::*;
+use skia_safe::{Matrix, Rect, Size, PictureRecorder, ClipOp};
+
+use crate::utils::*;
+use super::page::{Page, PageSequence};
+use super::document::PlacedField;
+
+//
+// Output transforms (rotating, flipping, or fitting a page into a fixed-size frame as part of an export)
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit{
+  None, ContainX, ContainY, Contain, Cover, Fill, ScaleDown
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputTransform{
+  pub matrix: Matrix,
+  pub size: Option<Size>,
+  pub fit: Fit,
+}
+
+impl OutputTransform{
+  // maps the crop rect into the output frame (which defaults to the bounding box of the transformed rect)
+  fn fitting_matrix(&self, src:&Rect) -> Result<(Matrix, Size), String> {
+    let content = self.matrix.map_rect(src).0;
+    if content.is_empty() {
+      return Err("Output transform must not collapse the page to an empty area".to_string())
+    }
+
+    let mut matrix = self.matrix;
+    matrix.post_translate((-content.left, -content.top));
+
+    let dims = content.size();
+    let size = match self.size {
+      Some(size) => size,
+      None => return Ok((matrix, dims))
+    };
+
+    let fit_x = size.width / dims.width;
+    let fit_y = size.height / dims.height;
+
+    let sf = match self.fit{
+      Fit::Cover => fit_x.max(fit_y),
+      Fit::ScaleDown => fit_x.min(fit_y).min(1.0),
+      Fit::Contain => fit_x.min(fit_y),
+      Fit::ContainX => fit_x,
+      Fit::ContainY => fit_y,
+      _ => 1.0
+    };
+
+    let (x_scale, y_scale) = match self.fit{
+      Fit::Fill => (fit_x, fit_y),
+      _ => (sf, sf)
+    };
+
+    // center the content, leaving letterbox margins (or cropping the overflow) on either side
+    matrix
+      .post_scale((x_scale, y_scale), None)
+      .post_translate(((size.width - dims.width * x_scale) / 2.0, (size.height - dims.height * y_scale) / 2.0));
+    Ok((matrix, size))
+  }
+}
+
+impl Page{
+  // re-records the page's layers within the output frame, clipped to the `src` rect in page coordinates
+  pub fn transformed(&self, transform:&OutputTransform, src:&Rect) -> Result<Page, String> {
+    let (matrix, size) = transform.fitting_matrix(src)?;
+    let bounds = Rect::from_size(size);
+    let layers = self.layers.iter().map(|layer|{
+      let mut recorder = PictureRecorder::new();
+      let canvas = recorder.begin_recording(bounds, None);
+      canvas.concat(&matrix);
+      canvas.clip_rect(src, ClipOp::Intersect, true);
+      canvas.draw_picture(layer, None, None);
+      recorder.finish_recording_as_picture(Some(&bounds)).ok_or("Could not generate picture")
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    // fields are moved along with the content (and dropped if they fall outside the crop)
+    let fields = self.fields.iter()
+      .filter(|placed| placed.rect.intersects(src))
+      .map(|placed| PlacedField{ rect:matrix.map_rect(placed.rect).0, ..placed.clone() })
+      .collect();
+    Ok(Page{ layers, bounds, fields, tags:self.tags.clone() })
+  }
+}
+
+impl PageSequence{
+  // applies the crop bounds (if any) and then the transform to every page; since the cropping has already
+  // been done, the bounds the export functions should use afterward are returned alongside the new sequence
+  pub fn with_transform(self, transform:Option<OutputTransform>, bounds:Option<Rect>) -> Result<(Self, Option<Rect>), String>{
+    let transform = match transform {
+      Some(transform) => transform,
+      None => return Ok((self, bounds))
+    };
+
+    let pages = self.pages
+      .iter()
+      .map(|page| page.transformed(&transform, &page.render_bounds(bounds)?))
+      .collect::<Result<Vec<_>, String>>()?;
+    Ok((PageSequence{ pages, ..self }, None))
+  }
+}
+
+//
+// Argument parsing
+//
+
+fn to_fit(mode_name:&str) -> Option<Fit>{
+  let mode = match mode_name.to_lowercase().as_str(){
+    "none" => Fit::None,
+    "contain-x" => Fit::ContainX,
+    "contain-y" => Fit::ContainY,
+    "contain" => Fit::Contain,
+    "cover" => Fit::Cover,
+    "fill" => Fit::Fill,
+    "scale-down" => Fit::ScaleDown,
+    _ => return None
+  };
+  Some(mode)
+}
+
+// reads a {matrix, width, height, fit} object (or returns None if the option is missing)
+pub fn transform_for_key(cx: &mut FunctionContext, options: &Handle<JsObject>, attr: &str) -> NeonResult<Option<OutputTransform>>{
+  let obj = match opt_value_for_key(cx, options, attr).and_then(|arg| arg.downcast::<JsObject, _>(cx).ok()) {
+    Some(obj) => obj,
+    None => return Ok(None)
+  };
+
+  let matrix = match obj.get_value(cx, "matrix")?.downcast::<JsArray, _>(cx) {
+    Ok(array) => {
+      let terms = array.to_vec(cx)?;
+      match to_matrix(&floats_in(cx, &terms)) {
+        Some(matrix) => matrix,
+        None => return cx.throw_type_error("Expected 6 or 9 values for the transform matrix")
+      }
+    },
+    Err(_) => Matrix::new_identity()
+  };
+
+  let size = match (opt_float_for_key(cx, &obj, "width"), opt_float_for_key(cx, &obj, "height")) {
+    (Some(width), Some(height)) if width > 0.0 && height > 0.0 => Some(Size::new(width, height)),
+    (None, None) => None,
+    _ => return cx.throw_range_error("The transform’s width and height must both be positive numbers")
+  };
+
+  let fit = match opt_string_for_key(cx, &obj, "fit") {
+    Some(name) => match to_fit(&name){
+      Some(fit) => fit,
+      None => return cx.throw_type_error("Expected \"none\", \"contain\", \"contain-x\", \"contain-y\", \"cover\", \"fill\", or \"scale-down\" for fit")
+    },
+    None => Fit::Contain
+  };
+
+  Ok(Some(OutputTransform{ matrix, size, fit }))
+}
@@ -4,9 +4,19 @@
 #![allow(dead_code)]
 use std::cell::RefCell;
 use neon::{prelude::*, types::buffer::TypedArray};
-use skia_safe::{Image as SkImage, ImageInfo, Size, ColorType, AlphaType, Data};
+use skia_safe::{Image as SkImage, ImageInfo, Size, ISize, Rect, ColorType, ColorSpace, AlphaType, Data, Picture, PictureRecorder};
+use skia_safe::{Surface, Canvas, Paint, IRect, SamplingOptions, FilterMode, MipmapMode, EncodedImageFormat};
+use skia_safe::image::CachingHint;
+use skia_safe::image::BitDepth;
+use skia_safe::codec::{Codec, Result as CodecResult};
+use skia_safe::svg::Dom;
 
 use crate::utils::*;
+use crate::filter::{ImageFilter, FilterQuality};
+use crate::context::page::picture_from_data;
+use crate::context::metadata::SourceMetadata;
+use crate::context::animation::AnimatedSource;
+use crate::context::encoding::{ImageOptions, encode_image, image_options_for_keys, to_image_format};
 
 
 pub type BoxedImage = JsBox<RefCell<Image>>;
@@ -14,7 +24,13 @@ impl Finalize for Image {}
 
 pub struct Image{
   src:String,
-  pub image:Option<SkImage>
+  pub image:Option<SkImage>,
+  pub picture:Option<Picture>, // vector sources (which are also rasterized lazily in `image`)
+  pub meta:SourceMetadata,
+  frames:Option<AnimatedSource>,
+  frame:usize,                 // the index of the animation frame currently held in `image`
+  orientation:u16,             // the EXIF orientation applied to the decoded frames (if enabled)
+  generation:usize,            // incremented with each new source so stale background decodes can be discarded
 }
 
 impl Image{
@@ -34,12 +50,315 @@ impl Image{
   }
 }
 
+fn image_from_picture(picture:Picture) -> Option<SkImage>{
+  let dims = picture.cull_rect().size().to_ceil();
+  SkImage::from_picture(picture, dims, None, None, BitDepth::U8, Some(ColorSpace::new_srgb()))
+}
+
+// records an svg document at its intrinsic size
+fn picture_from_svg(data:&[u8]) -> Option<Picture>{
+  let mut dom = Dom::from_bytes(data).ok()?;
+  let size = svg_size(&String::from_utf8_lossy(data));
+  dom.set_container_size(size);
+
+  let bounds = Rect::from_size(size);
+  let mut recorder = PictureRecorder::new();
+  dom.render(recorder.begin_recording(bounds, None));
+  recorder.finish_recording_as_picture(Some(&bounds))
+}
+
+// determine the document size from the root element's width, height, & viewBox (using the
+// browser defaults for replaced elements when they're missing)
+fn svg_size(svg:&str) -> Size{
+  let tag = svg.find("<svg").and_then(|start|{
+    let end = svg[start..].find('>')?;
+    Some(&svg[start..start + end])
+  }).unwrap_or_default();
+
+  let attr = |name:&str| tag.match_indices(name).find_map(|(i, _)|{
+    if !tag[..i].ends_with(char::is_whitespace) { return None }
+    let value = tag[i + name.len()..].trim_start().strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    value.find(quote).map(|end| &value[..end])
+  });
+
+  let view_box = attr("viewBox").and_then(|terms|{
+    let terms:Vec<f32> = terms.split(|c:char| c.is_whitespace() || c == ',')
+      .filter(|term| !term.is_empty())
+      .filter_map(|term| term.parse().ok())
+      .collect();
+    match terms.as_slice() {
+      [_, _, width, height] if *width > 0.0 && *height > 0.0 => Some((*width, *height)),
+      _ => None
+    }
+  });
+
+  let (width, height) = (attr("width").and_then(svg_length), attr("height").and_then(svg_length));
+  let (width, height) = match (width, height, view_box) {
+    (Some(width), Some(height), _) => (width, height),
+    (Some(width), None, Some((vw, vh))) => (width, width * vh / vw),
+    (None, Some(height), Some((vw, vh))) => (height * vw / vh, height),
+    (None, None, Some(view_box)) => view_box,
+    (width, height, None) => (width.unwrap_or(300.0), height.unwrap_or(150.0)),
+  };
+  Size::new(width, height)
+}
+
+// convert absolute lengths to px (percentages and font-relative units are ignored)
+fn svg_length(value:&str) -> Option<f32>{
+  let value = value.trim();
+  let (num, unit) = value.split_at(value.find(|c:char| c.is_ascii_alphabetic() || c == '%').unwrap_or(value.len()));
+  let scale = match unit {
+    "" | "px" => 1.0,
+    "pt" => 4.0 / 3.0,
+    "pc" => 16.0,
+    "in" => 96.0,
+    "cm" => 96.0 / 2.54,
+    "mm" => 96.0 / 25.4,
+    _ => return None
+  };
+  num.trim().parse::<f32>().ok().map(|num| num * scale).filter(|len| *len > 0.0)
+}
+
+// redraws the image with its EXIF orientation applied (lazily, at its natural size)
+fn oriented(image:SkImage, orientation:u16) -> SkImage{
+  let (w, h) = (image.width() as f32, image.height() as f32);
+  let terms = match orientation {
+    2 => [-1.0, 0.0, w, 0.0, 1.0, 0.0],   // flipped horizontally
+    3 => [-1.0, 0.0, w, 0.0, -1.0, h],    // rotated 180°
+    4 => [1.0, 0.0, 0.0, 0.0, -1.0, h],   // flipped vertically
+    5 => [0.0, 1.0, 0.0, 1.0, 0.0, 0.0],  // transposed
+    6 => [0.0, -1.0, h, 1.0, 0.0, 0.0],   // rotated 90° clockwise
+    7 => [0.0, -1.0, h, -1.0, 0.0, w],    // transversed
+    8 => [0.0, 1.0, 0.0, -1.0, 0.0, w],   // rotated 90° counter-clockwise
+    _ => return image
+  };
+  let dims = match orientation {
+    5..=8 => ISize::new(image.height(), image.width()),
+    _ => image.dimensions()
+  };
+
+  // keep sources with more than 8 bits per channel from being reduced to N32
+  let bit_depth = match image.color_type() {
+    ColorType::RGBA8888 | ColorType::BGRA8888 | ColorType::RGB888x | ColorType::Gray8 | ColorType::Alpha8 => BitDepth::U8,
+    ColorType::RGB565 | ColorType::ARGB4444 | ColorType::Unknown => BitDepth::U8,
+    _ => BitDepth::F16
+  };
+  let bounds = Rect::from_isize(dims);
+  let color_space = image.image_info().color_space();
+  let mut recorder = PictureRecorder::new();
+  let canvas = recorder.begin_recording(bounds, None);
+  if let Some(matrix) = to_matrix(&terms){
+    canvas.concat(&matrix);
+  }
+  canvas.draw_image(&image, (0, 0), None);
+  recorder.finish_recording_as_picture(Some(&bounds))
+    .and_then(|picture| SkImage::from_picture(picture, dims, None, None, bit_depth, color_space))
+    .unwrap_or(image)
+}
+
+//
+// Decoding (which can be run on a background thread and then handed off to the Image)
+//
+
+struct DecodeOptions{
+  auto_orient: bool,  // apply the EXIF orientation
+  raster: bool,       // decode the pixels up front rather than the first time the image is drawn
+  scale: f32,         // reduce the size of still bitmap images (1.0 for full-size)
+}
+
+struct Decoded{
+  image: Option<SkImage>,
+  picture: Option<Picture>,
+  meta: SourceMetadata,
+  frames: Option<AnimatedSource>,
+  orientation: u16,
+}
+
+impl Decoded{
+  fn empty() -> Self{
+    Decoded{ image:None, picture:None, meta:SourceMetadata::default(), frames:None, orientation:1 }
+  }
+
+  fn from_data(data:Data, options:&DecodeOptions) -> Self{
+    let meta = SourceMetadata::read(data.as_bytes());
+    let orientation = match options.auto_orient { true => meta.orientation, false => 1 };
+
+    // svg documents are kept as vectors so they can be drawn at any scale
+    let picture = match meta.format.as_deref() {
+      Some("svg") => picture_from_svg(data.as_bytes()),
+      _ => None
+    };
+
+    // animations start out on their first frame (which for APNGs isn't necessarily the default image)
+    let frames = match &picture {
+      Some(_) => None,
+      None => AnimatedSource::read(&data)
+    };
+    let first_frame = frames.as_ref().and_then(|frames| frames.decode(0).ok());
+    let still_frame = || match options.scale < 1.0 {
+      true => scaled_image(&data, options.scale),
+      false => SkImage::from_encoded(data.clone())
+    };
+
+    // fall back to treating the data as a serialized picture (rendered lazily at its natural size)
+    let image = match &picture {
+      Some(picture) => image_from_picture(picture.clone()),
+      None => first_frame.or_else(still_frame)
+        .map(|image| oriented(image, orientation))
+        .or_else(|| picture_from_data(data.as_bytes()).and_then(image_from_picture))
+    };
+
+    // skia normally defers decoding (or rendering) until the image is first drawn
+    let image = match options.raster {
+      true => image.map(|image| image.to_raster_image(None).unwrap_or(image)),
+      false => image
+    };
+
+    Decoded{ image, picture, meta, frames, orientation }
+  }
+}
+
+impl Image{
+  fn set_decoded(&mut self, decoded:Decoded) -> bool{
+    let Decoded{ image, picture, meta, frames, orientation } = decoded;
+    self.image = image;
+    self.picture = picture;
+    self.meta = meta;
+    self.frames = frames;
+    self.frame = 0;
+    self.orientation = orientation;
+    self.image.is_some()
+  }
+}
+
+// decodes a still image at a reduced size, letting the codec do the work where it can (JPEGs can be
+// decoded directly at 1/8 steps of their full size) and resampling whatever it couldn't reach
+fn scaled_image(data:&Data, scale:f32) -> Option<SkImage>{
+  let mut codec = Codec::from_data(data.clone())?;
+  let dims = codec.dimensions();
+  let target = ISize::new(
+    ((dims.width as f32 * scale).round() as i32).max(1),
+    ((dims.height as f32 * scale).round() as i32).max(1)
+  );
+
+  let info = codec.info()
+    .with_dimensions(codec.get_scaled_dimensions(scale))
+    .with_color_type(ColorType::N32)
+    .with_alpha_type(AlphaType::Premul);
+  let row_bytes = info.min_row_bytes();
+  let mut pixels = vec![0u8; info.compute_byte_size(row_bytes)];
+  match codec.get_pixels_with_options(&info, &mut pixels, row_bytes, None) {
+    CodecResult::Success | CodecResult::IncompleteInput => {},
+    _ => return None
+  }
+
+  let image = SkImage::from_raster_data(&info, Data::new_copy(&pixels), row_bytes)?;
+  if image.dimensions() == target {
+    return Some(image)
+  }
+
+  let mut surface = Surface::new_raster(&info.with_dimensions(target), None, None)?;
+  let sampling = SamplingOptions::new(FilterMode::Linear, MipmapMode::Linear);
+  surface.canvas().draw_image_rect_with_sampling_options(&image, None, Rect::from_isize(target), sampling, &Paint::default());
+  Some(surface.image_snapshot())
+}
+
+fn decode_options_arg(cx: &mut FunctionContext, idx: usize) -> DecodeOptions{
+  DecodeOptions{
+    auto_orient: bool_arg_or(cx, idx, true),
+    raster: bool_arg_or(cx, idx + 1, false),
+    scale: float_arg_or(cx, idx + 2, 1.0).clamp(0.0, 1.0),
+  }
+}
+
+//
+// Transformations (which render the image into a new bitmap rather than modifying it in place)
+//
+
+// draws into a bitmap of the given size (preserving the source's color type & space where possible)
+fn redraw(image:&SkImage, dims:ISize, draw:impl FnOnce(&mut Canvas)) -> Result<SkImage, String>{
+  let color_space = image.image_info().color_space();
+  let info = ImageInfo::new(dims, image.color_type(), AlphaType::Premul, color_space.clone());
+  let mut surface = Surface::new_raster(&info, None, None)
+    .or_else(|| Surface::new_raster(&ImageInfo::new_n32_premul(dims, color_space), None, None))
+    .ok_or(format!("Could not allocate new {}×{} bitmap", dims.width, dims.height))?;
+  draw(surface.canvas());
+  Ok(surface.image_snapshot())
+}
+
+fn cropped(image:&SkImage, rect:IRect) -> Result<SkImage, String>{
+  // regions extending beyond the image's bounds are left transparent
+  redraw(image, rect.size(), |canvas|{
+    canvas.draw_image(image, (-rect.left as f32, -rect.top as f32), None);
+  })
+}
+
+fn resized(image:&SkImage, dims:ISize, sampling:SamplingOptions) -> Result<SkImage, String>{
+  redraw(image, dims, |canvas|{
+    canvas.draw_image_rect_with_sampling_options(image, None, Rect::from_isize(dims), sampling, &Paint::default());
+  })
+}
+
+fn converted(image:&SkImage, color_type:ColorType, premultiplied:bool) -> Result<SkImage, String>{
+  let alpha_type = match premultiplied { true => AlphaType::Premul, false => AlphaType::Unpremul };
+  let info = ImageInfo::new(image.dimensions(), color_type, alpha_type, image.image_info().color_space());
+  let row_bytes = info.min_row_bytes();
+  let mut pixels = vec![0u8; info.compute_byte_size(row_bytes)];
+  match image.read_pixels(&info, &mut pixels, row_bytes, (0, 0), CachingHint::Allow){
+    true => SkImage::from_raster_data(&info, Data::new_copy(&pixels), row_bytes),
+    false => None
+  }.ok_or(format!("Could not convert image to {}", from_color_type(color_type)))
+}
+
+// converts the image to the requested color space (or to 16 bits per channel) before encoding it just as a canvas would be
+fn encoded(image:&SkImage, format:EncodedImageFormat, color_space:Option<ColorSpace>, options:&ImageOptions) -> Result<Data, String>{
+  let color_type = match options.bit_depth {
+    BitDepth::F16 => ColorType::RGBAF16,
+    _ => image.color_type()
+  };
+  let image = match color_space.is_some() || color_type != image.color_type() {
+    true => {
+      let info = ImageInfo::new(image.dimensions(), color_type, AlphaType::Premul, color_space.or_else(|| image.image_info().color_space()));
+      let mut surface = Surface::new_raster(&info, None, None)
+        .ok_or(format!("Could not allocate new {}×{} bitmap", image.width(), image.height()))?;
+      surface.canvas().draw_image(image, (0, 0), None);
+      surface.image_snapshot()
+    },
+    false => image.clone()
+  };
+  encode_image(&image, format, options)
+}
+
+// applies an operation to `this` image and stores the result in the `target` (the 2nd argument)
+fn derive(cx:&mut FunctionContext, op:impl FnOnce(&SkImage) -> Result<SkImage, String>) -> JsResult<JsUndefined>{
+  let this = cx.argument::<BoxedImage>(0)?;
+  let target = cx.argument::<BoxedImage>(1)?;
+
+  let result = match &this.borrow().image {
+    Some(image) => op(image),
+    None => Err("Image has not been loaded yet".to_string())
+  };
+  match result {
+    Ok(image) => {
+      let mut target = target.borrow_mut();
+      target.generation += 1;
+      target.set_decoded(Decoded{ image:Some(image), ..Decoded::empty() });
+      Ok(cx.undefined())
+    },
+    Err(msg) => cx.throw_error(msg)
+  }
+}
+
 //
 // -- Javascript Methods --------------------------------------------------------------------------
 //
 
 pub fn new(mut cx: FunctionContext) -> JsResult<BoxedImage> {
-  let this = RefCell::new(Image{ src:"".to_string(), image:None });
+  let this = RefCell::new(Image{
+    src:"".to_string(), image:None, picture:None, meta:SourceMetadata::default(), frames:None, frame:0, orientation:1, generation:0
+  });
   Ok(cx.boxed(this))
 }
 
@@ -61,13 +380,46 @@ pub fn set_src(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 
 pub fn set_data(mut cx: FunctionContext) -> JsResult<JsBoolean> {
   let this = cx.argument::<BoxedImage>(0)?;
+  let buffer = cx.argument::<JsBuffer>(1)?;
+  let data = Data::new_copy(buffer.as_slice(&mut cx));
+  let options = decode_options_arg(&mut cx, 2);
+
+  let decoded = Decoded::from_data(data, &options);
   let mut this = this.borrow_mut();
+  this.generation += 1;
+  Ok(cx.boolean(this.set_decoded(decoded)))
+}
 
+pub fn decode_data(mut cx: FunctionContext) -> JsResult<JsPromise> {
+  let this = cx.argument::<BoxedImage>(0)?;
   let buffer = cx.argument::<JsBuffer>(1)?;
   let data = Data::new_copy(buffer.as_slice(&mut cx));
+  let options = decode_options_arg(&mut cx, 2);
 
-  this.image = SkImage::from_encoded(data);
-  Ok(cx.boolean(this.image.is_some()))
+  // the image is incomplete until the background decode finishes
+  let generation = {
+    let mut this = this.borrow_mut();
+    this.set_decoded(Decoded::empty());
+    this.generation += 1;
+    this.generation
+  };
+
+  let this = this.root(&mut cx);
+  let promise = cx
+    .task(move || Decoded::from_data(data, &options))
+    .promise(move |mut cx, decoded| {
+      let this = this.into_inner(&mut cx);
+      let mut this = this.borrow_mut();
+
+      // ignore the results if a different source was assigned in the meantime
+      let success = match this.generation == generation {
+        true => this.set_decoded(decoded),
+        false => false
+      };
+      Ok(cx.boolean(success))
+    });
+
+  Ok(promise)
 }
 
 pub fn load_pixel_data(mut cx: FunctionContext) -> JsResult<JsBoolean> {
@@ -90,6 +442,11 @@ pub fn load_pixel_data(mut cx: FunctionContext) -> JsResult<JsBoolean> {
 
   let image_info = make_raw_image_info((width, height), premult, ctype);
   this.image = SkImage::from_raster_data(&image_info, data, image_info.min_row_bytes());
+  this.generation += 1;
+  this.picture = None;
+  this.meta = SourceMetadata::default();
+  this.frames = None;
+  this.frame = 0;
 
   Ok(cx.boolean(this.image.is_some()))
 }
@@ -119,3 +476,240 @@ pub fn get_complete(mut cx: FunctionContext) -> JsResult<JsBoolean> {
   let this = this.borrow();
   Ok(cx.boolean(this.image.is_some()))
 }
+
+pub fn get_format(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  match &this.meta.format {
+    Some(format) => Ok(cx.string(format).upcast()),
+    None => Ok(cx.undefined().upcast())
+  }
+}
+
+pub fn get_orientation(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+  Ok(cx.number(this.meta.orientation as f64))
+}
+
+pub fn get_density(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  // expressed relative to 72 dpi (like the export functions' `density` option)
+  let density = this.meta.dpi.map_or(1.0, |(x, _)| x / 72.0);
+  Ok(cx.number(density as f64))
+}
+
+#[allow(non_snake_case)]
+pub fn get_colorSpace(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  let color_space = match &this.image {
+    Some(image) => image.image_info().color_space(),
+    None => return Ok(cx.undefined().upcast())
+  };
+
+  // use the export option's name for familiar color spaces or the embedded profile's for anything else
+  let name = color_space
+    .and_then(|color_space| from_color_space(&color_space).map(str::to_string))
+    .or_else(|| this.meta.icc_name())
+    .unwrap_or_else(|| "srgb".to_string());
+  Ok(cx.string(name).upcast())
+}
+
+pub fn get_exif(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  match &this.meta.exif {
+    Some(exif) => {
+      let mut buffer = cx.buffer(exif.len())?;
+      buffer.as_mut_slice(&mut cx).copy_from_slice(exif);
+      Ok(buffer.upcast())
+    },
+    None => Ok(cx.undefined().upcast())
+  }
+}
+
+pub fn get_xmp(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  match &this.meta.xmp {
+    Some(xmp) => Ok(cx.string(xmp).upcast()),
+    None => Ok(cx.undefined().upcast())
+  }
+}
+
+#[allow(non_snake_case)]
+pub fn get_frameCount(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  let count = match (&this.frames, &this.image) {
+    (Some(frames), _) => frames.frame_count(),
+    (None, Some(_)) => 1,
+    (None, None) => 0
+  };
+  Ok(cx.number(count as f64))
+}
+
+pub fn get_durations(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  let durations = match (&this.frames, &this.image) {
+    (Some(frames), _) => frames.durations.clone(),
+    (None, Some(_)) => vec![0.0],
+    (None, None) => vec![]
+  };
+  floats_to_array(&mut cx, &durations)
+}
+
+pub fn get_loop(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  // like the `loop` export option, 0 means repeat forever (and still images ‘play’ once)
+  let loops = this.frames.as_ref().map_or(1, |frames| frames.loops);
+  Ok(cx.number(loops as f64))
+}
+
+pub fn get_frame(mut cx: FunctionContext) -> JsResult<JsNumber> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+  Ok(cx.number(this.frame as f64))
+}
+
+pub fn set_frame(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let mut this = this.borrow_mut();
+  let index = float_arg(&mut cx, 1, "frame")? as usize;
+
+  if index == this.frame {
+    return Ok(cx.undefined())
+  }
+
+  let decoded = match &this.frames {
+    Some(frames) => frames.decode(index),
+    None => Err(format!("Frame {} is out of range (the image has 1)", index))
+  };
+  match decoded {
+    Ok(image) => {
+      this.image = Some(oriented(image, this.orientation));
+      this.frame = index;
+      Ok(cx.undefined())
+    },
+    Err(msg) => cx.throw_range_error(msg)
+  }
+}
+
+#[allow(non_snake_case)]
+pub fn get_colorType(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  match &this.image {
+    Some(image) => Ok(cx.string(from_color_type(image.color_type())).upcast()),
+    None => Ok(cx.undefined().upcast())
+  }
+}
+
+pub fn get_premultiplied(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let this = this.borrow();
+
+  match &this.image {
+    Some(image) => Ok(cx.boolean(image.alpha_type() == AlphaType::Premul).upcast()),
+    None => Ok(cx.undefined().upcast())
+  }
+}
+
+pub fn crop(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let x = float_arg(&mut cx, 2, "x")?;
+  let y = float_arg(&mut cx, 3, "y")?;
+  let width = float_arg(&mut cx, 4, "width")?;
+  let height = float_arg(&mut cx, 5, "height")?;
+  let rect = IRect::from_xywh(x.round() as i32, y.round() as i32, width.round() as i32, height.round() as i32);
+
+  derive(&mut cx, |image| cropped(image, rect))
+}
+
+pub fn resize(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let width = float_arg(&mut cx, 2, "width")?;
+  let height = float_arg(&mut cx, 3, "height")?;
+  let dims = ISize::new(width.round() as i32, height.round() as i32);
+
+  // uses the same filtering as drawImage() with the equivalent imageSmoothingQuality
+  let quality = string_arg_or(&mut cx, 4, "high");
+  let sampling = match to_filter_quality(&quality) {
+    Some(quality) => ImageFilter{ smoothing:true, quality }.sampling(),
+    None => ImageFilter{ smoothing:false, quality:FilterQuality::None }.sampling()
+  };
+
+  derive(&mut cx, |image| resized(image, dims, sampling))
+}
+
+pub fn orient(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  // rotations & flips are expressed as the equivalent EXIF orientation value
+  let orientation = float_arg(&mut cx, 2, "orientation")? as u16;
+  derive(&mut cx, |image| Ok(oriented(image.clone(), orientation)))
+}
+
+pub fn convert(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+  let color_type = color_type_arg(&mut cx, 2).unwrap_or(ColorType::RGBA8888);
+  let premultiplied = bool_arg_or(&mut cx, 3, false);
+  derive(&mut cx, |image| converted(image, color_type, premultiplied))
+}
+
+#[allow(non_snake_case)]
+pub fn toBuffer(mut cx: FunctionContext) -> JsResult<JsPromise> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let (format, color_space, options) = encoding_arg(&mut cx, 1)?;
+
+  let image = this.borrow().image.clone();
+  let promise = cx
+    .task(move || match image {
+      Some(image) => encoded(&image, format, color_space, &options),
+      None => Err("Image has not been loaded yet".to_string())
+    })
+    .promise(move |mut cx, result| {
+      let data = result.or_else(|err| cx.throw_error(err))?;
+      let mut buffer = cx.buffer(data.len())?;
+      buffer.as_mut_slice(&mut cx).copy_from_slice(&data);
+      Ok(buffer)
+    });
+
+  Ok(promise)
+}
+
+#[allow(non_snake_case)]
+pub fn toBufferSync(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+  let this = cx.argument::<BoxedImage>(0)?;
+  let (format, color_space, options) = encoding_arg(&mut cx, 1)?;
+
+  let result = match &this.borrow().image {
+    Some(image) => encoded(image, format, color_space, &options),
+    None => Err("Image has not been loaded yet".to_string())
+  };
+  let data = result.or_else(|err| cx.throw_error(err))?;
+  let mut buffer = cx.buffer(data.len())?;
+  buffer.as_mut_slice(&mut cx).copy_from_slice(&data);
+  Ok(buffer)
+}
+
+// the same encoding options as a canvas export, though the image is only converted if a colorSpace is specified
+fn encoding_arg(cx:&mut FunctionContext, idx:usize) -> NeonResult<(EncodedImageFormat, Option<ColorSpace>, ImageOptions)>{
+  let obj = cx.argument::<JsObject>(idx as i32)?;
+  let format = string_for_key(cx, &obj, "format")?;
+  let format = match to_image_format(&format){
+    Some(format) => format,
+    None => return cx.throw_type_error(format!("Unsupported image format {} (expected png, jpg, or webp)", format))
+  };
+  let color_space = opt_string_for_key(cx, &obj, "colorSpace").and_then(|name| to_color_space(&name));
+  let options = image_options_for_keys(cx, &obj)?;
+  Ok((format, color_space, options))
+}
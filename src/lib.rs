@@ -13,6 +13,7 @@ mod pattern;
 mod texture;
 mod typography;
 mod utils;
+mod compare;
 mod gpu;
 #[cfg(feature = "window")]
 mod gui;
@@ -31,10 +32,30 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
   cx.export_function("Image_get_src", image::get_src)?;
   cx.export_function("Image_set_src", image::set_src)?;
   cx.export_function("Image_set_data", image::set_data)?;
+  cx.export_function("Image_decode_data", image::decode_data)?;
   cx.export_function("Image_load_pixel_data", image::load_pixel_data)?;
   cx.export_function("Image_get_width", image::get_width)?;
   cx.export_function("Image_get_height", image::get_height)?;
   cx.export_function("Image_get_complete", image::get_complete)?;
+  cx.export_function("Image_get_format", image::get_format)?;
+  cx.export_function("Image_get_orientation", image::get_orientation)?;
+  cx.export_function("Image_get_density", image::get_density)?;
+  cx.export_function("Image_get_colorSpace", image::get_colorSpace)?;
+  cx.export_function("Image_get_exif", image::get_exif)?;
+  cx.export_function("Image_get_xmp", image::get_xmp)?;
+  cx.export_function("Image_get_frameCount", image::get_frameCount)?;
+  cx.export_function("Image_get_durations", image::get_durations)?;
+  cx.export_function("Image_get_loop", image::get_loop)?;
+  cx.export_function("Image_get_frame", image::get_frame)?;
+  cx.export_function("Image_set_frame", image::set_frame)?;
+  cx.export_function("Image_get_colorType", image::get_colorType)?;
+  cx.export_function("Image_get_premultiplied", image::get_premultiplied)?;
+  cx.export_function("Image_crop", image::crop)?;
+  cx.export_function("Image_resize", image::resize)?;
+  cx.export_function("Image_orient", image::orient)?;
+  cx.export_function("Image_convert", image::convert)?;
+  cx.export_function("Image_toBuffer", image::toBuffer)?;
+  cx.export_function("Image_toBufferSync", image::toBufferSync)?;
 
   // -- Path2D ------------------------------------------------------------------------------------
 
@@ -112,15 +133,25 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
   cx.export_function("Canvas_set_async", canvas::set_async)?;
   cx.export_function("Canvas_save", canvas::save)?;
   cx.export_function("Canvas_saveSync", canvas::saveSync)?;
+  cx.export_function("Canvas_saveTiles", canvas::saveTiles)?;
+  cx.export_function("Canvas_toThumbnails", canvas::toThumbnails)?;
+  cx.export_function("Canvas_toThumbnailsSync", canvas::toThumbnailsSync)?;
   cx.export_function("Canvas_toBuffer", canvas::toBuffer)?;
   cx.export_function("Canvas_toBufferSync", canvas::toBufferSync)?;
+  cx.export_function("Canvas_toStream", canvas::toStream)?;
+  cx.export_function("Canvas_readStream", canvas::readStream)?;
+  cx.export_function("Canvas_closeStream", canvas::closeStream)?;
   cx.export_function("Canvas_toRaw", canvas::toRaw)?;
 
+  cx.export_function("CancelToken_new", context::progress::new)?;
+  cx.export_function("CancelToken_cancel", context::progress::cancel)?;
+
   // -- Context -----------------------------------------------------------------------------------
 
   cx.export_function("CanvasRenderingContext2D_new", ctx::new)?;
   cx.export_function("CanvasRenderingContext2D_resetSize", ctx::resetSize)?;
   cx.export_function("CanvasRenderingContext2D_get_size", ctx::get_size)?;
+  cx.export_function("CanvasRenderingContext2D_get_layerCount", ctx::get_layerCount)?;
   cx.export_function("CanvasRenderingContext2D_set_size", ctx::set_size)?;
   cx.export_function("CanvasRenderingContext2D_reset", ctx::reset)?;
 
@@ -185,6 +216,7 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
   // imagery
   cx.export_function("CanvasRenderingContext2D_drawImage", ctx::drawImage)?;
   cx.export_function("CanvasRenderingContext2D_drawCanvas", ctx::drawCanvas)?;
+  cx.export_function("CanvasRenderingContext2D_loadPicture", ctx::loadPicture)?;
   cx.export_function("CanvasRenderingContext2D_getImageData", ctx::getImageData)?;
   cx.export_function("CanvasRenderingContext2D_putImageData", ctx::putImageData)?;
   cx.export_function("CanvasRenderingContext2D_get_imageSmoothingEnabled", ctx::get_imageSmoothingEnabled)?;
@@ -228,9 +260,18 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
   cx.export_function("CanvasRenderingContext2D_set_shadowOffsetX", ctx::set_shadowOffsetX)?;
   cx.export_function("CanvasRenderingContext2D_set_shadowOffsetY", ctx::set_shadowOffsetY)?;
 
+  // annotations
+  cx.export_function("CanvasRenderingContext2D_addLink", ctx::addLink)?;
+  cx.export_function("CanvasRenderingContext2D_addTextField", ctx::addTextField)?;
+  cx.export_function("CanvasRenderingContext2D_beginTag", ctx::beginTag)?;
+  cx.export_function("CanvasRenderingContext2D_endTag", ctx::endTag)?;
+  cx.export_function("CanvasRenderingContext2D_addDestination", ctx::addDestination)?;
+
   // -- Utilities---------------------------------------------------------------------------------
 
   cx.export_function("utils_colorTypeBytesPerPixel", utils::to_color_type_bytes_per_pixel)?;
+  cx.export_function("utils_compareImages", compare::compareImages)?;
+  cx.export_function("utils_compareImagesSync", compare::compareImagesSync)?;
 
   // -- Window -----------------------------------------------------------------------------------
 
@@ -64,9 +64,10 @@ pub fn from_image(mut cx: FunctionContext) -> JsResult<BoxedCanvasPattern> {
   if let Some(repeat) = to_repeat_mode(&repetition){
     let src = src.borrow();
     let dims = src.size();
+    // prefer the vector version of svg images so the pattern isn't pixelated when scaled up
     let stamp = Stamp{
-      image:src.image.clone(),
-      pict:None,
+      image:match src.picture { Some(_) => None, None => src.image.clone() },
+      pict:src.picture.clone(),
       dims,
       repeat,
       matrix:Matrix::new_identity()
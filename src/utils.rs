@@ -8,9 +8,9 @@ use core::ops::Range;
 use neon::prelude::*;
 use css_color::Rgba;
 use skia_safe::{
-  AlphaType, BlendMode, Color, ColorType, ColorSpace, ImageInfo, ISize, Matrix,
+  AlphaType, BlendMode, Color, ColorType, ColorSpace, Data, ImageInfo, ISize, Matrix,
   PaintCap, PaintJoin, Path, path_1d_path_effect, path::FillType, PathOp, Point,
-  Rect, RGB, Size, TileMode, TileMode::{Decal, Repeat}
+  Rect, RGB, Size, TileMode, TileMode::{Decal, Repeat}, image::BitDepth
 };
 
 use crate::filter::{FilterSpec, FilterQuality};
@@ -65,6 +65,16 @@ pub fn check_argc(cx: &mut FunctionContext, argc:i32) -> NeonResult<()>{
   }
 }
 
+// an options-object entry, treating undefined and null values as missing
+pub fn opt_value_for_key<'a>(cx: &mut FunctionContext<'a>, obj: &Handle<JsObject>, attr:&str) -> Option<Handle<'a, JsValue>>{
+  let key = cx.string(attr);
+  match obj.get_value(cx, key) {
+    Ok(val) if val.is_a::<JsUndefined, _>(cx) || val.is_a::<JsNull, _>(cx) => None,
+    Ok(val) => Some(val),
+    Err(_e) => None
+  }
+}
+
 
 // pub fn symbol<'a>(cx: &mut FunctionContext<'a>, symbol_name: &str) -> JsResult<'a, JsValue> {
 //   let global = cx.global();
@@ -111,6 +121,17 @@ pub fn string_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&st
   }
 }
 
+pub fn opt_string_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> Option<String>{
+  let key = cx.string(attr);
+  match obj.get_value(cx, key) {
+    Ok(val) => match val.downcast::<JsString, _>(cx){
+      Ok(s) => Some(s.value(cx)),
+      Err(_e) => None
+    },
+    Err(_e) => None
+  }
+}
+
 pub fn opt_string_arg(cx: &mut FunctionContext, idx: usize) -> Option<String>{
   match cx.argument_opt(idx as i32) {
     Some(arg) => match arg.downcast::<JsString, _>(cx) {
@@ -181,6 +202,17 @@ pub fn bool_arg_or(cx: &mut FunctionContext, idx: usize, default:bool) -> bool{
   }
 }
 
+pub fn opt_bool_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> Option<bool>{
+  let key = cx.string(attr);
+  match obj.get_value(cx, key) {
+    Ok(val) => match val.downcast::<JsBoolean, _>(cx){
+      Ok(b) => Some(b.value(cx)),
+      Err(_e) => None
+    },
+    Err(_e) => None
+  }
+}
+
 pub fn bool_arg(cx: &mut FunctionContext, idx: usize, attr:&str) -> NeonResult<bool>{
   let exists = cx.len() > idx as i32;
   match opt_bool_arg(cx, idx){
@@ -258,7 +290,7 @@ pub fn float_arg(cx: &mut FunctionContext, idx: usize, attr:&str) -> NeonResult<
   }
 }
 
-pub fn floats_to_array<'a>(cx: &mut FunctionContext<'a>, nums: &[f32]) -> JsResult<'a, JsValue> {
+pub fn floats_to_array<'a>(cx: &mut impl Context<'a>, nums: &[f32]) -> JsResult<'a, JsValue> {
   let array = JsArray::new(cx, nums.len() as u32);
   for (i, val) in nums.iter().enumerate() {
     let num = cx.number(*val);
@@ -345,6 +377,13 @@ pub fn color_arg(cx: &mut FunctionContext, idx: usize) -> Option<Color> {
   }
 }
 
+pub fn opt_color_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> Option<Color> {
+  match opt_value_for_key(cx, obj, attr) {
+    Some(val) => color_in(cx, val),
+    None => None
+  }
+}
+
 pub fn color_to_css<'a>(cx: &mut FunctionContext<'a>, color:&Color) -> JsResult<'a, JsValue> {
   let RGB {r, g, b} = color.to_rgb();
   let css = match color.a() {
@@ -366,6 +405,10 @@ pub fn color_type_arg(cx: &mut FunctionContext, idx: usize) -> Option<ColorType>
   None
 }
 
+pub fn opt_color_type_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> Option<ColorType> {
+  opt_string_for_key(cx, obj, attr).map(|name| to_color_type(&name))
+}
+
 // Exported utility to return bytes per pixel of a color type.
 pub fn to_color_type_bytes_per_pixel(mut cx: FunctionContext) -> JsResult<JsValue> {
   if let Some(ctype) = color_type_arg(&mut cx, 0) {
@@ -490,6 +533,21 @@ pub fn opt_rect_obj_arg(cx: &mut FunctionContext, idx: usize, default_size: impl
   None
 }
 
+pub fn opt_rect_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str, default_size: impl Into<Size>) -> Option<Rect> {
+  let rect = opt_value_for_key(cx, obj, attr)?.downcast::<JsObject, _>(cx).ok()?;
+  let x = opt_float_for_key(cx, &rect, "left");
+  let y = opt_float_for_key(cx, &rect, "top");
+  let w = opt_float_for_key(cx, &rect, "width");
+  let h = opt_float_for_key(cx, &rect, "height");
+  let size = default_size.into();
+  Some(skia_safe::Rect::from_xywh(
+    x.unwrap_or(0.0f32),
+    y.unwrap_or(0.0f32),
+    w.unwrap_or(size.width),
+    h.unwrap_or(size.height)
+  ))
+}
+
 //
 // Path2D
 //
@@ -802,6 +860,67 @@ pub fn from_color_type(color_type: ColorType) -> String {
   }.to_string()
 }
 
+// transfer function & D50-adapted gamut matrix (as defined in skcms's named constants)
+const SRGB_TRANSFER_FN:[f32; 7] = [2.4, 1.0/1.055, 0.055/1.055, 1.0/12.92, 0.04045, 0.0, 0.0];
+const REC2020_TRANSFER_FN:[f32; 7] = [2.22222, 0.909672, 0.0903276, 0.222222, 0.0812429, 0.0, 0.0];
+const DISPLAY_P3_GAMUT:[f32; 9] = [
+   0.515102,   0.291965,  0.157153,
+   0.241182,   0.692236,  0.0665819,
+  -0.00104941, 0.0418818, 0.784378,
+];
+const REC2020_GAMUT:[f32; 9] = [
+   0.673459,   0.165661,  0.125100,
+   0.279033,   0.675338,  0.0456288,
+  -0.00193139, 0.0299794, 0.797162,
+];
+
+fn make_color_space(transfer_fn:&[f32; 7], to_xyz_d50:&[f32; 9]) -> ColorSpace {
+  // skia-safe doesn't wrap SkColorSpace::MakeRGB, but its serialization format is just a
+  // version header followed by the transfer function & gamut matrix
+  let mut bytes = vec![1, 0, 0, 0];
+  for term in transfer_fn.iter().chain(to_xyz_d50.iter()){
+    bytes.extend(term.to_ne_bytes());
+  }
+  ColorSpace::deserialize(Data::new_copy(&bytes))
+}
+
+pub fn to_color_space(name:&str) -> Option<ColorSpace> {
+  let color_space = match name {
+    "srgb" => ColorSpace::new_srgb(),
+    "srgb-linear" => ColorSpace::new_srgb_linear(),
+    "display-p3" => make_color_space(&SRGB_TRANSFER_FN, &DISPLAY_P3_GAMUT),
+    "rec2020" => make_color_space(&REC2020_TRANSFER_FN, &REC2020_GAMUT),
+    _ => return None
+  };
+  Some(color_space)
+}
+
+pub fn from_color_space(color_space:&ColorSpace) -> Option<&'static str> {
+  // compare the serialized terms loosely since profiles read from ICC data have lost some precision
+  let terms = |color_space:&ColorSpace| color_space.serialize().as_bytes().get(4..68).map(|bytes|
+    bytes.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<_>>()
+  );
+  let target = terms(color_space)?;
+  ["srgb", "srgb-linear", "display-p3", "rec2020"].iter().copied().find(|name|
+    to_color_space(name).and_then(|known| terms(&known)).map_or(false, |known|
+      known.iter().zip(&target).all(|(a, b)| (a - b).abs() < 0.001)
+    )
+  )
+}
+
+pub fn color_space_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> ColorSpace {
+  opt_string_for_key(cx, obj, attr)
+    .and_then(|name| to_color_space(&name))
+    .unwrap_or_else(ColorSpace::new_srgb)
+}
+
+pub fn bit_depth_for_key(cx: &mut FunctionContext, obj: &Handle<JsObject>, attr:&str) -> BitDepth {
+  match opt_float_for_key(cx, obj, attr).unwrap_or(8.0) as i32 {
+    16 => BitDepth::F16,
+    _ => BitDepth::U8
+  }
+}
+
 // pub fn blend_mode_arg(cx: &mut FunctionContext, idx: usize, attr: &str) -> NeonResult<BlendMode>{
 //   let mode_name = string_arg(cx, idx, attr)?;
 //   match to_blend_mode(&mode_name){
@@ -4,7 +4,7 @@ const _ = require('lodash'),
       fs = require('fs'),
       tmp = require('tmp'),
       glob = require('glob').sync,
-      {Canvas, Image} = require('../lib');
+      {Canvas, Image, compareImages, compareImagesSync} = require('../lib');
 
 const BLACK = [0,0,0,255],
       WHITE = [255,255,255,255],
@@ -12,12 +12,16 @@ const BLACK = [0,0,0,255],
       MAGIC = {
         jpg: Buffer.from([0xFF, 0xD8, 0xFF]),
         png: Buffer.from([0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]),
+        webp: Buffer.from(`RIFF`, 'utf-8'),
+        gif: Buffer.from(`GIF89a`, 'utf-8'),
         pdf: Buffer.from([0x25, 0x50, 0x44, 0x46, 0x2d]),
         svg: Buffer.from(`<?xml version`, 'utf-8')
       },
       MIME = {
         png: "image/png",
         jpg: "image/jpeg",
+        webp: "image/webp",
+        gif: "image/gif",
         pdf: "application/pdf",
         svg: "image/svg+xml"
       };
@@ -131,7 +135,7 @@ describe("Canvas", ()=>{
     })
 
     test("export file formats", async () => {
-      expect(() => canvas.saveAs(`${TMP}/output.gif`) ).toThrowError('Unsupported file format');
+      expect(() => canvas.saveAs(`${TMP}/output.psd`) ).toThrowError('Unsupported file format');
       expect(() => canvas.saveAs(`${TMP}/output.targa`) ).toThrowError('Unsupported file format');
       expect(() => canvas.saveAs(`${TMP}/output`) ).toThrowError('Cannot determine image format');
       expect(() => canvas.saveAs(`${TMP}/`) ).toThrowError('Cannot determine image format');
@@ -183,6 +187,257 @@ describe("Canvas", ()=>{
       }
     })
 
+    test("WEBPs", async ()=>{
+      await Promise.all([
+        canvas.saveAs(`${TMP}/output1.webp`),
+        canvas.saveAs(`${TMP}/output2.WEBP`),
+        canvas.saveAs(`${TMP}/output3`, {format:'webp'}),
+        canvas.saveAs(`${TMP}/output4.png`, {format:'webp', lossless:true}),
+      ])
+
+      for (let path of findTmp(`/*`)){
+        let data = fs.readFileSync(path)
+        expect(data.slice(0, 4).toString()).toEqual('RIFF')
+        expect(data.slice(8, 12).toString()).toEqual('WEBP')
+      }
+
+      let lossy = await canvas.toBuffer('webp', {quality:0.5}),
+          lossless = await canvas.toBuffer('webp', {lossless:true})
+      expect(lossy.slice(12, 16).toString()).toEqual('VP8 ')
+      expect(lossless.slice(12, 16).toString()).toEqual('VP8L')
+    })
+
+    test("wide-gamut & 16-bit images", async ()=>{
+      let png = await canvas.toBuffer('png'),
+          png16 = await canvas.toBuffer('png', {bitDepth:16}),
+          p3 = await canvas.toBuffer('png', {colorSpace:'display-p3'}),
+          jpg = await canvas.toBuffer('jpg', {colorSpace:'rec2020'})
+
+      // bit depth is the first byte after the IHDR chunk's width & height
+      expect(png[24]).toEqual(8)
+      expect(png16[24]).toEqual(16)
+      expect(p3.includes('iCCP')).toBe(true)
+      expect(jpg.includes('ICC_PROFILE')).toBe(true)
+
+      expect(() => canvas.toBuffer('png', {bitDepth:32})).toThrowError('bitDepth')
+      expect(() => canvas.toBuffer('png', {colorSpace:'cmyk'})).toThrowError('colorSpace')
+    })
+
+    test("encoder options", async ()=>{
+      let fast = await canvas.toBuffer('png', {encoding:{compression:0, filters:'none'}}),
+          small = await canvas.toBuffer('png', {encoding:{compression:9}}),
+          indexed = await canvas.toBuffer('png', {encoding:{palette:true}}),
+          baseline = await canvas.toBuffer('jpg', {encoding:{chromaSubsampling:'4:4:4'}}),
+          progressive = await canvas.toBuffer('jpg', {encoding:{progressive:true}})
+
+      expect(fast.length).toBeGreaterThan(small.length)
+      expect(indexed[25]).toEqual(3) // color type follows the bit depth in the IHDR chunk
+      expect(indexed.includes('PLTE')).toBe(true)
+      expect(baseline.includes(Buffer.from([0xFF, 0xC0]))).toBe(true)
+      expect(progressive.includes(Buffer.from([0xFF, 0xC2]))).toBe(true)
+
+      expect(() => canvas.toBuffer('png', {encoding:{compression:11}})).toThrowError('compression')
+      expect(() => canvas.toBuffer('png', {encoding:{filters:['up', 'down']}})).toThrowError('filters')
+      expect(() => canvas.toBuffer('jpg', {encoding:{chromaSubsampling:'4:2:1'}})).toThrowError('chromaSubsampling')
+      expect(() => canvas.toBuffer('png', {bitDepth:16, encoding:{palette:true}})).toThrowError('palette')
+    })
+
+    test("image metadata", async ()=>{
+      let xmp = `<x:xmpmeta xmlns:x="adobe:ns:meta/"></x:xmpmeta>`,
+          metadata = {title:"Test Image", creator:"Test Suite", copyright:"© 2023", xmp, text:{Comment:"embedded"}},
+          png = await canvas.toBuffer('png', {metadata}),
+          jpg = await canvas.toBuffer('jpg', {metadata}),
+          webp = await canvas.toBuffer('webp', {metadata})
+
+      expect(png.includes('eXIf')).toBe(true)
+      expect(png.includes('tEXtComment\0embedded')).toBe(true)
+      expect(png.includes('XML:com.adobe.xmp')).toBe(true)
+      expect(jpg.includes('Exif\0\0MM')).toBe(true)
+      expect(jpg.includes('http://ns.adobe.com/xap/1.0/')).toBe(true)
+      expect(webp.slice(12, 16).toString()).toEqual('VP8X')
+      expect(webp.includes('EXIF')).toBe(true)
+      expect(webp.includes(xmp)).toBe(true)
+
+      for (let data of [png, jpg, webp]){
+        expect(data.includes('Test Image')).toBe(true)
+        expect(data.includes('© 2023')).toBe(true)
+      }
+
+      expect(() => canvas.toBuffer('png', {metadata:{text:{" leading":"space"}}})).toThrowError('Text metadata')
+      expect(() => canvas.toBuffer('png', {metadata:{text:{"説明":"non-latin-1"}}})).toThrowError('Latin-1')
+      expect(() => canvas.toBuffer('png', {metadata:{text:{["k".repeat(80)]:"too long"}}})).toThrowError('Latin-1')
+    })
+
+    test("streams", async ()=>{
+      let chunks = []
+      for await (const chunk of canvas.toStream('png', {density:2})) chunks.push(chunk)
+      let streamed = Buffer.concat(chunks),
+          buffered = await canvas.toBuffer('png', {density:2})
+      expect(streamed.equals(buffered)).toBe(true)
+
+      // pngs with encoding options are streamed as they're compressed
+      let opts = {density:2, encoding:{compression:9}, metadata:{title:'Streamed'}}
+      chunks = []
+      for await (const chunk of canvas.toStream('png', opts)) chunks.push(chunk)
+      expect(Buffer.concat(chunks).equals(await canvas.toBuffer('png', opts))).toBe(true)
+
+      let controller = new AbortController(),
+          cancelled = canvas.toStream('png', {...opts, signal:controller.signal})
+      controller.abort()
+      await expect(new Promise((res, rej) => cancelled.on('error', rej).on('end', res).resume())).rejects.toThrow()
+
+      let offCanvas = canvas.toStream('png', {left:WIDTH * 2, top:HEIGHT * 2})
+      await expect(new Promise((res, rej) => offCanvas.on('error', rej).on('end', res).resume()))
+        .rejects.toThrowError('must be non-empty')
+    })
+
+    test("tile pyramids", async ()=>{
+      await canvas.saveTiles(`${TMP}/xyz`)
+      expect(findTmp('/xyz/*/*/*.png').length).toEqual(5)
+      let tile = new Image()
+      tile.src = `${TMP}/xyz/1/1/0.png`
+      expect(tile.width).toEqual(256)
+
+      await canvas.saveTiles(`${TMP}/poster`, {layout:'deepzoom', format:'jpg', overlap:1})
+      expect(findTmp('/poster_files/*/*.jpg').length).toEqual(13)
+      let dzi = fs.readFileSync(`${TMP}/poster.dzi`, 'utf-8')
+      expect(dzi).toMatch('TileSize="256"')
+      expect(dzi).toMatch('<Size Width="512" Height="512"/>')
+
+      expect(() => canvas.saveTiles(`${TMP}/bad`, {tileSize:0})).toThrowError('tileSize')
+      expect(() => canvas.saveTiles(`${TMP}/bad`, {overlap:2})).toThrowError('deepzoom')
+      expect(() => canvas.saveTiles(`${TMP}/bad`, {format:'pdf'})).toThrowError('Tiles must be')
+    })
+
+    test("thumbnails", async ()=>{
+      canvas.width = 400
+      ctx.fillStyle = 'lightskyblue'
+      ctx.fillRect(0, 0, canvas.width, canvas.height)
+
+      let thumbs = await canvas.toThumbnails([
+        {width:200, format:'jpg', quality:0.5}, {height:64, format:'webp'}, 50, {width:800}
+      ], {filter:'catmull-rom'})
+      expect(thumbs.length).toEqual(4)
+      expect(thumbs[0].slice(0, MAGIC.jpg.length)).toEqual(MAGIC.jpg)
+      expect(thumbs[1].slice(0, MAGIC.webp.length)).toEqual(MAGIC.webp)
+      expect(thumbs[2].slice(0, MAGIC.png.length)).toEqual(MAGIC.png)
+
+      let dims = thumbs.map(buf => Object.assign(new Image(), {src:buf})).map(({width, height}) => [width, height])
+      expect(dims).toEqual([[200, 256], [50, 64], [39, 50], [800, 1024]])
+
+      // a full-sized thumbnail is identical to the canvas's own output
+      let opts = {encoding:{palette:true}, metadata:{title:'Thumbnail'}},
+          [full] = await canvas.toThumbnails([{width:400}], opts)
+      expect(full.equals(canvas.toBufferSync('png', opts))).toBe(true)
+
+      let sync = canvas.toThumbnailsSync([32], {density:2})
+      expect(Object.assign(new Image(), {src:sync[0]})).toMatchObject({width:25, height:32})
+
+      expect(() => canvas.toThumbnails([])).toThrowError('array of thumbnail sizes')
+      expect(() => canvas.toThumbnails([{format:'png'}])).toThrowError('width, a height')
+      expect(() => canvas.toThumbnails([{width:10, format:'gif'}])).toThrowError('Thumbnails must be')
+      expect(() => canvas.toThumbnails([10], {filter:'box'})).toThrowError('filter')
+    })
+
+    test("cancellable exports with progress", async ()=>{
+      for (let i=0; i<7; i++) canvas.newPage().fillRect(0, 0, 10, 10)
+
+      let calls = [], reported,
+          finished = new Promise(res => reported = res),
+          onProgress = (done, total) => { calls.push([done, total]); if (done==total) reported() }
+      await Promise.all([canvas.saveAs(`${TMP}/page-{}.png`, {onProgress}), finished])
+      expect(calls).toEqual([1, 2, 3, 4, 5, 6, 7, 8].map(n => [n, 8]))
+      expect(findTmp('/page-*.png').length).toEqual(8)
+
+      let controller = new AbortController()
+      controller.abort()
+      await expect(canvas.toBuffer('pdf', {signal:controller.signal})).rejects.toThrow()
+      await expect(canvas.saveAs(`${TMP}/cancelled-{}.png`, {signal:controller.signal})).rejects.toThrow()
+      expect(findTmp('/cancelled-*.png').length).toEqual(0)
+
+      let pdf = await canvas.toBuffer('pdf', {signal:new AbortController().signal})
+      expect(pdf.slice(0, MAGIC.pdf.length)).toEqual(MAGIC.pdf)
+
+      expect(() => canvas.toBuffer('png', {signal:{}})).toThrowError('AbortSignal')
+      expect(() => canvas.toBuffer('png', {onProgress:true})).toThrowError('function')
+      expect(() => canvas.toBufferSync('png', {signal:controller.signal})).toThrowError('asynchronous')
+      expect(() => canvas.saveAsSync(`${TMP}/sync.png`, {onProgress})).toThrowError('asynchronous')
+    })
+
+    test("raw pixels with custom layouts", async ()=>{
+      canvas.width = 5
+      canvas.height = 3
+      ctx.fillStyle = 'red'
+      ctx.fillRect(0, 0, 5, 3)
+
+      let padded = await canvas.toRaw({rowBytes:32})
+      expect(padded.length).toEqual(32 * 3)
+      expect(Array.from(padded.slice(32, 36))).toEqual([255, 0, 0, 255])
+
+      let frame = Buffer.alloc(80, 7),
+          result = await canvas.toRaw({rowBytes:24, buffer:frame})
+      expect(frame.length).toEqual(0)
+      expect(result.length).toEqual(80)
+      expect(Array.from(result.slice(0, 4))).toEqual([255, 0, 0, 255])
+      expect(Array.from(result.slice(20, 24))).toEqual([7, 7, 7, 7])
+      expect(Array.from(result.slice(72))).toEqual([7, 7, 7, 7, 7, 7, 7, 7])
+      expect(await canvas.toRaw({rowBytes:24, buffer:result})).toHaveLength(80)
+
+      let yuv = await canvas.toRaw({layout:'yuv420'})
+      expect(yuv.length).toEqual(5 * 3 + 2 * 3 * 2)
+      expect(Array.from(yuv.slice(0, 5))).toEqual([82, 82, 82, 82, 82])
+      expect(Array.from(yuv.slice(15, 18))).toEqual([90, 90, 90])
+      expect(Array.from(yuv.slice(21, 24))).toEqual([240, 240, 240])
+
+      let nv12 = await canvas.toRaw({layout:'nv12'})
+      expect(nv12.length).toEqual(6 * 3 + 6 * 2)
+      expect(Array.from(nv12.slice(18, 20))).toEqual([90, 240])
+
+      expect(() => canvas.toRaw({layout:'rgb'})).toThrowError('layout')
+      expect(() => canvas.toRaw({rowBytes:-1})).toThrowError('positive integer')
+      expect(() => canvas.toRaw({rowBytes:16})).toThrowError('at least 20')
+      expect(() => canvas.toRaw({buffer:Buffer.alloc(8)})).toThrowError('too small')
+      expect(() => canvas.toRaw({buffer:[]})).toThrowError('Buffer or typed array')
+      expect(() => canvas.toRaw({buffer:Buffer.alloc(128).subarray(0, 100)})).toThrowError('entire ArrayBuffer')
+    })
+
+    test("output transforms", async ()=>{
+      canvas.width = 40
+      canvas.height = 20
+      ctx.fillStyle = 'red'
+      ctx.fillRect(0, 0, 20, 20)
+      ctx.fillStyle = 'blue'
+      ctx.fillRect(20, 0, 20, 20)
+
+      let pixel = (buf, width, x, y) => Array.from(buf.slice((y * width + x) * 4, (y * width + x + 1) * 4)),
+          [red, blue, white] = [[255, 0, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]]
+
+      let rotated = await canvas.toRaw({transform:{rotate:90}})
+      expect(rotated.length).toEqual(20 * 40 * 4)
+      expect(pixel(rotated, 20, 10, 5)).toEqual(red)
+      expect(pixel(rotated, 20, 10, 35)).toEqual(blue)
+
+      let flipped = await canvas.toRaw({transform:{flipX:true}})
+      expect(pixel(flipped, 40, 5, 5)).toEqual(blue)
+
+      let boxed = await canvas.toRaw({matte:'white', transform:{width:100, height:100}})
+      expect(boxed.length).toEqual(100 * 100 * 4)
+      expect(pixel(boxed, 100, 50, 10)).toEqual(white)
+      expect(pixel(boxed, 100, 10, 50)).toEqual(red)
+      expect(pixel(boxed, 100, 90, 50)).toEqual(blue)
+
+      let cropped = await canvas.toRaw({left:20, width:20, transform:{width:10, height:10, fit:'fill'}})
+      expect(cropped.length).toEqual(10 * 10 * 4)
+      expect(pixel(cropped, 10, 0, 0)).toEqual(blue)
+
+      let png = canvas.toBufferSync('png', {density:2, transform:{rotate:-90}})
+      expect(Object.assign(new Image(), {src:png})).toMatchObject({width:40, height:80})
+
+      expect(() => canvas.toBuffer('png', {transform:{width:10}})).toThrowError('provided together')
+      expect(() => canvas.toBuffer('png', {transform:{fit:'resize'}})).toThrowError('transform.fit')
+      expect(() => canvas.toBuffer('png', {transform:{matrix:[0, 0, 0, 0, 0, 0]}})).toThrowError('empty area')
+    })
+
     test("SVGs", async ()=>{
       await Promise.all([
         canvas.saveAs(`${TMP}/output1.svg`),
@@ -197,6 +452,77 @@ describe("Canvas", ()=>{
       }
     })
 
+    test("SVG options", async ()=>{
+      let img = new Image()
+      img.src = 'test/assets/pentagon.png'
+      ctx.drawImage(img, 0, 0)
+      ctx.font = '32px sans-serif'
+      ctx.fillText("Hello", 100, 100)
+
+      let svg = (await canvas.toBuffer('svg', {svg:{fonts:'embed', sizing:'viewbox'}})).toString()
+      expect(svg).toMatch('<text')
+      expect(svg).toMatch('@font-face')
+      expect(svg).toMatch('data:font/woff;base64,')
+      expect(svg).toMatch(`viewBox="0 0 ${WIDTH} ${HEIGHT}"`)
+      expect(svg).not.toMatch(/<svg[^>]* width=/)
+
+      await canvas.saveAs(`${TMP}/figure.svg`, {svg:{images:'files'}})
+      expect(findTmp('/figure-*.png').length).toEqual(1)
+      expect(fs.readFileSync(`${TMP}/figure.svg`, 'utf-8')).toMatch('"figure-1.png"')
+
+      expect(() => canvas.toBuffer('svg', {svg:{images:'files'}})).toThrowError('saving to disk')
+      expect(() => canvas.toBuffer('svg', {outline:true, svg:{fonts:'embed'}})).toThrowError('outline')
+      expect(() => canvas.toBuffer('svg', {svg:{sizing:'auto'}})).toThrowError('svg.sizing')
+    })
+
+    test("Skia pictures", async ()=>{
+      ctx.fillStyle = 'red'
+      ctx.fillRect(0, 0, WIDTH/2, HEIGHT/2)
+      let skp = await canvas.toBuffer('skp')
+
+      let copy = Canvas.fromPicture(skp)
+      expect(copy.width).toEqual(WIDTH)
+      expect(copy.height).toEqual(HEIGHT)
+      ctx = copy.getContext('2d')
+      expect(pixel(10, 10)).toEqual([255, 0, 0, 255])
+      expect(pixel(WIDTH - 10, HEIGHT - 10)).toEqual(CLEAR)
+
+      await canvas.saveAs(`${TMP}/scene.skp`)
+      let img = new Image()
+      img.src = `${TMP}/scene.skp`
+      expect(img.complete).toBe(true)
+      expect(img.width).toEqual(WIDTH)
+
+      let cropped = Canvas.fromPicture(await canvas.toBuffer('skp', {left:WIDTH/4, top:HEIGHT/4, width:WIDTH/2, height:HEIGHT/2}))
+      expect(cropped.width).toEqual(WIDTH/2)
+      expect(() => Canvas.fromPicture(Buffer.from('not a picture'))).toThrowError('Could not decode')
+    })
+
+    test("layers", async ()=>{
+      ctx.fillStyle = 'red'
+      ctx.fillRect(0, 0, 10, 10)
+      expect(ctx.layerCount).toEqual(1)
+      ctx.fillStyle = 'blue'
+      ctx.fillRect(10, 10, 10, 10)
+      ctx.getImageData(0, 0, 1, 1)
+      ctx.fillStyle = 'lime'
+      ctx.fillRect(20, 20, 10, 10)
+      expect(ctx.layerCount).toEqual(3)
+
+      await canvas.saveAs(`${TMP}/layer-{}.png`, {flatten:false})
+      expect(findTmp('/layer-*.png').length).toEqual(3)
+
+      let partial = Canvas.fromPicture(await canvas.toBuffer('skp', {layers:[2, -1]}))
+      ctx = partial.getContext('2d')
+      expect(pixel(5, 5)).toEqual(CLEAR)
+      expect(pixel(15, 15)).toEqual([0, 0, 255, 255])
+      expect(pixel(25, 25)).toEqual([0, 255, 0, 255])
+
+      expect(() => canvas.toBuffer('png', {flatten:false})).toThrowError('multi-page')
+      expect(() => canvas.toBuffer('png', {layers:[1, 5]})).toThrowError('layers 1–3')
+      expect(() => canvas.toBuffer('png', {layers:0})).toThrowError('non-zero')
+    })
+
     test("PDFs", async ()=>{
       await Promise.all([
         canvas.saveAs(`${TMP}/output1.pdf`),
@@ -242,6 +568,254 @@ describe("Canvas", ()=>{
 
     })
 
+    test("TIFFs, BMPs, & ICOs", async () => {
+      await Promise.all([
+        canvas.saveAs(`${TMP}/output1.tiff`),
+        canvas.saveAs(`${TMP}/output2.TIF`),
+        canvas.saveAs(`${TMP}/output3`, {format:'tiff'}),
+      ])
+      for (let path of findTmp(`/output*`)){
+        let header = fs.readFileSync(path).slice(0, 4)
+        expect(header.equals(Buffer.from('II*\0', 'latin1'))).toBe(true)
+      }
+
+      // count the pages by following the chain of image directories
+      let tiffPages = buf => {
+        let count = 0
+        for (let ifd = buf.readUInt32LE(4); ifd; count++){
+          ifd = buf.readUInt32LE(ifd + 2 + 12 * buf.readUInt16LE(ifd))
+        }
+        return count
+      }
+
+      let bmp = await canvas.toBuffer('bmp', {density:2})
+      expect(bmp.slice(0, 2).toString()).toEqual('BM')
+      expect(bmp.readInt32LE(18)).toEqual(WIDTH * 2)
+      expect(bmp.readInt32LE(22)).toEqual(HEIGHT * 2)
+      expect(bmp.readUInt32LE(38)).toEqual(Math.round(144 * 39.3701))
+
+      let ico = await canvas.toBuffer('ico')
+      expect(ico.readUInt16LE(2)).toEqual(1)
+      expect(ico.readUInt16LE(4)).toEqual(6)
+      expect(ico[6]).toEqual(0) // i.e., 256
+      expect(ico[6 + 16 * 5]).toEqual(16)
+
+      expect(tiffPages(await canvas.toBuffer('tiff'))).toEqual(1)
+      canvas.newPage(32, 32)
+      canvas.newPage(16, 16)
+      expect(tiffPages(await canvas.toBuffer('tiff'))).toEqual(3)
+      expect(tiffPages(await canvas.toBuffer('tiff', {page:2}))).toEqual(1)
+      await expect(canvas.toBuffer('ico')).rejects.toThrowError("256×256")
+
+      ico = await canvas.toBuffer('ico', {page:-1})
+      expect(ico.readUInt16LE(4)).toEqual(6)
+    })
+
+    test("animations", async () => {
+      let colors = ['orange', 'yellow', 'green', 'skyblue', 'purple']
+      colors.forEach((color, i) => {
+        ctx = canvas.newPage()
+        ctx.fillStyle = color
+        ctx.fillRect(0, 0, 100 + 50*i, 100)
+      })
+      let frames = canvas.pages.length
+
+      await Promise.all([
+        canvas.saveAs(`${TMP}/anim.gif`, {delay:50}),
+        canvas.saveAs(`${TMP}/anim.apng`, {delay:[200, 50], loop:2}),
+        canvas.saveAs(`${TMP}/anim.webp`, {animated:true, disposal:'none'}),
+      ])
+
+      let gif = fs.readFileSync(`${TMP}/anim.gif`)
+      expect(gif.slice(0, 6).toString()).toEqual('GIF89a')
+      expect(gif.includes('NETSCAPE2.0')).toBe(true)
+      expect(gif[gif.length-1]).toEqual(0x3B)
+
+      let apng = fs.readFileSync(`${TMP}/anim.apng`),
+          acTL = apng.indexOf('acTL')
+      expect(apng.slice(0, 8).equals(MAGIC.png)).toBe(true)
+      expect(acTL).toBeGreaterThan(0)
+      expect(apng.readUInt32BE(acTL + 4)).toEqual(frames)
+      expect(apng.readUInt32BE(acTL + 8)).toEqual(2)
+
+      let webp = fs.readFileSync(`${TMP}/anim.webp`),
+          anmf = webp.toString('latin1').split('ANMF').length - 1
+      expect(webp.slice(12, 16).toString()).toEqual('VP8X')
+      expect(webp.includes('ANIM')).toBe(true)
+      expect(anmf).toEqual(frames)
+
+      // page selection and image-sequences produce single-frame files
+      let single = await canvas.toBuffer('webp')
+      expect(single.includes('ANIM')).toBe(false)
+      await canvas.saveAs(`${TMP}/frame-{}.gif`)
+      expect(findTmp(`/frame-*.gif`).length).toEqual(frames)
+
+      expect(() => canvas.toBuffer('gif', {delay:-1})).toThrowError('delay')
+      expect(() => canvas.toBuffer('gif', {disposal:'erase'})).toThrowError('disposal')
+      expect(() => canvas.toBuffer('gif', {loop:1.5})).toThrowError('loop')
+    })
+
+    test("PDF metadata, bookmarks, & links", async () => {
+      ctx.addLink("https://skia-canvas.org", 10, 10, 100, 20)
+      ctx.addLink("#conclusion", 10, 40, 100, 20)
+      ctx = canvas.newPage()
+      ctx.addDestination("conclusion", 0, 50)
+
+      let pdf = (await canvas.toBuffer('pdf', {
+        metadata: {
+          title: "Test Document",
+          author: "Skia Canvas",
+          keywords: ["one", "two"],
+          creationDate: new Date(Date.UTC(2020, 1, 29, 12, 30, 0)),
+        },
+        bookmarks: [
+          {title:"Introduction"},
+          {title:"Conclusion", page:2, children:[
+            {title:"Details", destination:"conclusion"}
+          ]},
+        ]
+      })).toString('latin1')
+
+      expect(pdf).toMatch('/Title (Test Document)')
+      expect(pdf).toMatch('/Author (Skia Canvas)')
+      expect(pdf).toMatch('/Keywords (one, two)')
+      expect(pdf).toMatch('/CreationDate (D:20200229123000')
+      expect(pdf).toMatch('/URI (https://skia-canvas.org)')
+      expect(pdf).toMatch('/Dest /conclusion')
+      expect(pdf).toMatch('/Dests')
+      expect(pdf).toMatch('/PageMode /UseOutlines')
+      expect(pdf).toMatch('/Title (Introduction)')
+      expect(pdf).toMatch('/Count 3')
+
+      // the outline is appended as an incremental update pointing back to the original xref table
+      let [first, second] = [...pdf.matchAll(/startxref\s+(\d+)/g)].map(m => m[1])
+      expect(pdf).toMatch(`/Prev ${first}`)
+      expect(pdf.slice(parseInt(second), parseInt(second) + 4)).toEqual('xref')
+
+      expect(() => canvas.toBuffer('pdf', {bookmarks:[{title:"Nowhere", page:3}]})).toThrowError('range')
+      expect(() => canvas.toBuffer('pdf', {metadata:{creationDate:'someday'}})).toThrowError('creationDate')
+      expect(pdf).not.toMatch('pdfaid:part')
+
+      let pdfa = (await canvas.toBuffer('pdf', {pdfa:true})).toString('latin1')
+      expect(pdfa).toMatch('pdfaid:part')
+      expect(pdfa).toMatch('/OutputIntents')
+    })
+
+    test("PDF page sizes & print boxes", async () => {
+      ctx.fillRect(0, 0, 10, 10)
+      canvas.newPage(300, 200).fillRect(0, 0, 10, 10)
+      canvas.newPage(100, 100).fillRect(0, 0, 10, 10)
+
+      let pdf = (await canvas.toBuffer('pdf', {
+        pageSetup: [
+          {size:'letter'},
+          {size:'tabloid', orientation:'landscape', bleed:9, crop:'trim'},
+          {size:[200, 100]},
+        ]
+      })).toString('latin1')
+
+      expect(pdf).toMatch('/MediaBox [0 0 612 792]')
+      expect(pdf).toMatch('/MediaBox [0 0 1242 810]')
+      expect(pdf).toMatch('/TrimBox [9 9 1233 801]')
+      expect(pdf).toMatch('/BleedBox [0 0 1242 810]')
+      expect(pdf).toMatch('/CropBox [9 9 1233 801]')
+      expect(pdf).toMatch('/MediaBox [0 0 200 100]')
+
+      // a single setup applies to every page
+      let a4 = (await canvas.toBuffer('pdf', {pageSetup:{size:'A4', orientation:'landscape'}})).toString('latin1')
+      expect([...a4.matchAll(/\/TrimBox \[0 0 841.89 595.28\]/g)].length).toEqual(3)
+
+      let plain = (await canvas.toBuffer('pdf')).toString('latin1')
+      expect(plain).toMatch('/MediaBox [0 0 300 200]')
+      expect(plain).not.toMatch('/TrimBox')
+
+      expect(() => canvas.toBuffer('pdf', {pageSetup:{size:'quarto'}})).toThrowError('paper size')
+      expect(() => canvas.toBuffer('pdf', {pageSetup:{orientation:'landscape'}})).toThrowError('along with a size')
+      expect(() => canvas.toBuffer('pdf', {pageSetup:{bleed:-3}})).toThrowError('bleed')
+      expect(() => canvas.toBuffer('pdf', {pageSetup:{crop:'art'}})).toThrowError('crop')
+    })
+
+    test("PDF page labels, attachments & form fields", async () => {
+      ctx.addTextField("total", 10, 10, 100, 20, {value:"(none)", maxLength:12})
+      canvas.newPage().addTextField("notes", 10, 10, 100, 60, {multiline:true, fontSize:10})
+      canvas.newPage()
+
+      let pdf = (await canvas.toBuffer('pdf', {
+        pageLabels: [{page:2, style:'lower-roman', prefix:'p. ', start:4}],
+        attachments: [
+          {name:'data.csv', data:'a,b\n1,2', mimeType:'text/csv', relationship:'Data', description:'Raw values'},
+          {name:'notes.txt', data:new Uint8Array([104, 105])},
+        ]
+      })).toString('latin1')
+
+      // a default range is added for the pages before the first label
+      expect(pdf).toMatch('/PageLabels')
+      expect(pdf).toMatch('/Nums [0 <</S /D>> 1 <</S /r\n/P (p. )\n/St 4>>]')
+
+      expect(pdf).toMatch('/EmbeddedFiles')
+      expect(pdf).toMatch('/Subtype /text#2Fcsv')
+      expect(pdf).toMatch('/AFRelationship /Data')
+      expect(pdf).toMatch('/AFRelationship /Unspecified')
+      expect(pdf).toMatch('/Desc (Raw values)')
+
+      expect(pdf).toMatch('/AcroForm')
+      expect(pdf).toMatch('/FT /Tx')
+      expect(pdf).toMatch('/T (total)')
+      expect(pdf).toMatch('/V (\\(none\\))')
+      expect(pdf).toMatch('/MaxLen 12')
+      expect(pdf).toMatch('/Ff 4096')
+      expect(pdf).toMatch('/DA (/Helv 10 Tf 0 g)')
+      expect(pdf).toMatch(`/Rect [10 ${HEIGHT - 30} 110 ${HEIGHT - 10}]`)
+
+      // fields follow the context's transform and are moved along with the page's content
+      ctx.translate(50, 0)
+      ctx.addTextField("moved", 10, 10, 100, 20)
+      let moved = (await canvas.toBuffer('pdf', {page:1, pageSetup:{bleed:5}})).toString('latin1')
+      expect(moved).toMatch(`/Rect [65 ${HEIGHT - 25} 165 ${HEIGHT - 5}]`)
+
+      // fields are ignored by other formats
+      expect(() => canvas.toBufferSync('png')).not.toThrow()
+      let svg = canvas.toBufferSync('svg', {page:1}).toString()
+      expect(svg).toMatch('<svg')
+      expect(svg).not.toMatch('<a ')
+
+      // only PDF/A-3 allows attachments and no part of the standard allows fields drawn with unembedded fonts
+      let archive = (await canvas.toBuffer('pdf', {page:3, pdfa:true, attachments:[{name:'data.csv', data:'a,b'}]})).toString('latin1')
+      expect(archive).toMatch('<pdfaid:part>3</pdfaid:part>')
+      expect(archive).toMatch('/Subtype /application#2Foctet-stream')
+      expect(() => canvas.toBuffer('pdf', {pdfa:true})).toThrowError('PDF/A')
+
+      expect(() => canvas.toBuffer('pdf', {pageLabels:[{page:4}]})).toThrowError('range')
+      expect(() => canvas.toBuffer('pdf', {pageLabels:[{page:1, style:'hebrew'}]})).toThrowError('style')
+      expect(() => canvas.toBuffer('pdf', {pageLabels:[{page:1}, {page:1}]})).toThrowError('single page label')
+      expect(() => canvas.toBuffer('pdf', {attachments:[{name:'a.txt', data:''}, {name:'a.txt', data:''}]})).toThrowError('unique')
+      expect(() => canvas.toBuffer('pdf', {attachments:[{name:'a.txt', data:42}]})).toThrowError('must be a string')
+      expect(() => canvas.toBuffer('pdf', {attachments:[{name:'a.txt', data:'', relationship:'Other'}]})).toThrowError('relationship')
+      expect(() => ctx.addTextField("x", 0, 0, 10, 10, {maxLength:0})).toThrowError('maxLength')
+    })
+
+    test("tagged PDFs", async () => {
+      let untagged = (await canvas.toBuffer('pdf')).toString('latin1')
+      expect(untagged).not.toMatch('/StructTreeRoot')
+
+      ctx.beginTag('H1')
+      ctx.fillText("Results", 10, 20)
+      ctx.endTag()
+      ctx.beginTag('Figure', {alt:"A bar chart", lang:"en-US"})
+      ctx.fillRect(10, 30, 50, 50)
+      ctx.endTag()
+
+      let pdf = (await canvas.toBuffer('pdf')).toString('latin1')
+      expect(pdf).toMatch('/StructTreeRoot')
+      expect(pdf).toMatch('/MarkInfo')
+      expect(pdf).toMatch('/S /H1')
+      expect(pdf).toMatch('/Alt (A bar chart)')
+      expect(pdf).toMatch('/Lang (en-US)')
+
+      expect(() => ctx.beginTag('Heading')).toThrowError('Unknown structure type')
+      expect(() => ctx.endTag()).toThrowError('beginTag')
+    })
+
     test("multi-page PDFs", async () => {
       let colors = ['orange', 'yellow', 'green', 'skyblue', 'purple']
       colors.forEach((color, i) => {
@@ -261,7 +835,7 @@ describe("Canvas", ()=>{
     })
 
     test("image Buffers", async () => {
-      for (let ext of ["png", "jpg", "pdf", "svg"]){
+      for (let ext of ["png", "jpg", "webp", "gif", "pdf", "svg"]){
         // use extension to specify type
         let path = `${TMP}/output.${ext}`
         let buf = await canvas.toBuffer(ext)
@@ -427,7 +1001,7 @@ describe("Canvas", ()=>{
     })
 
     test("image Buffers", () => {
-      for (let ext of ["png", "jpg", "pdf", "svg"]){
+      for (let ext of ["png", "jpg", "webp", "gif", "pdf", "svg"]){
         // use extension to specify type
         let path = `${TMP}/output.${ext}`
         let buf = canvas.toBufferSync(ext)
@@ -477,6 +1051,42 @@ describe("Canvas", ()=>{
       expect(canvas).toMatchObject({width, height})
       expect( () => canvas.saveAsSync(`${TMP}/zeroed.png`)).toThrowError("must be non-empty")
     })
+
+    test("image comparisons", async () => {
+      let copy = new Canvas(WIDTH, HEIGHT),
+          copyCtx = copy.getContext("2d")
+      copyCtx.drawCanvas(canvas, 0, 0)
+
+      // identical images
+      let same = await compareImages(canvas, copy)
+      expect(same).toMatchObject({maxError:[0, 0, 0, 0], mismatched:0, total:WIDTH * HEIGHT, ssim:1})
+      expect(same.diff).toBeUndefined()
+      expect(compareImagesSync(canvas, copy)).toEqual(same)
+
+      // canvases, Images, and encoded Buffers are interchangeable
+      let img = new Image()
+      img.src = canvas.toBufferSync("png")
+      expect(compareImagesSync(img, copy.toBufferSync("png")).mismatched).toBe(0)
+
+      // a small change is counted unless it falls under the threshold
+      copyCtx.fillStyle = 'rgb(0,0,10)'
+      copyCtx.fillRect(300, 300, 10, 10)
+      let changed = await compareImages(canvas, copy, {diff:true})
+      expect(changed.maxError).toEqual([0, 0, 10, 255])
+      expect(changed.mismatched).toBe(100)
+      expect(changed.ssim).toBeLessThan(1)
+      expect(changed.diff).toBeInstanceOf(Image)
+      expect(changed.diff).toMatchObject({width:WIDTH, height:HEIGHT, complete:true})
+      expect(compareImagesSync(canvas, copy, {diff:true}).diff).toMatchObject({width:WIDTH, height:HEIGHT})
+      expect(compareImagesSync(canvas, copy, {threshold:255}).mismatched).toBe(0)
+
+      // bad arguments
+      expect(() => compareImages(canvas, new Canvas(WIDTH, HEIGHT/2))).toThrowError("same size")
+      expect(() => compareImagesSync(canvas, {})).toThrowError("Expected a Canvas, Image, or Buffer")
+      expect(() => compareImagesSync(canvas, copy, {threshold:-1})).toThrowError("between 0 and 255")
+      copy.newPage()
+      expect(() => compareImagesSync(canvas, copy)).toThrowError("single-page Canvas")
+    })
   })
 
 })
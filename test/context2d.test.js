@@ -373,6 +373,33 @@ describe("Context2D", ()=>{
   })
 
   describe("supports", () => {
+    test("drawImage() with SVG sources", async () => {
+      let svg = '<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="5" height="10" fill="#f00"/></svg>',
+          img = await loadImage(Buffer.from(svg))
+
+      // enlarged without interpolating between pixels
+      ctx.drawImage(img, 0, 0, 200, 200)
+      expect(pixel(98, 50)).toEqual([255, 0, 0, 255])
+      expect(pixel(102, 50)).toEqual(CLEAR)
+
+      // cropped to the source rect
+      ctx.clearRect(0, 0, WIDTH, HEIGHT)
+      ctx.drawImage(img, 0, 0, 5, 5, 300, 300, 100, 100)
+      expect(pixel(350, 350)).toEqual([255, 0, 0, 255])
+      expect(pixel(350, 410)).toEqual(CLEAR)
+
+      // patterns stay sharp when scaled
+      let pattern = ctx.createPattern(img, 'repeat')
+      pattern.setTransform(new DOMMatrix().scale(20))
+      ctx.fillStyle = pattern
+      ctx.fillRect(0, 0, 200, 200)
+      expect(pixel(98, 150)).toEqual([255, 0, 0, 255])
+
+      // vectors are passed through to vector output formats
+      let doc = (await canvas.toBuffer('svg')).toString()
+      expect(doc).not.toMatch('<image')
+    })
+
     test("filter", () => {
       // make sure chains of filters compose correctly <https://codepen.io/sosuke/pen/Pjoqqp>
       ctx.filter = 'blur(5px) invert(56%) sepia(63%) saturate(4837%) hue-rotate(163deg) brightness(96%) contrast(101%)'
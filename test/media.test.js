@@ -3,7 +3,7 @@
 const _ = require('lodash'),
       fs = require('fs'),
       glob = require('glob').sync,
-      {Image, FontLibrary, loadImage} = require('../lib'),
+      {Canvas, Image, FontLibrary, loadImage} = require('../lib'),
       simple = require('simple-get')
 
 jest.mock('simple-get', () => {
@@ -206,6 +206,268 @@ describe("Image", () => {
       img.src = FORMAT + '.ico'
       expect(img).toMatchObject(PARSED)
     })
+
+    test("SVG", async () => {
+      let svg = '<svg xmlns="http://www.w3.org/2000/svg" width="40" viewBox="0 0 20 10"><rect x="10" width="10" height="10" fill="#f00"/></svg>'
+      img.src = Buffer.from(svg)
+      expect(img).toMatchObject({complete:true, format:'svg', width:40, height:20})
+
+      img = await loadImage(`data:image/svg+xml,${encodeURIComponent(svg)}`)
+      expect(img).toMatchObject({width:40, height:20})
+
+      img = await loadImage(Buffer.from('<?xml version="1.0"?>\n<svg xmlns="http://www.w3.org/2000/svg"/>'))
+      expect(img).toMatchObject({width:300, height:150})
+
+      img = await loadImage(Buffer.from(svg.replace('width="40"', 'width="1in" height="2in"')))
+      expect(img).toMatchObject({width:96, height:192})
+
+      // like bitmaps, vector images are cropped to the source rect
+      img = await loadImage(Buffer.from(svg))
+      let ctx = new Canvas(40, 20).getContext("2d")
+      ctx.drawImage(img, 0, 0, 20, 20, 0, 0, 20, 20)
+      expect(Array.from(ctx.getImageData(30, 10, 1, 1).data)).toEqual([0, 0, 0, 0])
+      ctx.drawImage(img, 20, 0, 20, 20, 0, 0, 20, 20)
+      expect(Array.from(ctx.getImageData(10, 10, 1, 1).data)).toEqual([255, 0, 0, 255])
+    })
+  })
+
+  describe("can be decoded", () => {
+    test("in the background", async () => {
+      img = new Image({decoding:'async'})
+      expect(img.decoding).toEqual('async')
+      img.src = BUFFER
+      expect(img).toMatchObject(FRESH)
+      await img.decode()
+      expect(img).toMatchObject(LOADED)
+
+      img = await loadImage(URL, {decoding:'async', raster:true})
+      expect(img).toMatchObject(LOADED)
+
+      img = new Image({decoding:'async'})
+      img.src = fs.readFileSync('test/assets/star.png')
+      img.src = BUFFER // supersedes the pending decode
+      await img.decode()
+      expect(img).toMatchObject(LOADED)
+
+      img = new Image({decoding:'async'})
+      img.src = Buffer.from("not an image")
+      await expect(img.decode()).rejects.toThrow("Could not decode")
+
+      expect(() => new Image({decoding:'later'})).toThrowError('decoding')
+    })
+
+    test("at a reduced size", async () => {
+      let full = await loadImage(`${FORMAT}.jpg`),
+          half = await loadImage(`${FORMAT}.jpg`, {scale:0.5}),
+          third = await loadImage(PATH, {decoding:'async', scale:1/3})
+      expect(half).toMatchObject({complete:true, width:full.width / 2, height:full.height / 2})
+      expect(third).toMatchObject({complete:true, width:42, height:42})
+
+      let ctx = new Canvas(60, 60).getContext("2d")
+      ctx.drawImage(half, 0, 0, 60, 60)
+      expect(Array.from(ctx.getImageData(30, 30, 1, 1).data)).not.toEqual([0, 0, 0, 0])
+
+      expect(() => new Image({scale:0})).toThrowError('scale')
+      expect(() => new Image({scale:2})).toThrowError('scale')
+    })
+  })
+
+  describe("can be transformed", () => {
+    let RED = [255, 0, 0, 255],
+        BLUE = [0, 0, 255, 255],
+        halves = () => {
+          let canvas = new Canvas(4, 2),
+              ctx = canvas.getContext("2d")
+          ctx.fillStyle = 'red'
+          ctx.fillRect(0, 0, 2, 2)
+          ctx.fillStyle = 'blue'
+          ctx.fillRect(2, 0, 2, 2)
+          return loadImage(canvas.toBufferSync('png'))
+        },
+        pixel = (img, x, y) => {
+          let ctx = new Canvas(img.width, img.height).getContext("2d")
+          ctx.drawImage(img, 0, 0)
+          return Array.from(ctx.getImageData(x, y, 1, 1).data)
+        }
+
+    test("geometry", async () => {
+      img = await halves()
+
+      let crop = img.crop(2, 0, 2, 2)
+      expect(crop).toMatchObject({complete:true, width:2, height:2, format:undefined})
+      expect(pixel(crop, 0, 0)).toEqual(BLUE)
+      expect(pixel(img.crop(3, 0, 2, 2), 1, 0)).toEqual([0, 0, 0, 0])
+
+      let big = img.resize(8, 4, {quality:'none'})
+      expect(big).toMatchObject({width:8, height:4})
+      expect(pixel(big, 3, 3)).toEqual(RED)
+      expect(pixel(big, 4, 0)).toEqual(BLUE)
+
+      let rotated = img.rotate(90)
+      expect(rotated).toMatchObject({width:2, height:4})
+      expect(pixel(rotated, 0, 0)).toEqual(RED)
+      expect(pixel(rotated, 0, 3)).toEqual(BLUE)
+      expect(pixel(img.rotate(-90), 0, 0)).toEqual(BLUE)
+      expect(img.rotate(180)).toMatchObject({width:4, height:2})
+
+      expect(pixel(img.flip(), 0, 0)).toEqual(BLUE)
+      expect(pixel(img.flip('vertical'), 0, 0)).toEqual(RED)
+      expect(pixel(img, 0, 0)).toEqual(RED) // the original is unchanged
+
+      expect(() => img.rotate(45)).toThrowError('90°')
+      expect(() => img.flip('diagonal')).toThrowError('direction')
+      expect(() => img.resize(0, 10)).toThrowError('positive')
+      expect(() => img.resize(10, 10, {quality:'best'})).toThrowError('quality')
+      expect(() => new Image().crop(0, 0, 1, 1)).toThrowError('loaded')
+    })
+
+    test("pixel formats", async () => {
+      img = await halves()
+      let bgra = img.convert({colorType:'bgra', premultiplied:true})
+      expect(bgra).toMatchObject({colorType:'BGRA8888', premultiplied:true, width:4, height:2})
+      expect(pixel(bgra, 3, 1)).toEqual(BLUE)
+      expect(img.convert()).toMatchObject({colorType:'RGBA8888', premultiplied:false})
+
+      // derived images keep the source's bit depth
+      let wide = img.convert({colorType:'RGBAF16', premultiplied:true})
+      expect(wide.crop(2, 0, 2, 2)).toMatchObject({colorType:'RGBAF16', width:2, height:2})
+      expect(wide.rotate(90)).toMatchObject({colorType:'RGBAF16', width:2, height:4})
+      expect(pixel(wide.resize(8, 4, {quality:'none'}), 4, 0)).toEqual(BLUE)
+    })
+
+    test("encoding", async () => {
+      img = await halves()
+      let png = img.toBufferSync(),
+          jpg = await img.toBuffer('image/jpeg', {quality:0.5})
+      expect(png.slice(1, 4).toString()).toEqual('PNG')
+      expect(Array.from(jpg.slice(0, 2))).toEqual([0xFF, 0xD8])
+
+      let reloaded = await loadImage(png)
+      expect(reloaded).toMatchObject({format:'png', width:4, height:2})
+      expect(pixel(reloaded, 3, 0)).toEqual(BLUE)
+
+      // images are encoded with the same options as canvases
+      let xmp = '<x:xmpmeta xmlns:x="adobe:ns:meta/"></x:xmpmeta>'
+      reloaded = await loadImage(await img.toBuffer('png', {density:2, colorSpace:'display-p3', metadata:{xmp}, encoding:{palette:true}}))
+      expect(reloaded).toMatchObject({colorSpace:'display-p3', xmp})
+      expect(reloaded.density).toBeCloseTo(2)
+
+      expect(() => img.toBufferSync('gif')).toThrowError('format')
+      expect(() => img.toBufferSync('jpg', {quality:2})).toThrowError('quality')
+      expect(() => img.toBufferSync('png', {bitDepth:12})).toThrowError('bitDepth')
+    })
+  })
+
+  describe("provides animation frames", () => {
+    let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]],
+        animation = format => {
+          let canvas = new Canvas(20, 20)
+          colors.forEach(([r, g, b], i) => {
+            let ctx = i==0 ? canvas.getContext("2d") : canvas.newPage()
+            ctx.fillStyle = `rgb(${r},${g},${b})`
+            ctx.fillRect(0, 0, 20, 20)
+          })
+          return canvas.toBufferSync(format, {animated:true, delay:[100, 200, 300], loop:3, lossless:true})
+        },
+        pixel = img => {
+          let ctx = new Canvas(20, 20).getContext("2d")
+          ctx.drawImage(img, 0, 0)
+          return Array.from(ctx.getImageData(10, 10, 1, 1).data.slice(0, 3))
+        }
+
+    for (const format of ['gif', 'webp', 'apng']){
+      test(format.toUpperCase(), async () => {
+        img = await loadImage(animation(format))
+        expect(img).toMatchObject({frameCount:3, durations:[100, 200, 300], loop:3, frame:0})
+        expect(pixel(img)).toEqual(colors[0])
+
+        img.frame = 2
+        expect(img.frame).toEqual(2)
+        expect(pixel(img)).toEqual(colors[2])
+
+        img.frame = 1
+        expect(pixel(img)).toEqual(colors[1])
+
+        expect(() => img.frame = 3).toThrowError('range')
+        expect(() => img.frame = 0.5).toThrowError('range')
+      })
+    }
+
+    test("still images", async () => {
+      img = await loadImage(PATH)
+      expect(img).toMatchObject({frameCount:1, durations:[0], loop:1, frame:0})
+      img.frame = 0
+      expect(() => img.frame = 1).toThrowError('range')
+    })
+  })
+
+  describe("reads metadata", () => {
+    // a minimal little-endian EXIF block with just an orientation tag
+    let exifSegment = orientation => Buffer.from([
+      0xFF, 0xE1, 0, 34, ...Buffer.from('Exif\0\0'),
+      0x49, 0x49, 0x2A, 0, 8, 0, 0, 0,
+      1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, orientation, 0, 0, 0, 0, 0, 0, 0
+    ])
+
+    let withOrientation = (jpeg, orientation) => Buffer.concat([
+      jpeg.subarray(0, 2), exifSegment(orientation), jpeg.subarray(2)
+    ])
+
+    test("EXIF orientation", async () => {
+      let canvas = new Canvas(40, 20),
+          ctx = canvas.getContext("2d")
+      ctx.fillStyle = 'red'
+      ctx.fillRect(0, 0, 20, 20)
+      let jpeg = withOrientation(canvas.toBufferSync('jpeg'), 6)
+
+      img = await loadImage(jpeg)
+      expect(img).toMatchObject({format:'jpeg', orientation:6, width:20, height:40})
+      expect(img.exif?.subarray(0, 4)).toEqual(Buffer.from('II*\0'))
+
+      // rotated 90° clockwise, so the red half ends up on top
+      let check = new Canvas(20, 40).getContext("2d")
+      check.drawImage(img, 0, 0)
+      let [r, g, b] = check.getImageData(10, 5, 1, 1).data
+      expect(r).toBeGreaterThan(200)
+      expect(g).toBeLessThan(50)
+
+      img = await loadImage(jpeg, {imageOrientation:'none'})
+      expect(img).toMatchObject({orientation:6, width:40, height:20})
+
+      for (const orientation of [2, 3, 4]){
+        img = await loadImage(withOrientation(jpeg, orientation))
+        expect(img).toMatchObject({orientation, width:40, height:20})
+      }
+      for (const orientation of [5, 7, 8]){
+        img = await loadImage(withOrientation(jpeg, orientation))
+        expect(img).toMatchObject({orientation, width:20, height:40})
+      }
+
+      expect(() => new Image({imageOrientation:'flip'})).toThrowError('imageOrientation')
+    })
+
+    test("resolution, color space & XMP", async () => {
+      let canvas = new Canvas(20, 20),
+          xmp = '<x:xmpmeta xmlns:x="adobe:ns:meta/"></x:xmpmeta>'
+
+      img = await loadImage(canvas.toBufferSync('png', {density:2, metadata:{xmp}}))
+      expect(img).toMatchObject({format:'png', orientation:1, colorSpace:'srgb', xmp})
+      expect(img.density).toBeCloseTo(2) // stored as dots per meter
+      expect(img.exif).toBeUndefined()
+
+      img = await loadImage(canvas.toBufferSync('jpeg', {density:3}))
+      expect(img.density).toEqual(3)
+
+      img = await loadImage(canvas.toBufferSync('png', {colorSpace:'display-p3'}))
+      expect(img.colorSpace).toEqual('display-p3')
+
+      img = await loadImage(canvas.toBufferSync('webp', {metadata:{xmp}}))
+      expect(img).toMatchObject({format:'webp', xmp})
+
+      img = await loadImage(PATH.replace('.png', '.raw'), {raw:{width:125, height:125}})
+      expect(img.format).toBeUndefined()
+      expect(img.density).toEqual(1)
+    })
   })
 })
 